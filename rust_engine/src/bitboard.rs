@@ -30,6 +30,9 @@ pub const NOT_FILE_H: u64 = !FILE_H;
 pub const NOT_FILE_AB: u64 = !(FILE_A | FILE_B);
 pub const NOT_FILE_GH: u64 = !(FILE_G | FILE_H);
 
+pub const LIGHT_SQUARES: u64 = 0x55AA55AA55AA55AA;
+pub const DARK_SQUARES: u64 = !LIGHT_SQUARES;
+
 // ============================================================================
 // PRECOMPUTED ATTACK TABLES
 // ============================================================================
@@ -180,7 +183,7 @@ const fn init_pawn_attacks() -> [[u64; 64]; 2] {
 
 /// Get rook attacks from a square given occupied squares
 #[inline]
-pub fn rook_attacks(sq: usize, occupied: u64) -> u64 {
+pub const fn rook_attacks(sq: usize, occupied: u64) -> u64 {
     let mut attacks = 0u64;
     
     // North
@@ -228,7 +231,7 @@ pub fn rook_attacks(sq: usize, occupied: u64) -> u64 {
 
 /// Get bishop attacks from a square given occupied squares
 #[inline]
-pub fn bishop_attacks(sq: usize, occupied: u64) -> u64 {
+pub const fn bishop_attacks(sq: usize, occupied: u64) -> u64 {
     let mut attacks = 0u64;
     let file = sq % 8;
     let rank = sq / 8;
@@ -288,6 +291,61 @@ pub fn bishop_attacks(sq: usize, occupied: u64) -> u64 {
     attacks
 }
 
+// ============================================================================
+// LINES BETWEEN SQUARES
+// ============================================================================
+
+/// Squares strictly between two squares on a rank, file or diagonal
+/// (empty if the squares are not aligned) - [from][to]
+static BETWEEN: [[u64; 64]; 64] = init_lines(false);
+
+/// Whole rank, file or diagonal through two aligned squares (empty if the
+/// squares are not aligned) - [from][to]
+static LINE: [[u64; 64]; 64] = init_lines(true);
+
+const fn init_lines(full: bool) -> [[u64; 64]; 64] {
+    let mut lines = [[0u64; 64]; 64];
+    let mut a = 0usize;
+
+    while a < 64 {
+        let mut b = 0usize;
+        while b < 64 {
+            let (a_bb, b_bb) = (1u64 << a, 1u64 << b);
+            if a != b && rook_attacks(a, 0) & b_bb != 0 {
+                lines[a][b] = if full {
+                    (rook_attacks(a, 0) & rook_attacks(b, 0)) | a_bb | b_bb
+                } else {
+                    rook_attacks(a, b_bb) & rook_attacks(b, a_bb)
+                };
+            } else if a != b && bishop_attacks(a, 0) & b_bb != 0 {
+                lines[a][b] = if full {
+                    (bishop_attacks(a, 0) & bishop_attacks(b, 0)) | a_bb | b_bb
+                } else {
+                    bishop_attacks(a, b_bb) & bishop_attacks(b, a_bb)
+                };
+            }
+            b += 1;
+        }
+        a += 1;
+    }
+
+    lines
+}
+
+/// Squares strictly between `a` and `b` if they share a rank, file or
+/// diagonal, otherwise empty
+#[inline]
+pub fn between(a: usize, b: usize) -> u64 {
+    BETWEEN[a][b]
+}
+
+/// The rank, file or diagonal through `a` and `b` (both included), or
+/// empty if they are not aligned
+#[inline]
+pub fn line(a: usize, b: usize) -> u64 {
+    LINE[a][b]
+}
+
 /// Get queen attacks (combination of rook and bishop)
 #[inline]
 pub fn queen_attacks(sq: usize, occupied: u64) -> u64 {
@@ -306,6 +364,26 @@ pub fn pop_lsb(bb: &mut u64) -> usize {
     idx
 }
 
+/// Iterator over the squares of a bitboard, lowest first
+#[derive(Clone, Copy, Debug)]
+pub struct Squares(pub u64);
+
+impl Iterator for Squares {
+    type Item = usize;
+
+    #[inline]
+    fn next(&mut self) -> Option<usize> {
+        if self.0 == 0 { None } else { Some(pop_lsb(&mut self.0)) }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let count = self.0.count_ones() as usize;
+        (count, Some(count))
+    }
+}
+
+impl ExactSizeIterator for Squares {}
+
 /// Count the number of set bits in a bitboard
 #[inline]
 pub fn popcount(bb: u64) -> u32 {
@@ -3,10 +3,21 @@
 //! This module provides the core data structures for representing a chess board,
 //! pieces, and moves. It includes FEN parsing and generation, move execution,
 //! and position history tracking.
+//!
+//! FEN parsing is strict: a position that could not arise in a game (a
+//! missing king, pawns on the back ranks, the side not to move in check,
+//! castling rights without their king and rook) is rejected with a
+//! `FenError` instead of being searched. Trailing fields may be omitted.
+
+use std::fmt;
 
 use crate::types::*;
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
+use crate::bitboard::{attackers_to, lsb, Squares, DARK_SQUARES, LIGHT_SQUARES};
+use crate::zobrist::ZobristHash;
+use crate::evaluation::{psqt_value, PIECE_VALUES, Score};
+
+/// Game phase of the initial position (see `Board::phase`)
+pub const MAX_PHASE: i32 = 24;
 
 /// Starting position FEN
 pub const STARTING_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
@@ -82,6 +93,26 @@ impl Move {
         uci
     }
 
+    /// Parse UCI notation (e.g. "e2e4", "e7e8q"). The castling and en
+    /// passant flags are not part of the notation and are left unset; use
+    /// the matching legal move of a board to recover them.
+    pub fn from_uci(uci: &str) -> Option<Self> {
+        if !uci.is_ascii() || !(4..=5).contains(&uci.len()) {
+            return None;
+        }
+        let from_sq = parse_square(&uci[0..2])?;
+        let to_sq = parse_square(&uci[2..4])?;
+        let promotion = match uci[4..].chars().next() {
+            None => 0,
+            Some('q' | 'Q') => QUEEN,
+            Some('r' | 'R') => ROOK,
+            Some('b' | 'B') => BISHOP,
+            Some('n' | 'N') => KNIGHT,
+            Some(_) => return None,
+        };
+        Some(Move::with_promotion(from_sq, to_sq, promotion))
+    }
+
     /// Null move constant
     pub fn null() -> Self {
         Move::new(0, 0)
@@ -99,6 +130,116 @@ impl Default for Move {
     }
 }
 
+/// Move packed into 16 bits for tables that store many of them:
+/// bits 0-5 from square, 6-11 to square, 12-13 promotion piece (knight to
+/// queen) and 14-15 kind (normal, promotion, en passant, castling).
+/// Zero, a move from a1 to a1, stands for no move.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct PackedMove(u16);
+
+const PACKED_PROMOTION: u16 = 1;
+const PACKED_EN_PASSANT: u16 = 2;
+const PACKED_CASTLING: u16 = 3;
+
+impl PackedMove {
+    /// No move
+    pub const NONE: PackedMove = PackedMove(0);
+
+    pub fn new(mv: Move) -> Self {
+        let (kind, promotion) = if mv.promotion != 0 {
+            (PACKED_PROMOTION, (mv.promotion - KNIGHT) as u16 & 0x3)
+        } else if mv.is_en_passant {
+            (PACKED_EN_PASSANT, 0)
+        } else if mv.is_castling {
+            (PACKED_CASTLING, 0)
+        } else {
+            (0, 0)
+        };
+        PackedMove(mv.from_sq as u16 | (mv.to_sq as u16) << 6 | promotion << 12 | kind << 14)
+    }
+
+    /// Packed form of an optional move
+    pub fn from_option(mv: Option<Move>) -> Self {
+        mv.map_or(PackedMove::NONE, PackedMove::new)
+    }
+
+    /// The move, or None for `NONE`
+    pub fn to_move(self) -> Option<Move> {
+        if self.is_none() {
+            return None;
+        }
+        let from_sq = (self.0 & 0x3f) as usize;
+        let to_sq = (self.0 >> 6 & 0x3f) as usize;
+        Some(match self.0 >> 14 {
+            PACKED_PROMOTION => Move::with_promotion(from_sq, to_sq, KNIGHT + (self.0 >> 12 & 0x3) as u8),
+            PACKED_EN_PASSANT => Move::en_passant(from_sq, to_sq),
+            PACKED_CASTLING => Move::castling(from_sq, to_sq),
+            _ => Move::new(from_sq, to_sq),
+        })
+    }
+
+    pub fn is_none(self) -> bool {
+        self.0 == 0
+    }
+
+    /// The 16-bit encoding
+    pub fn bits(self) -> u16 {
+        self.0
+    }
+
+    pub fn from_bits(bits: u16) -> Self {
+        PackedMove(bits)
+    }
+}
+
+impl From<Move> for PackedMove {
+    fn from(mv: Move) -> Self {
+        PackedMove::new(mv)
+    }
+}
+
+/// Why `Board::from_fen` rejected a FEN string
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FenError {
+    /// No piece placement field
+    Empty,
+    /// Unknown character, or not exactly 8 ranks of 8 squares
+    InvalidPlacement(String),
+    InvalidSideToMove(String),
+    /// Unknown character, or a right without its king and rook
+    InvalidCastling(String),
+    /// Malformed square, or no pawn that could just have double-pushed
+    InvalidEnPassant(String),
+    InvalidMoveCounter(String),
+    /// A side without exactly one king
+    KingCount { white: bool, count: u32 },
+    /// More than 16 pieces or 8 pawns on one side
+    TooManyPieces { white: bool },
+    PawnOnBackRank(usize),
+    /// The side that just moved is in check
+    OpponentInCheck,
+}
+
+impl fmt::Display for FenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let side = |white: bool| if white { "white" } else { "black" };
+        match self {
+            FenError::Empty => write!(f, "empty FEN"),
+            FenError::InvalidPlacement(field) => write!(f, "invalid piece placement {}", field),
+            FenError::InvalidSideToMove(field) => write!(f, "invalid side to move {}", field),
+            FenError::InvalidCastling(field) => write!(f, "invalid castling rights {}", field),
+            FenError::InvalidEnPassant(field) => write!(f, "invalid en passant square {}", field),
+            FenError::InvalidMoveCounter(field) => write!(f, "invalid move counter {}", field),
+            FenError::KingCount { white, count } => write!(f, "{} has {} kings", side(*white), count),
+            FenError::TooManyPieces { white } => write!(f, "{} has too many pieces", side(*white)),
+            FenError::PawnOnBackRank(sq) => write!(f, "pawn on {}", square_name(*sq)),
+            FenError::OpponentInCheck => write!(f, "side not to move is in check"),
+        }
+    }
+}
+
+impl std::error::Error for FenError {}
+
 /// Information needed to undo a move
 #[derive(Clone, Copy, Debug)]
 pub struct UndoInfo {
@@ -109,6 +250,16 @@ pub struct UndoInfo {
     pub moved_piece: u8,
 }
 
+/// Index of a castling right bit (and of its rook in `Board::castling_rooks`)
+pub fn castling_index(white: bool, kingside: bool) -> usize {
+    match (white, kingside) {
+        (true, true) => 0,
+        (true, false) => 1,
+        (false, true) => 2,
+        (false, false) => 3,
+    }
+}
+
 /// Chess board representation
 #[derive(Clone)]
 pub struct Board {
@@ -118,6 +269,12 @@ pub struct Board {
     pub white_to_move: bool,
     /// Bitmask for castling rights (1=K, 2=Q, 4=k, 8=q)
     pub castling_rights: u8,
+    /// Start squares of the castling rooks, in the order of the castling
+    /// right bits (K, Q, k, q)
+    pub castling_rooks: [usize; 4],
+    /// Chess960 rules: castling moves go from the king to its rook's square
+    /// ("king takes rook") and the FEN names castling rooks by file
+    pub chess960: bool,
     /// Target square for en passant (-1 if none)
     pub en_passant_square: i8,
     /// Moves since last pawn move or capture (for 50-move rule)
@@ -138,6 +295,11 @@ pub struct Board {
     // Bitboards by color
     pub bb_white: u64,
     pub bb_black: u64,
+
+    /// Packed material + PST score from White's point of view
+    pub psqt: Score,
+    /// Material excluding kings (white, black)
+    pub material: [i32; 2],
 }
 
 impl Board {
@@ -147,16 +309,18 @@ impl Board {
     }
 
     /// Create a board from a FEN string
-    pub fn from_fen(fen: &str) -> Option<Self> {
+    pub fn from_fen(fen: &str) -> Result<Self, FenError> {
         let parts: Vec<&str> = fen.split_whitespace().collect();
         if parts.is_empty() {
-            return None;
+            return Err(FenError::Empty);
         }
 
         let mut board = Board {
             squares: [EMPTY; 64],
             white_to_move: true,
             castling_rights: 0,
+            castling_rooks: [7, 0, 63, 56],
+            chess960: false,
             en_passant_square: -1,
             halfmove_clock: 0,
             fullmove_number: 1,
@@ -169,69 +333,149 @@ impl Board {
             bb_kings: 0,
             bb_white: 0,
             bb_black: 0,
+            psqt: 0,
+            material: [0; 2],
         };
 
-        // Parse piece placement
-        let mut rank = 7i8;
-        let mut file = 0i8;
-        
-        for c in parts[0].chars() {
-            if c == '/' {
-                rank -= 1;
-                file = 0;
-            } else if c.is_ascii_digit() {
-                file += c.to_digit(10).unwrap() as i8;
-            } else if let Some(piece) = fen_to_piece(c) {
-                let sq = (rank * 8 + file) as usize;
-                if sq < 64 {
-                    board.squares[sq] = piece;
+        // Parse piece placement: 8 ranks of 8 squares, from rank 8 down
+        let invalid_placement = || FenError::InvalidPlacement(parts[0].to_string());
+        let ranks: Vec<&str> = parts[0].split('/').collect();
+        if ranks.len() != 8 {
+            return Err(invalid_placement());
+        }
+        for (i, rank_text) in ranks.iter().enumerate() {
+            let rank = 7 - i;
+            let mut file = 0;
+            for c in rank_text.chars() {
+                if let Some(empty) = c.to_digit(10).filter(|d| (1..=8).contains(d)) {
+                    file += empty as usize;
+                } else if let Some(piece) = fen_to_piece(c) {
+                    if file < 8 {
+                        board.squares[rank * 8 + file] = piece;
+                    }
+                    file += 1;
+                } else {
+                    return Err(invalid_placement());
+                }
+                if file > 8 {
+                    return Err(invalid_placement());
                 }
-                file += 1;
+            }
+            if file != 8 {
+                return Err(invalid_placement());
             }
         }
 
+        // Sync bitboards from squares (castling parsing below looks up kings)
+        board.sync_bitboards();
+        board.validate_material()?;
+
         // Parse active color
         if parts.len() > 1 {
-            board.white_to_move = parts[1] != "b";
+            board.white_to_move = match parts[1] {
+                "w" => true,
+                "b" => false,
+                other => return Err(FenError::InvalidSideToMove(other.to_string())),
+            };
         }
 
-        // Parse castling rights
+        // Parse castling rights: KQkq (the outermost rook on that side of
+        // the king, as in X-FEN) or the files of the rooks (Shredder-FEN)
+        // Every right needs the king on its back rank and the rook
         if parts.len() > 2 && parts[2] != "-" {
+            let invalid_castling = || FenError::InvalidCastling(parts[2].to_string());
             for c in parts[2].chars() {
-                match c {
-                    'K' => board.castling_rights |= CASTLE_WK,
-                    'Q' => board.castling_rights |= CASTLE_WQ,
-                    'k' => board.castling_rights |= CASTLE_BK,
-                    'q' => board.castling_rights |= CASTLE_BQ,
-                    _ => {}
-                }
+                let white = c.is_ascii_uppercase();
+                let base = if white { 0 } else { 56 };
+                let rook = if white { WHITE_ROOK } else { BLACK_ROOK };
+                let king_sq = board.find_king(white)
+                    .filter(|&sq| sq / 8 == base / 8)
+                    .ok_or_else(invalid_castling)?;
+
+                let rook_sq = match c.to_ascii_lowercase() {
+                    'k' => (king_sq + 1..base + 8).rev().find(|&sq| board.squares[sq] == rook),
+                    'q' => (base..king_sq).find(|&sq| board.squares[sq] == rook),
+                    file @ 'a'..='h' => {
+                        // Only Chess960 FENs name the rook files
+                        board.chess960 = true;
+                        Some(base + (file as u8 - b'a') as usize)
+                            .filter(|&sq| sq != king_sq && board.squares[sq] == rook)
+                    }
+                    _ => None,
+                };
+                let rook_sq = rook_sq.ok_or_else(invalid_castling)?;
+                let index = castling_index(white, rook_sq > king_sq);
+                board.castling_rights |= 1 << index;
+                board.castling_rooks[index] = rook_sq;
             }
         }
 
-        // Parse en passant square
+        // Parse en passant square: behind a pawn of the side that just
+        // moved, with both squares it crossed empty
         if parts.len() > 3 && parts[3] != "-" {
-            if let Some(sq) = parse_square(parts[3]) {
-                board.en_passant_square = sq as i8;
+            let invalid_en_passant = || FenError::InvalidEnPassant(parts[3].to_string());
+            let sq = parse_square(parts[3]).ok_or_else(invalid_en_passant)?;
+            let (rank, pawn_sq, from_sq, pawn) = if board.white_to_move {
+                (5, sq.wrapping_sub(8), sq + 8, BLACK_PAWN)
+            } else {
+                (2, sq + 8, sq.wrapping_sub(8), WHITE_PAWN)
+            };
+            if sq / 8 != rank || board.squares[pawn_sq] != pawn
+                || board.squares[sq] != EMPTY || board.squares[from_sq] != EMPTY {
+                return Err(invalid_en_passant());
             }
+            board.en_passant_square = sq as i8;
         }
 
         // Parse halfmove clock
         if parts.len() > 4 {
-            board.halfmove_clock = parts[4].parse().unwrap_or(0);
+            board.halfmove_clock = parts[4].parse()
+                .map_err(|_| FenError::InvalidMoveCounter(parts[4].to_string()))?;
         }
 
         // Parse fullmove number
         if parts.len() > 5 {
-            board.fullmove_number = parts[5].parse().unwrap_or(1);
+            board.fullmove_number = parts[5].parse::<u16>().ok()
+                .filter(|&n| n > 0)
+                .ok_or_else(|| FenError::InvalidMoveCounter(parts[5].to_string()))?;
         }
 
-        // Sync bitboards from squares
-        board.sync_bitboards();
+        // The side that just moved cannot have left its king in check
+        let their_king = board.find_king(!board.white_to_move).unwrap();
+        let attackers = attackers_to(their_king, board.occupied(), board.bb_white, board.bb_black,
+                                     board.bb_pawns, board.bb_knights, board.bb_bishops, board.bb_rooks,
+                                     board.bb_queens, board.bb_kings);
+        let ours = if board.white_to_move { board.bb_white } else { board.bb_black };
+        if attackers & ours != 0 {
+            return Err(FenError::OpponentInCheck);
+        }
 
         // Initialize position history
         board.position_history.push(board.compute_hash());
 
-        Some(board)
+        Ok(board)
+    }
+
+    /// Piece counts a game can reach: one king, at most 16 pieces and 8
+    /// pawns per side, and no pawn on the first or last rank
+    fn validate_material(&self) -> Result<(), FenError> {
+        for white in [true, false] {
+            let color = if white { self.bb_white } else { self.bb_black };
+            let kings = (self.bb_kings & color).count_ones();
+            if kings != 1 {
+                return Err(FenError::KingCount { white, count: kings });
+            }
+            if color.count_ones() > 16 || (self.bb_pawns & color).count_ones() > 8 {
+                return Err(FenError::TooManyPieces { white });
+            }
+        }
+
+        const BACK_RANKS: u64 = 0xff00_0000_0000_00ff;
+        let back_rank_pawns = self.bb_pawns & BACK_RANKS;
+        if back_rank_pawns != 0 {
+            return Err(FenError::PawnOnBackRank(lsb(back_rank_pawns)));
+        }
+        Ok(())
     }
 
     /// Generate FEN string from current board state
@@ -271,11 +515,33 @@ impl Board {
         fen.push(' ');
         if self.castling_rights == 0 {
             fen.push('-');
+        } else if self.chess960 {
+            for (index, &rook_sq) in self.castling_rooks.iter().enumerate() {
+                if self.castling_rights & (1 << index) != 0 {
+                    let file = FILE_NAMES[rook_sq % 8] as char;
+                    fen.push(if index < 2 { file.to_ascii_uppercase() } else { file });
+                }
+            }
         } else {
-            if self.castling_rights & CASTLE_WK != 0 { fen.push('K'); }
-            if self.castling_rights & CASTLE_WQ != 0 { fen.push('Q'); }
-            if self.castling_rights & CASTLE_BK != 0 { fen.push('k'); }
-            if self.castling_rights & CASTLE_BQ != 0 { fen.push('q'); }
+            // X-FEN: KQkq, unless another rook stands between the castling
+            // rook and the board edge, which takes the rook's file instead
+            for (index, &rook_sq) in self.castling_rooks.iter().enumerate() {
+                if self.castling_rights & (1 << index) == 0 {
+                    continue;
+                }
+                let (white, kingside) = (index < 2, index % 2 == 0);
+                let base = if white { 0 } else { 56 };
+                let outer = if kingside { rook_sq + 1..base + 8 } else { base..rook_sq };
+                let rook = self.squares[rook_sq];
+                let c = if outer.clone().any(|sq| self.squares[sq] == rook) {
+                    FILE_NAMES[rook_sq % 8] as char
+                } else if kingside {
+                    'k'
+                } else {
+                    'q'
+                };
+                fen.push(if white { c.to_ascii_uppercase() } else { c });
+            }
         }
 
         // En passant
@@ -292,14 +558,9 @@ impl Board {
         fen
     }
 
-    /// Compute a hash of the current position for repetition detection
-    fn compute_hash(&self) -> u64 {
-        let mut hasher = DefaultHasher::new();
-        self.squares.hash(&mut hasher);
-        self.white_to_move.hash(&mut hasher);
-        self.castling_rights.hash(&mut hasher);
-        self.en_passant_square.hash(&mut hasher);
-        hasher.finish()
+    /// Zobrist hash of the current position, as used by the search
+    pub fn compute_hash(&self) -> u64 {
+        ZobristHash::get().hash_position(self)
     }
 
     /// Execute a move on the board. Returns UndoInfo for undoing the move later.
@@ -307,7 +568,7 @@ impl Board {
         let from_sq = mv.from_sq;
         let to_sq = mv.to_sq;
         let piece = self.squares[from_sq];
-        let captured = self.squares[to_sq];
+        let captured = self.captured_piece(mv);
 
         // Save undo information
         let undo = UndoInfo {
@@ -346,52 +607,35 @@ impl Board {
             self.clear_piece_bb(to_sq, captured);
         }
 
-        // Handle castling
         if mv.is_castling {
-            match to_sq {
-                6 => {  // White kingside (g1)
-                    self.squares[7] = EMPTY;
-                    self.squares[5] = WHITE_ROOK;
-                    self.clear_piece_bb(7, WHITE_ROOK);
-                    self.set_piece_bb(5, WHITE_ROOK);
-                }
-                2 => {  // White queenside (c1)
-                    self.squares[0] = EMPTY;
-                    self.squares[3] = WHITE_ROOK;
-                    self.clear_piece_bb(0, WHITE_ROOK);
-                    self.set_piece_bb(3, WHITE_ROOK);
-                }
-                62 => { // Black kingside (g8)
-                    self.squares[63] = EMPTY;
-                    self.squares[61] = BLACK_ROOK;
-                    self.clear_piece_bb(63, BLACK_ROOK);
-                    self.set_piece_bb(61, BLACK_ROOK);
-                }
-                58 => { // Black queenside (c8)
-                    self.squares[56] = EMPTY;
-                    self.squares[59] = BLACK_ROOK;
-                    self.clear_piece_bb(56, BLACK_ROOK);
-                    self.set_piece_bb(59, BLACK_ROOK);
-                }
-                _ => {}
-            }
-        }
-
-        // Move the piece
-        self.squares[to_sq] = piece;
-        self.squares[from_sq] = EMPTY;
-
-        // Handle promotion
-        let final_piece = if mv.promotion != 0 {
-            let promo_piece = (if self.white_to_move { WHITE } else { BLACK }) | mv.promotion;
-            self.squares[to_sq] = promo_piece;
-            promo_piece
+            // Both pieces leave before either lands: in Chess960 the king or
+            // the rook may end on the other's start square
+            let (king_to, rook_from, rook_to) = self.castling_squares(mv);
+            let rook = self.squares[rook_from];
+            self.squares[from_sq] = EMPTY;
+            self.squares[rook_from] = EMPTY;
+            self.clear_piece_bb(rook_from, rook);
+            self.squares[rook_to] = rook;
+            self.set_piece_bb(rook_to, rook);
+            self.squares[king_to] = piece;
+            self.set_piece_bb(king_to, piece);
         } else {
-            piece
-        };
+            // Move the piece
+            self.squares[to_sq] = piece;
+            self.squares[from_sq] = EMPTY;
+
+            // Handle promotion
+            let final_piece = if mv.promotion != 0 {
+                let promo_piece = (if self.white_to_move { WHITE } else { BLACK }) | mv.promotion;
+                self.squares[to_sq] = promo_piece;
+                promo_piece
+            } else {
+                piece
+            };
 
-        // Set piece at destination
-        self.set_piece_bb(to_sq, final_piece);
+            // Set piece at destination
+            self.set_piece_bb(to_sq, final_piece);
+        }
 
         // Update castling rights
         if piece_type == KING {
@@ -403,10 +647,11 @@ impl Board {
         }
 
         // If rook moves or is captured
-        if from_sq == 0 || to_sq == 0 { self.castling_rights &= !CASTLE_WQ; }
-        if from_sq == 7 || to_sq == 7 { self.castling_rights &= !CASTLE_WK; }
-        if from_sq == 56 || to_sq == 56 { self.castling_rights &= !CASTLE_BQ; }
-        if from_sq == 63 || to_sq == 63 { self.castling_rights &= !CASTLE_BK; }
+        for (index, &rook_sq) in self.castling_rooks.iter().enumerate() {
+            if from_sq == rook_sq || to_sq == rook_sq {
+                self.castling_rights &= !(1 << index);
+            }
+        }
 
         // Update en passant square
         self.en_passant_square = -1;
@@ -439,63 +684,46 @@ impl Board {
         let from_sq = mv.from_sq;
         let to_sq = mv.to_sq;
 
-        // Get the piece that was at to_sq (could be promoted piece)
-        let piece_at_to = self.squares[to_sq];
-
         // === INCREMENTAL BITBOARD UPDATES ===
-        
-        // Clear piece from destination (might be promoted piece)
-        self.clear_piece_bb(to_sq, piece_at_to);
 
-        // Restore the moved piece at source
-        self.squares[from_sq] = undo.moved_piece;
-        self.set_piece_bb(from_sq, undo.moved_piece);
+        if mv.is_castling {
+            // Lift king and rook before putting them back, as in make_move
+            let (king_to, rook_from, rook_to) = self.castling_squares(mv);
+            let rook = self.squares[rook_to];
+            self.squares[king_to] = EMPTY;
+            self.clear_piece_bb(king_to, undo.moved_piece);
+            self.squares[rook_to] = EMPTY;
+            self.clear_piece_bb(rook_to, rook);
+            self.squares[rook_from] = rook;
+            self.set_piece_bb(rook_from, rook);
+            self.squares[from_sq] = undo.moved_piece;
+            self.set_piece_bb(from_sq, undo.moved_piece);
+        } else {
+            // Get the piece that was at to_sq (could be promoted piece)
+            let piece_at_to = self.squares[to_sq];
 
-        // Restore captured piece
+            // Clear piece from destination (might be promoted piece)
+            self.clear_piece_bb(to_sq, piece_at_to);
+
+            // Restore the moved piece at source
+            self.squares[from_sq] = undo.moved_piece;
+            self.set_piece_bb(from_sq, undo.moved_piece);
+        }
+
+        // Restore captured piece (castling captures nothing)
         if mv.is_en_passant {
             self.squares[to_sq] = EMPTY;
             let ep_restore_sq = if self.white_to_move { to_sq - 8 } else { to_sq + 8 };
             let ep_pawn = if self.white_to_move { BLACK_PAWN } else { WHITE_PAWN };
             self.squares[ep_restore_sq] = ep_pawn;
             self.set_piece_bb(ep_restore_sq, ep_pawn);
-        } else {
+        } else if !mv.is_castling {
             self.squares[to_sq] = undo.captured_piece;
             if undo.captured_piece != EMPTY {
                 self.set_piece_bb(to_sq, undo.captured_piece);
             }
         }
 
-        // Handle castling - move rook back
-        if mv.is_castling {
-            match to_sq {
-                6 => {  // White kingside
-                    self.squares[5] = EMPTY;
-                    self.squares[7] = WHITE_ROOK;
-                    self.clear_piece_bb(5, WHITE_ROOK);
-                    self.set_piece_bb(7, WHITE_ROOK);
-                }
-                2 => {  // White queenside
-                    self.squares[3] = EMPTY;
-                    self.squares[0] = WHITE_ROOK;
-                    self.clear_piece_bb(3, WHITE_ROOK);
-                    self.set_piece_bb(0, WHITE_ROOK);
-                }
-                62 => { // Black kingside
-                    self.squares[61] = EMPTY;
-                    self.squares[63] = BLACK_ROOK;
-                    self.clear_piece_bb(61, BLACK_ROOK);
-                    self.set_piece_bb(63, BLACK_ROOK);
-                }
-                58 => { // Black queenside
-                    self.squares[59] = EMPTY;
-                    self.squares[56] = BLACK_ROOK;
-                    self.clear_piece_bb(59, BLACK_ROOK);
-                    self.set_piece_bb(56, BLACK_ROOK);
-                }
-                _ => {}
-            }
-        }
-
         // Restore game state
         self.castling_rights = undo.castling_rights;
         self.en_passant_square = undo.en_passant_square;
@@ -510,15 +738,48 @@ impl Board {
         self.position_history.pop();
     }
 
+    /// Piece captured by `mv` (EMPTY for quiet moves, en passant and
+    /// castling, whose Chess960 target is the own rook)
+    #[inline]
+    pub fn captured_piece(&self, mv: &Move) -> u8 {
+        if mv.is_castling { EMPTY } else { self.squares[mv.to_sq] }
+    }
+
+    /// King destination, rook start and rook destination of a castling move
+    pub fn castling_squares(&self, mv: &Move) -> (usize, usize, usize) {
+        let base = mv.from_sq - mv.from_sq % 8;
+        let kingside = mv.to_sq > mv.from_sq;
+        let rook_from = self.castling_rooks[castling_index(base == 0, kingside)];
+        if kingside {
+            (base + 6, rook_from, base + 5)
+        } else {
+            (base + 2, rook_from, base + 3)
+        }
+    }
+
     /// Find the king's square for the specified color
     pub fn find_king(&self, white: bool) -> Option<usize> {
-        let king = if white { WHITE_KING } else { BLACK_KING };
-        for sq in 0..64 {
-            if self.squares[sq] == king {
-                return Some(sq);
-            }
+        let king = self.pieces(if white { WHITE } else { BLACK }, KING);
+        if king == 0 { None } else { Some(lsb(king)) }
+    }
+
+    /// Copy of the board for searching: the position history is cut back to
+    /// the last irreversible move, since earlier positions can never repeat
+    pub fn search_copy(&self) -> Board {
+        let keep = (self.halfmove_clock as usize + 1).min(self.position_history.len());
+        Board {
+            position_history: self.position_history[self.position_history.len() - keep..].to_vec(),
+            ..*self
         }
-        None
+    }
+
+    /// Game phase from the remaining pieces: minor pieces count 1, rooks 2
+    /// and queens 4, so the initial position is MAX_PHASE and pawn endings 0
+    pub fn phase(&self) -> i32 {
+        let minors = (self.bb_knights | self.bb_bishops).count_ones() as i32;
+        let rooks = self.bb_rooks.count_ones() as i32;
+        let queens = self.bb_queens.count_ones() as i32;
+        (minors + 2 * rooks + 4 * queens).min(MAX_PHASE)
     }
 
     /// Count how many times the current position has occurred
@@ -543,50 +804,50 @@ impl Board {
         self.halfmove_clock >= 100
     }
 
-    /// Check for insufficient material to checkmate
+    /// Whether the position is dead: neither side can checkmate by any
+    /// sequence of legal moves
     pub fn has_insufficient_material(&self) -> bool {
-        let mut pieces: Vec<(u8, u8, usize)> = Vec::new();
-        
-        for sq in 0..64 {
-            let piece = self.squares[sq];
-            if piece != EMPTY {
-                pieces.push((get_piece_type(piece), get_piece_color(piece), sq));
-            }
-        }
+        !self.can_mate(true) && !self.can_mate(false)
+    }
 
-        // Only kings left
-        if pieces.len() == 2 {
+    /// Whether `white` could still checkmate by some sequence of legal moves,
+    /// however unlikely, as the FIDE rules ask when a flag falls. A lone
+    /// knight needs an enemy pawn, minor piece or rook to hem in the king,
+    /// and bishops all on one square color need an enemy pawn or knight;
+    /// enemy queens and rooks could always block the bishops' check.
+    pub fn can_mate(&self, white: bool) -> bool {
+        let (own, their) = if white { (self.bb_white, self.bb_black) } else { (self.bb_black, self.bb_white) };
+        if own & (self.bb_pawns | self.bb_rooks | self.bb_queens) != 0 {
             return true;
         }
 
-        // King and minor piece vs King
-        if pieces.len() == 3 {
-            for (ptype, _, _) in &pieces {
-                if *ptype == KNIGHT || *ptype == BISHOP {
-                    return true;
-                }
-            }
+        let knights = own & self.bb_knights;
+        let bishops = own & self.bb_bishops;
+        if knights != 0 {
+            return (knights | bishops).count_ones() >= 2
+                || their & !(self.bb_kings | self.bb_queens) != 0;
+        }
+        if bishops != 0 {
+            let all_bishops = self.bb_bishops;
+            let one_color = all_bishops & LIGHT_SQUARES == 0 || all_bishops & DARK_SQUARES == 0;
+            return !one_color || their & (self.bb_pawns | self.bb_knights) != 0;
         }
+        false
+    }
 
-        // King + Bishop vs King + Bishop (same color squares)
-        if pieces.len() == 4 {
-            let bishops: Vec<(usize, u8)> = pieces.iter()
-                .filter(|(pt, _, _)| *pt == BISHOP)
-                .map(|(_, c, sq)| (*sq, *c))
-                .collect();
-            
-            if bishops.len() == 2 {
-                let (sq1, c1) = bishops[0];
-                let (sq2, c2) = bishops[1];
-                let sq1_color = (sq1 / 8 + sq1 % 8) % 2;
-                let sq2_color = (sq2 / 8 + sq2 % 8) % 2;
-                if sq1_color == sq2_color && c1 != c2 {
-                    return true;
-                }
-            }
+    /// Whether `white` has the material to force mate against a bare king:
+    /// a pawn, rook or queen, bishops on both square colors, bishop and
+    /// knight, or three knights. Two knights cannot force it.
+    pub fn can_force_mate(&self, white: bool) -> bool {
+        let own = if white { self.bb_white } else { self.bb_black };
+        if own & (self.bb_pawns | self.bb_rooks | self.bb_queens) != 0 {
+            return true;
         }
 
-        false
+        let knights = (own & self.bb_knights).count_ones();
+        let bishops = own & self.bb_bishops;
+        let both_colors = bishops & LIGHT_SQUARES != 0 && bishops & DARK_SQUARES != 0;
+        both_colors || (bishops != 0 && knights > 0) || knights >= 3
     }
 
     /// Create a copy of the board
@@ -633,6 +894,8 @@ impl Board {
         self.bb_kings = 0;
         self.bb_white = 0;
         self.bb_black = 0;
+        self.psqt = 0;
+        self.material = [0; 2];
 
         for sq in 0..64 {
             let piece = self.squares[sq];
@@ -661,6 +924,11 @@ impl Board {
             } else {
                 self.bb_black |= sq_bb;
             }
+
+            self.psqt += psqt_value(piece, sq);
+            if piece_type != KING {
+                self.material[!is_white as usize] += PIECE_VALUES[piece_type as usize];
+            }
         }
     }
 
@@ -689,6 +957,11 @@ impl Board {
         } else {
             self.bb_black |= sq_bb;
         }
+
+        self.psqt += psqt_value(piece, sq);
+        if piece_type != KING {
+            self.material[!is_white(piece) as usize] += PIECE_VALUES[piece_type as usize];
+        }
     }
 
     /// Clear a piece from a square in bitboards
@@ -716,14 +989,62 @@ impl Board {
         } else {
             self.bb_black &= sq_bb;
         }
+
+        self.psqt -= psqt_value(piece, sq);
+        if piece_type != KING {
+            self.material[!is_white(piece) as usize] -= PIECE_VALUES[piece_type as usize];
+        }
+    }
+
+    /// Bitboard of the pieces of one type (PAWN..KING) of one color
+    /// (WHITE or BLACK)
+    #[inline]
+    pub fn pieces(&self, color: u8, piece_type: u8) -> u64 {
+        self.pieces_of_type(piece_type) & self.pieces_of_color(color)
+    }
+
+    /// Bitboard of the pieces of one type of both colors
+    #[inline]
+    pub fn pieces_of_type(&self, piece_type: u8) -> u64 {
+        match piece_type {
+            PAWN => self.bb_pawns,
+            KNIGHT => self.bb_knights,
+            BISHOP => self.bb_bishops,
+            ROOK => self.bb_rooks,
+            QUEEN => self.bb_queens,
+            KING => self.bb_kings,
+            _ => 0,
+        }
+    }
+
+    /// Bitboard of all pieces of one color
+    #[inline]
+    pub fn pieces_of_color(&self, color: u8) -> u64 {
+        if color == WHITE { self.bb_white } else { self.bb_black }
     }
 
     /// Get the bitboard of all occupied squares
     #[inline]
-    pub fn get_occupied(&self) -> u64 {
+    pub fn occupied(&self) -> u64 {
         self.bb_white | self.bb_black
     }
 
+    /// Squares holding `piece` (a piece type with its color)
+    #[inline]
+    pub fn piece_squares(&self, piece: u8) -> Squares {
+        Squares(self.pieces(get_piece_color(piece), get_piece_type(piece)))
+    }
+
+    /// Squares and pieces of one color, lowest square first
+    pub fn pieces_of(&self, color: u8) -> impl Iterator<Item = (usize, u8)> + '_ {
+        Squares(self.pieces_of_color(color)).map(|sq| (sq, self.squares[sq]))
+    }
+
+    /// Squares and pieces of both colors, lowest square first
+    pub fn all_pieces(&self) -> impl Iterator<Item = (usize, u8)> + '_ {
+        Squares(self.occupied()).map(|sq| (sq, self.squares[sq]))
+    }
+
     /// Get bitboard of pieces for the current side to move
     #[inline]
     pub fn get_own_pieces(&self) -> u64 {
@@ -0,0 +1,190 @@
+//! OpusChess - Opening Book Module
+//!
+//! This module provides read-only opening book support. The book format is
+//! selected from the file extension of the BookFile option:
+//! - `.abk` - Arena books, a move tree rooted at the initial position
+//!
+//! Books are looked up by the moves played from the initial position, so
+//! games started from a FEN never use the book.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use rand::prelude::*;
+
+/// Book file formats that can be read
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BookFormat {
+    Abk,
+}
+
+impl BookFormat {
+    /// Detect the format from the file extension
+    pub fn from_path(path: &str) -> Option<Self> {
+        let extension = Path::new(path).extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "abk" => Some(BookFormat::Abk),
+            _ => None,
+        }
+    }
+}
+
+/// A loaded opening book
+pub enum OpeningBook {
+    Abk(AbkBook),
+}
+
+impl OpeningBook {
+    /// Open `path`, choosing the reader by file extension
+    pub fn open(path: &str) -> io::Result<Self> {
+        match BookFormat::from_path(path) {
+            Some(BookFormat::Abk) => Ok(OpeningBook::Abk(AbkBook::open(path)?)),
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput, "unsupported book format")),
+        }
+    }
+
+    /// Book moves (UCI notation) and their weights after `moves` were played
+    /// from the initial position
+    pub fn candidates(&self, moves: &[String]) -> Vec<(String, u32)> {
+        match self {
+            OpeningBook::Abk(book) => book.candidates(moves),
+        }
+    }
+
+    /// Pick a book move at random, weighted by the book's statistics
+    pub fn pick(&self, moves: &[String]) -> Option<String> {
+        let candidates = self.candidates(moves);
+        let total: u32 = candidates.iter().map(|&(_, weight)| weight).sum();
+        if total == 0 {
+            return None;
+        }
+
+        let mut roll = thread_rng().gen_range(0..total);
+        for (mv, weight) in candidates {
+            if roll < weight {
+                return Some(mv);
+            }
+            roll -= weight;
+        }
+        None
+    }
+}
+
+// ============================================================================
+// ARENA (ABK)
+// ============================================================================
+
+/// Size of one ABK tree entry in bytes
+const ABK_ENTRY_SIZE: usize = 28;
+
+/// Index of the first move of the initial position (after the header)
+const ABK_ROOT_INDEX: usize = 900;
+
+/// One node of the ABK move tree
+struct AbkEntry {
+    from_sq: u8,
+    to_sq: u8,
+    promotion: i8,
+    priority: u8,
+    games: i32,
+    first_child: i32,
+    next_sibling: i32,
+}
+
+impl AbkEntry {
+    fn to_uci(&self) -> String {
+        let square = |sq: u8| {
+            let file = (b'a' + sq % 8) as char;
+            let rank = (b'1' + sq / 8) as char;
+            format!("{}{}", file, rank)
+        };
+
+        let mut uci = format!("{}{}", square(self.from_sq), square(self.to_sq));
+        match self.promotion.abs() {
+            1 => uci.push('r'),
+            2 => uci.push('n'),
+            3 => uci.push('b'),
+            4 => uci.push('q'),
+            _ => {}
+        }
+        uci
+    }
+
+    fn weight(&self) -> u32 {
+        (self.games.max(0) as u32).max(self.priority as u32)
+    }
+}
+
+/// Arena opening book, kept in memory as raw tree entries
+pub struct AbkBook {
+    data: Vec<u8>,
+}
+
+impl AbkBook {
+    pub fn open(path: &str) -> io::Result<Self> {
+        let data = fs::read(path)?;
+        if data.len() < (ABK_ROOT_INDEX + 1) * ABK_ENTRY_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "ABK file too short"));
+        }
+        Ok(AbkBook { data })
+    }
+
+    fn entry(&self, index: usize) -> Option<AbkEntry> {
+        let start = index.checked_mul(ABK_ENTRY_SIZE)?;
+        let bytes = self.data.get(start..start + ABK_ENTRY_SIZE)?;
+        let int_at = |offset: usize| {
+            i32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+        };
+
+        Some(AbkEntry {
+            from_sq: bytes[0],
+            to_sq: bytes[1],
+            promotion: bytes[2] as i8,
+            priority: bytes[3],
+            games: int_at(4),
+            first_child: int_at(20),
+            next_sibling: int_at(24),
+        })
+    }
+
+    /// Entries of the sibling list starting at `index`
+    fn siblings(&self, index: usize) -> Vec<AbkEntry> {
+        let mut entries = Vec::new();
+        let mut next = Some(index);
+
+        while let Some(index) = next {
+            // Guard against cyclic links in corrupt books
+            if entries.len() > 256 {
+                break;
+            }
+            let Some(entry) = self.entry(index) else { break };
+            next = usize::try_from(entry.next_sibling).ok();
+            if entry.from_sq < 64 && entry.to_sq < 64 {
+                entries.push(entry);
+            }
+        }
+
+        entries
+    }
+
+    pub fn candidates(&self, moves: &[String]) -> Vec<(String, u32)> {
+        let mut index = ABK_ROOT_INDEX;
+
+        for played in moves {
+            let child = self.siblings(index)
+                .into_iter()
+                .find(|entry| entry.to_uci() == *played)
+                .and_then(|entry| usize::try_from(entry.first_child).ok());
+            match child {
+                Some(child) => index = child,
+                None => return Vec::new(),
+            }
+        }
+
+        self.siblings(index)
+            .into_iter()
+            .map(|entry| (entry.to_uci(), entry.weight()))
+            .collect()
+    }
+}
@@ -0,0 +1,347 @@
+//! OpusChess - Cluster Search Module (experimental)
+//!
+//! This module distributes a search over several machines. The master splits
+//! the root moves between itself and the worker processes listed in the
+//! ClusterHosts option; every participant searches its share to the requested
+//! depth and the master keeps the best result. Moves assigned to a worker that
+//! cannot be reached are searched locally instead.
+//!
+//! Workers are started with `opus_chess --cluster-worker [address]` and talk a
+//! line-based protocol over TCP:
+//!
+//! ```text
+//! master -> worker: search <depth> [movetime <ms>] [nodes <n>] <root moves...> fen <FEN>
+//! master -> worker: stop
+//! worker -> master: result <move|0000> <score> <nodes>
+//! ```
+//!
+//! The movetime is what is left of the master's hard time limit and the node
+//! budget applies to each participant. `stop` ends the worker's search early,
+//! which still replies with its result. The master gives up on a worker that
+//! has not replied shortly after the deadline or a stop, and searches its
+//! moves locally.
+//!
+//! Only the FEN is sent, so workers do not see the game history and cannot
+//! detect repetitions that involve earlier positions.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{atomic::{AtomicBool, Ordering}, mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::board::{Board, Move};
+use crate::move_generator::MoveGenerator;
+use crate::parallel_search::ParallelSearchEngine;
+use crate::search::{ScoreBound, INFINITY};
+use crate::time_manager::SearchLimits;
+
+/// Port used when a host is given without one
+pub const DEFAULT_PORT: u16 = 7700;
+
+/// How long the master waits for a worker to accept the connection
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long the master waits for a reply past the deadline or a stop
+const REPLY_GRACE: Duration = Duration::from_millis(100);
+
+/// How often the master checks for a stop while waiting for workers
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Combined result of a cluster search
+pub struct ClusterResult {
+    pub best_move: Option<Move>,
+    pub score: i32,
+    pub nodes: u64,
+}
+
+/// Split a ClusterHosts value ("host1:7700, host2") into socket addresses
+pub fn parse_hosts(value: &str) -> Vec<String> {
+    value.split([',', ' '])
+        .map(str::trim)
+        .filter(|host| !host.is_empty())
+        .map(|host| {
+            if host.contains(':') {
+                host.to_string()
+            } else {
+                format!("{}:{}", host, DEFAULT_PORT)
+            }
+        })
+        .collect()
+}
+
+// ============================================================================
+// MASTER
+// ============================================================================
+
+/// Search `board` to `depth` and the engine's limits, sharing the root moves
+/// round-robin between the local engine and `hosts`. `info_callback`
+/// receives the progress of the local part of the search.
+pub fn search<F>(engine: &mut ParallelSearchEngine, board: &Board, depth: i32, hosts: &[String],
+                 mut info_callback: Option<F>) -> ClusterResult
+where F: FnMut(i32, i32, ScoreBound, u64, u64, &str, usize, u64)
+{
+    let move_generator = MoveGenerator::new();
+    let moves = move_generator.generate_legal_moves(board);
+
+    let shares = hosts.len() + 1;
+    let mut buckets: Vec<Vec<Move>> = vec![Vec::new(); shares];
+    for (i, mv) in moves.into_iter().enumerate() {
+        buckets[i % shares].push(mv);
+    }
+
+    // Every local search consumes the limits, so each gets its own copy
+    let limits = std::mem::take(&mut engine.limits);
+    let remote_stop = Arc::new(AtomicBool::new(false));
+
+    // Remote shares run in the background while the local share is searched
+    let fen = board.to_fen();
+    let remote_handles: Vec<_> = hosts.iter()
+        .zip(buckets.iter().skip(1))
+        .filter(|(_, bucket)| !bucket.is_empty())
+        .map(|(host, bucket)| {
+            let (host, fen, moves) = (host.clone(), fen.clone(), bucket.clone());
+            let (deadline, nodes) = (limits.deadline(), limits.nodes);
+            let stop = Arc::clone(&remote_stop);
+            let handle = thread::spawn(move || {
+                remote_search(&host, &fen, depth, deadline, nodes, &moves, &stop)
+            });
+            (bucket.clone(), handle)
+        })
+        .collect();
+
+    let mut result = ClusterResult { best_move: None, score: -INFINITY, nodes: 0 };
+    let mut local_moves = buckets.swap_remove(0);
+    engine.pv.clear();
+
+    if !local_moves.is_empty() {
+        local_search(engine, board, depth, &limits, &local_moves, info_callback.as_mut(), &mut result);
+    }
+
+    // The local search leaves the stop flag raised, so only a stop raised
+    // after this reset comes from the caller; the UCI loop repeats its stop
+    // until the search ends, so one that arrived earlier is raised again
+    let stop = engine.stop_handle();
+    stop.store(false, Ordering::SeqCst);
+    while remote_handles.iter().any(|(_, handle)| !handle.is_finished()) {
+        if stop.load(Ordering::SeqCst) {
+            remote_stop.store(true, Ordering::SeqCst);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    // Collect remote results; moves of failed workers fall back to the local engine
+    local_moves.clear();
+    for (bucket, handle) in remote_handles {
+        match handle.join() {
+            Ok(Ok(remote)) => merge(&mut result, remote),
+            _ => local_moves.extend(bucket),
+        }
+    }
+
+    if !local_moves.is_empty() {
+        local_search(engine, board, depth, &limits, &local_moves, info_callback.as_mut(), &mut result);
+    }
+
+    // Keep the engine's PV only if it belongs to the chosen move
+    if engine.pv.first() != result.best_move.as_ref() {
+        engine.pv.clear();
+    }
+
+    result
+}
+
+/// Search `moves` on the local engine and merge the outcome into `result`,
+/// keeping the engine's PV from the best local search so far
+fn local_search<F>(engine: &mut ParallelSearchEngine, board: &Board, depth: i32, limits: &SearchLimits,
+                   moves: &[Move], info_callback: Option<&mut F>, result: &mut ClusterResult)
+where F: FnMut(i32, i32, ScoreBound, u64, u64, &str, usize, u64)
+{
+    let pv = std::mem::take(&mut engine.pv);
+
+    engine.limits = limits.clone();
+    engine.root_moves = moves.to_vec();
+    let (best_move, score) = engine.search(board, depth, info_callback);
+    engine.root_moves.clear();
+
+    let improved = best_move.is_some() && (result.best_move.is_none() || score > result.score);
+    merge(result, ClusterResult { best_move, score, nodes: engine.nodes_searched });
+    if !improved {
+        engine.pv = pv;
+    }
+}
+
+fn merge(result: &mut ClusterResult, other: ClusterResult) {
+    result.nodes += other.nodes;
+    if other.best_move.is_some() && (result.best_move.is_none() || other.score > result.score) {
+        result.best_move = other.best_move;
+        result.score = other.score;
+    }
+}
+
+/// Ask the worker at `host` to search `moves` of the position `fen` until
+/// `deadline` or `nodes`, passing `stop` on once it is raised
+fn remote_search(host: &str, fen: &str, depth: i32, deadline: Option<Instant>, nodes: Option<u64>,
+                 moves: &[Move], stop: &AtomicBool) -> io::Result<ClusterResult> {
+    let addr = host.to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "host has no address"))?;
+    let mut stream = TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)?;
+
+    let mut request = format!("search {}", depth);
+    if let Some(deadline) = deadline {
+        let movetime = deadline.saturating_duration_since(Instant::now()).as_millis().max(1);
+        request.push_str(&format!(" movetime {}", movetime));
+    }
+    if let Some(nodes) = nodes {
+        request.push_str(&format!(" nodes {}", nodes));
+    }
+    let move_list: Vec<String> = moves.iter().map(|m| m.to_uci()).collect();
+    writeln!(stream, "{} {} fen {}", request, move_list.join(" "), fen)?;
+    stream.flush()?;
+
+    // Wait for the reply in short slices, to pass a stop on and to give up
+    // on a worker that misses the deadline
+    stream.set_read_timeout(Some(POLL_INTERVAL))?;
+    let mut give_up = deadline.map(|deadline| deadline + REPLY_GRACE);
+    let mut stop_sent = false;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut line = String::new();
+    loop {
+        match reader.read_line(&mut line) {
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "worker closed the connection")),
+            Ok(_) => break,
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                let now = Instant::now();
+                if give_up.is_some_and(|give_up| now >= give_up) {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "worker missed the deadline"));
+                }
+                if stop.load(Ordering::SeqCst) && !stop_sent {
+                    writeln!(stream, "stop")?;
+                    stream.flush()?;
+                    stop_sent = true;
+                    give_up = Some(give_up.map_or(now + REPLY_GRACE, |give_up| give_up.min(now + REPLY_GRACE)));
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    if parts.len() != 4 || parts[0] != "result" {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "malformed worker reply"));
+    }
+
+    let best_move = moves.iter().copied().find(|m| m.to_uci() == parts[1]);
+    let score = parts[2].parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "bad score"))?;
+    let nodes = parts[3].parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "bad node count"))?;
+
+    Ok(ClusterResult { best_move, score, nodes })
+}
+
+// ============================================================================
+// WORKER
+// ============================================================================
+
+/// Serve search requests from masters on `addr` until the process is killed
+pub fn run_worker(addr: &str, tt_size_mb: usize, threads: usize) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    println!("info string cluster worker listening on {}", listener.local_addr()?);
+
+    let mut engine = ParallelSearchEngine::new(tt_size_mb, threads);
+    let move_generator = MoveGenerator::new();
+
+    for stream in listener.incoming() {
+        let result = stream.and_then(|stream| serve(&mut engine, &move_generator, stream));
+        if let Err(e) = result {
+            println!("info string cluster connection error: {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Answer every request sent over one connection
+fn serve(engine: &mut ParallelSearchEngine, move_generator: &MoveGenerator, stream: TcpStream) -> io::Result<()> {
+    // Lines are read on their own thread so that a stop reaches the search
+    // of the request before it; every request gets its own stop flag, which
+    // keeps a stop sent before the search starts
+    let reader = BufReader::new(stream.try_clone()?);
+    let (requests, received) = mpsc::channel();
+    thread::spawn(move || {
+        let mut stop = Arc::new(AtomicBool::new(false));
+        for line in reader.lines() {
+            let Ok(line) = line else { break };
+            if line.trim() == "stop" {
+                stop.store(true, Ordering::SeqCst);
+                continue;
+            }
+            stop = Arc::new(AtomicBool::new(false));
+            if requests.send((line, Arc::clone(&stop))).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut writer = stream;
+    for (line, stop) in received {
+        engine.search_stop = Some(stop);
+        let result = handle_request(engine, move_generator, line.trim());
+        engine.search_stop = None;
+
+        let reply = match result {
+            Some(result) => format!(
+                "result {} {} {}",
+                result.best_move.map(|m| m.to_uci()).unwrap_or_else(|| "0000".to_string()),
+                result.score,
+                result.nodes
+            ),
+            None => "error".to_string(),
+        };
+
+        writeln!(writer, "{}", reply)?;
+        writer.flush()?;
+    }
+
+    Ok(())
+}
+
+/// Parse and run one `search` request
+fn handle_request(engine: &mut ParallelSearchEngine, move_generator: &MoveGenerator, request: &str)
+    -> Option<ClusterResult>
+{
+    let (head, fen) = request.split_once(" fen ")?;
+    let mut parts = head.split_whitespace();
+    if parts.next()? != "search" {
+        return None;
+    }
+    let depth: i32 = parts.next()?.parse().ok()?;
+
+    let mut limits = SearchLimits::default();
+    let mut wanted = Vec::new();
+    while let Some(part) = parts.next() {
+        match part {
+            "movetime" => limits.time = SearchLimits::movetime(parts.next()?.parse().ok()?).time,
+            "nodes" => limits.nodes = Some(parts.next()?.parse().ok()?),
+            mv => wanted.push(mv),
+        }
+    }
+
+    let board = Board::from_fen(fen).ok()?;
+    let moves: Vec<Move> = move_generator.generate_legal_moves(&board)
+        .into_iter()
+        .filter(|m| wanted.contains(&m.to_uci().as_str()))
+        .collect();
+    if moves.is_empty() {
+        return None;
+    }
+
+    engine.limits = limits;
+    engine.root_moves = moves;
+    let (best_move, score) = engine.search::<fn(i32, i32, ScoreBound, u64, u64, &str, usize, u64)>(
+        &board, depth.clamp(1, 30), None
+    );
+    engine.root_moves.clear();
+
+    Some(ClusterResult { best_move, score, nodes: engine.nodes_searched })
+}
@@ -0,0 +1,307 @@
+//! OpusChess - Engine Module
+//!
+//! This module is the entry point for Rust programs that embed the engine
+//! instead of talking UCI to a subprocess:
+//! - `Engine::set_position` sets up a FEN (or the initial position) and the
+//!   moves played from it, in UCI notation
+//! - `Engine::set_option` takes the names and values of the UCI options
+//!   that affect the search
+//! - `Engine::search` searches to the given `SearchLimits` and returns a
+//!   `SearchResult`
+//! - `Engine::search_async` starts the same search on its own thread and
+//!   returns at once; the `SearchHandle` streams `SearchEvent`s over a
+//!   channel and stops or joins the search
+//! - `Engine::stop`, or a `StopHandle` used from another thread, ends the
+//!   running search early; its result is that of the last iteration. The
+//!   handle of an asynchronous search stops that search alone, even before
+//!   it has started.
+
+use std::fmt;
+use std::sync::{atomic::{AtomicBool, Ordering}, mpsc, Arc, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+
+use crate::board::{Board, FenError, Move};
+use crate::move_generator::MoveGenerator;
+use crate::nnue::{self, Network};
+use crate::parallel_search::{ParallelMode, ParallelSearchEngine};
+use crate::search::{ScoreBound, SearchEvent, SearchResult};
+use crate::time_manager::SearchLimits;
+
+/// Transposition table size of a new engine
+const DEFAULT_HASH_MB: usize = 64;
+
+/// Depth searched when the limits set none, deep enough that only `stop`
+/// or the other limits end the search
+const MAX_SEARCH_DEPTH: i32 = 30;
+
+/// Why the engine rejected a position or an option
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EngineError {
+    InvalidFen(FenError),
+    /// A move that is malformed or illegal in the position it is played in
+    IllegalMove(String),
+    UnknownOption(String),
+    InvalidValue { name: String, value: String },
+    /// The network file could not be loaded
+    EvalFile { path: String, reason: String },
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EngineError::InvalidFen(e) => write!(f, "invalid FEN: {}", e),
+            EngineError::IllegalMove(mv) => write!(f, "illegal move {}", mv),
+            EngineError::UnknownOption(name) => write!(f, "unknown option {}", name),
+            EngineError::InvalidValue { name, value } => write!(f, "invalid value {} for option {}", value, name),
+            EngineError::EvalFile { path, reason } => write!(f, "could not load EvalFile {}: {}", path, reason),
+        }
+    }
+}
+
+impl std::error::Error for EngineError {}
+
+impl From<FenError> for EngineError {
+    fn from(e: FenError) -> Self {
+        EngineError::InvalidFen(e)
+    }
+}
+
+/// Stop flags of the asynchronous searches not yet finished
+type AsyncStops = Arc<Mutex<Vec<Arc<AtomicBool>>>>;
+
+/// Stops the searches of the engine it came from, or the one search of a
+/// `SearchHandle`; it may be sent to another thread
+#[derive(Clone, Debug)]
+pub struct StopHandle {
+    flag: Arc<AtomicBool>,
+    /// Set for an engine's handle, which stops its asynchronous searches too
+    async_stops: Option<AsyncStops>,
+}
+
+impl StopHandle {
+    pub fn stop(&self) {
+        self.flag.store(true, Ordering::SeqCst);
+        if let Some(async_stops) = &self.async_stops {
+            for flag in async_stops.lock().unwrap_or_else(PoisonError::into_inner).iter() {
+                flag.store(true, Ordering::SeqCst);
+            }
+        }
+    }
+}
+
+/// A search started by `Engine::search_async`
+pub struct SearchHandle {
+    /// Progress of the search, ending with `SearchEvent::Finished`
+    pub events: mpsc::Receiver<SearchEvent>,
+    stop: StopHandle,
+    thread: JoinHandle<SearchResult>,
+}
+
+impl SearchHandle {
+    pub fn stop(&self) {
+        self.stop.stop();
+    }
+
+    pub fn stop_handle(&self) -> StopHandle {
+        self.stop.clone()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    /// Wait for the search to end; events not yet received stay readable
+    /// from `events` until the handle is dropped
+    pub fn wait(self) -> SearchResult {
+        self.thread.join().expect("search thread panicked")
+    }
+}
+
+/// A chess engine: the current position and the search state kept between
+/// searches (transposition table, history, options)
+pub struct Engine {
+    board: Board,
+    /// Shared with the thread of an asynchronous search, which holds the
+    /// lock until it ends
+    search_engine: Arc<Mutex<ParallelSearchEngine>>,
+    stop_search: Arc<AtomicBool>,
+    async_stops: AsyncStops,
+    move_generator: MoveGenerator,
+    chess960: bool,
+}
+
+/// Lock the shared engine; a search thread that panicked leaves it usable
+fn lock_engine(engine: &Mutex<ParallelSearchEngine>) -> MutexGuard<'_, ParallelSearchEngine> {
+    engine.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Search `board` to `limits` and collect the result
+fn run_search(engine: &mut ParallelSearchEngine, board: &Board, limits: SearchLimits) -> SearchResult {
+    type NoInfo = fn(i32, i32, ScoreBound, u64, u64, &str, usize, u64);
+
+    engine.limits = limits;
+    let (best_move, score) = engine.search::<NoInfo>(board, MAX_SEARCH_DEPTH, None);
+
+    SearchResult {
+        best_move,
+        score,
+        depth: engine.depth,
+        pv: engine.pv.clone(),
+        nodes: engine.nodes_searched,
+    }
+}
+
+impl Engine {
+    /// Engine on the initial position with a 64 MB hash and one thread
+    pub fn new() -> Self {
+        let search_engine = ParallelSearchEngine::new(DEFAULT_HASH_MB, 1);
+
+        Engine {
+            board: Board::new(),
+            stop_search: search_engine.stop_handle(),
+            search_engine: Arc::new(Mutex::new(search_engine)),
+            async_stops: Arc::default(),
+            move_generator: MoveGenerator::new(),
+            chess960: false,
+        }
+    }
+
+    /// Current position
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    /// Set up `fen` (the initial position when None) and play `moves`, in
+    /// UCI notation, from it. The position is unchanged on error.
+    pub fn set_position(&mut self, fen: Option<&str>, moves: &[&str]) -> Result<(), EngineError> {
+        let mut board = match fen {
+            Some(fen) => Board::from_fen(fen)?,
+            None => Board::new(),
+        };
+        // Chess960 castling moves are expected as king takes rook
+        board.chess960 = self.chess960;
+
+        for text in moves {
+            let mv = self.legal_move(&board, text)
+                .ok_or_else(|| EngineError::IllegalMove(text.to_string()))?;
+            board.make_move(&mv);
+        }
+
+        self.board = board;
+        Ok(())
+    }
+
+    /// Legal move of `board` written as `text` in UCI notation
+    fn legal_move(&self, board: &Board, text: &str) -> Option<Move> {
+        let Move { from_sq, to_sq, promotion, .. } = Move::from_uci(text)?;
+        self.move_generator.generate_legal_moves(board).into_iter()
+            .find(|mv| mv.from_sq == from_sq && mv.to_sq == to_sq && mv.promotion == promotion)
+    }
+
+    /// Set a UCI option. Supported: Threads, Hash, ParallelMode, Contempt,
+    /// UseTranspositionTable, UseNullMove, UseLMR, UseRazoring, UseProbcut,
+    /// UseCountermove, SharedHistory, Use NNUE, EvalFile and UCI_Chess960. Check options
+    /// take "true" or "false"; UCI_Chess960 applies from the next position.
+    /// Waits for a running asynchronous search to end.
+    pub fn set_option(&mut self, name: &str, value: &str) -> Result<(), EngineError> {
+        let invalid = || EngineError::InvalidValue { name: name.to_string(), value: value.to_string() };
+        let int = || value.trim().parse::<i32>().map_err(|_| invalid());
+        let check = || match value.trim() {
+            "true" => Ok(true),
+            "false" => Ok(false),
+            _ => Err(invalid()),
+        };
+
+        let mut engine = lock_engine(&self.search_engine);
+        match name {
+            "Threads" => engine.set_threads(usize::try_from(int()?).map_err(|_| invalid())?),
+            "Hash" => match int()? {
+                size if size > 0 => engine.resize_tt(size as usize),
+                _ => return Err(invalid()),
+            },
+            "ParallelMode" => engine.parallel_mode = ParallelMode::from_name(value).ok_or_else(invalid)?,
+            "Contempt" => engine.options.contempt = int()?,
+            "UseTranspositionTable" => engine.options.use_tt = check()?,
+            "UseNullMove" => engine.options.use_null_move = check()?,
+            "UseLMR" => engine.options.use_lmr = check()?,
+            "UseRazoring" => engine.options.use_razoring = check()?,
+            "UseProbcut" => engine.options.use_probcut = check()?,
+            "UseCountermove" => engine.options.use_countermove = check()?,
+            "SharedHistory" => engine.use_shared_history = check()?,
+            "Use NNUE" => {
+                engine.options.use_nnue = check()?;
+                if engine.options.use_nnue && engine.network.is_none() {
+                    engine.network = Some(load_network(nnue::DEFAULT_EVAL_FILE)?);
+                }
+            }
+            "EvalFile" => engine.network = Some(load_network(value)?),
+            "UCI_Chess960" => self.chess960 = check()?,
+            _ => return Err(EngineError::UnknownOption(name.to_string())),
+        }
+        Ok(())
+    }
+
+    /// Forget what earlier searches learned, before an unrelated game
+    pub fn new_game(&mut self) {
+        let mut engine = lock_engine(&self.search_engine);
+        engine.clear_tt();
+        engine.clear_history();
+    }
+
+    /// Search the current position until a limit is reached or the search
+    /// is stopped. Without any limit it only ends through a `StopHandle`.
+    pub fn search(&mut self, limits: SearchLimits) -> SearchResult {
+        run_search(&mut lock_engine(&self.search_engine), &self.board, limits)
+    }
+
+    /// Start searching the current position on another thread and return
+    /// at once. Searches started while one runs wait for it to end; each
+    /// has its own stop flag, so stopping one still waiting cancels it.
+    pub fn search_async(&self, limits: SearchLimits) -> SearchHandle {
+        let (sender, events) = mpsc::channel();
+        let board = self.board.clone();
+        let search_engine = Arc::clone(&self.search_engine);
+        let stop = Arc::new(AtomicBool::new(false));
+        let async_stops = Arc::clone(&self.async_stops);
+        async_stops.lock().unwrap_or_else(PoisonError::into_inner).push(Arc::clone(&stop));
+
+        let search_stop = Arc::clone(&stop);
+        let thread = thread::spawn(move || {
+            let mut engine = lock_engine(&search_engine);
+            engine.events = Some(sender.clone());
+            engine.search_stop = Some(Arc::clone(&search_stop));
+            let result = run_search(&mut engine, &board, limits);
+            engine.events = None;
+            async_stops.lock().unwrap_or_else(PoisonError::into_inner)
+                .retain(|flag| !Arc::ptr_eq(flag, &search_stop));
+            let _ = sender.send(SearchEvent::Finished(result.clone()));
+            result
+        });
+
+        SearchHandle { events, stop: StopHandle { flag: stop, async_stops: None }, thread }
+    }
+
+    /// Stop the running search and the asynchronous ones still waiting
+    pub fn stop(&self) {
+        self.stop_handle().stop();
+    }
+
+    /// Handle stopping this engine's searches from another thread, while
+    /// `search` holds the engine
+    pub fn stop_handle(&self) -> StopHandle {
+        StopHandle { flag: Arc::clone(&self.stop_search), async_stops: Some(Arc::clone(&self.async_stops)) }
+    }
+}
+
+/// Network in `path`
+fn load_network(path: &str) -> Result<Arc<Network>, EngineError> {
+    Network::load(path)
+        .map(Arc::new)
+        .map_err(|e| EngineError::EvalFile { path: path.to_string(), reason: e.to_string() })
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Engine::new()
+    }
+}
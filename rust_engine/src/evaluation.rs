@@ -7,9 +7,21 @@
 //! - King safety
 //! - Piece mobility
 //! - Bishop pair bonus
+//!
+//! Known drawish endgames (opposite-colored bishops, the wrong bishop with
+//! a rook pawn, pawnless endings a minor piece up, ...) scale the score of
+//! the side ahead toward a draw.
+//!
+//! Every term is a middlegame/endgame pair, and the total is interpolated
+//! between the two by the game phase (see `Board::phase`), so values change
+//! smoothly as pieces come off instead of switching at a material cutoff.
+//!
+//! It also provides the move scores used by the search: MVV-LVA ordering of
+//! captures and static exchange evaluation.
 
 use crate::types::*;
-use crate::board::Board;
+use crate::bitboard::*;
+use crate::board::{Board, Move, MAX_PHASE};
 
 // ============================================================================
 // PIECE VALUES
@@ -25,6 +37,11 @@ pub const PIECE_VALUES: [i32; 7] = [
     20000,  // KING
 ];
 
+/// Material in the middlegame and the endgame, by piece type, for the
+/// evaluation (PIECE_VALUES serves move ordering and exchanges)
+const MG_MATERIAL: [i32; 6] = [0, 100, 320, 330, 500, 900];
+const EG_MATERIAL: [i32; 6] = [0, 120, 300, 320, 530, 950];
+
 // ============================================================================
 // PIECE-SQUARE TABLES
 // ============================================================================
@@ -41,6 +58,18 @@ const PAWN_PST: [i32; 64] = [
     0,   0,   0,   0,   0,   0,   0,   0,   // Rank 8
 ];
 
+// Pawn endgame PST - advanced pawns are worth more, the center less
+const PAWN_ENDGAME_PST: [i32; 64] = [
+    0,   0,   0,   0,   0,   0,   0,   0,   // Rank 1
+    0,   0,   0,   0,   0,   0,   0,   0,   // Rank 2
+    5,   5,   5,   5,   5,   5,   5,   5,   // Rank 3
+   10,  10,  10,  10,  10,  10,  10,  10,   // Rank 4
+   20,  20,  20,  20,  20,  20,  20,  20,   // Rank 5
+   35,  35,  35,  35,  35,  35,  35,  35,   // Rank 6
+   60,  60,  60,  60,  60,  60,  60,  60,   // Rank 7
+    0,   0,   0,   0,   0,   0,   0,   0,   // Rank 8
+];
+
 // Knight PST - encourages central positioning
 const KNIGHT_PST: [i32; 64] = [
    -50, -40, -30, -30, -30, -30, -40, -50,
@@ -114,107 +143,239 @@ const KING_ENDGAME_PST: [i32; 64] = [
 ];
 
 // ============================================================================
-// EVALUATION BONUSES/PENALTIES
+// PACKED MATERIAL + PST
 // ============================================================================
 
-const DOUBLED_PAWN_PENALTY: i32 = -15;
-const ISOLATED_PAWN_PENALTY: i32 = -20;
-const PASSED_PAWN_BONUS: [i32; 8] = [0, 10, 20, 35, 60, 100, 150, 0];
-const PAWN_CHAIN_BONUS: i32 = 5;
+/// Middlegame and endgame values packed into one integer as `eg << 16 + mg`,
+/// so both phases are summed with a single addition
+pub type Score = i32;
 
-const BISHOP_PAIR_BONUS: i32 = 50;
-const ROOK_ON_OPEN_FILE_BONUS: i32 = 25;
-const ROOK_ON_SEMI_OPEN_FILE_BONUS: i32 = 15;
-const ROOK_ON_7TH_RANK_BONUS: i32 = 30;
-
-const KNIGHT_MOBILITY_BONUS: i32 = 4;
-const BISHOP_MOBILITY_BONUS: i32 = 5;
-const ROOK_MOBILITY_BONUS: i32 = 3;
-const QUEEN_MOBILITY_BONUS: i32 = 2;
-
-const CENTER_SQUARES: [usize; 4] = [27, 28, 35, 36];
-const CENTER_PAWN_BONUS: i32 = 15;
-
-// ============================================================================
-// HELPER FUNCTIONS
-// ============================================================================
+pub const fn make_score(mg: i32, eg: i32) -> Score {
+    (eg << 16) + mg
+}
 
-/// Get piece-square table value for a piece
-fn get_pst_value(piece_type: u8, sq: usize, is_white: bool, is_endgame: bool) -> i32 {
-    let pst = match piece_type {
-        PAWN => &PAWN_PST,
-        KNIGHT => &KNIGHT_PST,
-        BISHOP => &BISHOP_PST,
-        ROOK => &ROOK_PST,
-        QUEEN => &QUEEN_PST,
-        KING => if is_endgame { &KING_ENDGAME_PST } else { &KING_MIDDLEGAME_PST },
-        _ => return 0,
-    };
+#[inline]
+pub fn mg_value(score: Score) -> i32 {
+    score as i16 as i32
+}
 
-    let index = if is_white {
-        sq
-    } else {
-        let rank = sq / 8;
-        let file = sq % 8;
-        (7 - rank) * 8 + file
-    };
+#[inline]
+pub fn eg_value(score: Score) -> i32 {
+    (score + 0x8000) >> 16
+}
 
-    pst[index]
+/// Interpolate a packed score by `phase` (MAX_PHASE = middlegame, 0 = bare
+/// kings and pawns)
+#[inline]
+pub fn taper(score: Score, phase: i32) -> i32 {
+    let phase = phase.clamp(0, MAX_PHASE);
+    (mg_value(score) * phase + eg_value(score) * (MAX_PHASE - phase)) / MAX_PHASE
 }
 
-/// Count material for both sides (excluding kings)
-fn count_material(board: &Board) -> (i32, i32) {
-    let mut white_material = 0;
-    let mut black_material = 0;
+/// Material plus piece-square value of every piece on every square from
+/// White's point of view (black entries are negative), indexed by the piece
+/// code. Kings carry no material: both sides always have exactly one.
+/// Pawns and kings have their own endgame tables.
+static PSQT: [[Score; 64]; 32] = build_psqt();
+
+const fn build_psqt() -> [[Score; 64]; 32] {
+    let mut table = [[0; 64]; 32];
+    let mut piece_type = PAWN;
+    while piece_type <= KING {
+        let (mg_pst, eg_pst) = match piece_type {
+            PAWN => (&PAWN_PST, &PAWN_ENDGAME_PST),
+            KNIGHT => (&KNIGHT_PST, &KNIGHT_PST),
+            BISHOP => (&BISHOP_PST, &BISHOP_PST),
+            ROOK => (&ROOK_PST, &ROOK_PST),
+            QUEEN => (&QUEEN_PST, &QUEEN_PST),
+            _ => (&KING_MIDDLEGAME_PST, &KING_ENDGAME_PST),
+        };
+        let (mg_material, eg_material) = if piece_type == KING {
+            (0, 0)
+        } else {
+            (MG_MATERIAL[piece_type as usize], EG_MATERIAL[piece_type as usize])
+        };
 
-    for sq in 0..64 {
-        let piece = board.squares[sq];
-        if piece == EMPTY {
-            continue;
+        let mut sq = 0;
+        while sq < 64 {
+            let mirrored = (7 - sq / 8) * 8 + sq % 8;
+            table[(WHITE | piece_type) as usize][sq] =
+                make_score(mg_material + mg_pst[sq], eg_material + eg_pst[sq]);
+            table[(BLACK | piece_type) as usize][sq] =
+                -make_score(mg_material + mg_pst[mirrored], eg_material + eg_pst[mirrored]);
+            sq += 1;
         }
+        piece_type += 1;
+    }
+    table
+}
 
-        let piece_type = get_piece_type(piece);
-        if piece_type == KING {
-            continue;
-        }
+/// Packed material + PST contribution of `piece` standing on `sq`
+#[inline]
+pub fn psqt_value(piece: u8, sq: usize) -> Score {
+    PSQT[piece as usize][sq]
+}
 
-        let value = PIECE_VALUES[piece_type as usize];
+// ============================================================================
+// EVALUATION BONUSES/PENALTIES
+// ============================================================================
 
-        if get_piece_color(piece) == WHITE {
-            white_material += value;
-        } else {
-            black_material += value;
-        }
+// Bonuses and penalties as (middlegame, endgame) pairs
+const DOUBLED_PAWN_PENALTY: Score = make_score(-10, -20);
+const ISOLATED_PAWN_PENALTY: Score = make_score(-20, -20);
+const PASSED_PAWN_BONUS: [Score; 8] = [
+    make_score(0, 0), make_score(5, 15), make_score(10, 30), make_score(20, 50),
+    make_score(40, 80), make_score(70, 130), make_score(110, 190), make_score(0, 0),
+];
+/// Passed pawn refinements, scaled by PASSER_WEIGHT of the relative rank:
+/// enemy piece on the stop square, path to promotion clear, stop square
+/// safe to push to, and another passer on an adjacent file next to it
+const PASSER_WEIGHT: [i32; 8] = [0, 0, 0, 1, 3, 5, 8, 0];
+const BLOCKADED_PASSER_PENALTY: Score = make_score(-4, -10);
+const FREE_PASSER_BONUS: Score = make_score(2, 6);
+const SAFE_PUSH_BONUS: Score = make_score(2, 5);
+const CONNECTED_PASSER_BONUS: Score = make_score(3, 8);
+/// Endgame bonus per weight and square of distance (capped) between the stop
+/// square and the enemy king, and penalty for the own king
+const PASSER_ENEMY_KING_DISTANCE: i32 = 5;
+const PASSER_OWN_KING_DISTANCE: i32 = 2;
+const MAX_PASSER_KING_DISTANCE: i32 = 5;
+const PAWN_CHAIN_BONUS: Score = make_score(5, 5);
+/// Passed pawn the enemy king cannot catch in a pawn ending - worth nearly
+/// a queen, less a little for every move it still needs
+const UNSTOPPABLE_PASSER_BONUS: Score = make_score(800, 800);
+const UNSTOPPABLE_PASSER_STEP: Score = make_score(20, 20);
+
+/// Rook on the file of a passed pawn: behind it, own or enemy, the rook
+/// supports or holds it from behind; in front of its own passer it is
+/// tied to passive blocking
+const ROOK_BEHIND_PASSER_BONUS: Score = make_score(10, 25);
+const ROOK_IN_FRONT_OF_PASSER_PENALTY: Score = make_score(-5, -20);
+const BISHOP_PAIR_BONUS: Score = make_score(40, 60);
+const ROOK_ON_OPEN_FILE_BONUS: Score = make_score(30, 15);
+const ROOK_ON_SEMI_OPEN_FILE_BONUS: Score = make_score(15, 10);
+const ROOK_ON_7TH_RANK_BONUS: Score = make_score(20, 40);
+const QUEEN_ON_7TH_RANK_BONUS: Score = make_score(10, 20);
+const DOUBLED_ROOKS_ON_7TH_BONUS: Score = make_score(25, 25);
+const QUEEN_AND_ROOK_ON_7TH_BONUS: Score = make_score(20, 20);
+/// Pawn storms with kings on opposite wings, indexed by the relative rank
+/// of the pawn: bonus for pawns advancing on the enemy king's wing and
+/// penalty for pawns advanced in front of the own king (middlegame only)
+const PAWN_STORM_BONUS: [Score; 8] = [
+    0, 0, 0, make_score(5, 0), make_score(12, 0), make_score(20, 0), make_score(25, 0), 0,
+];
+const OWN_SHIELD_ADVANCE_PENALTY: [Score; 8] = [
+    0, 0, 0, make_score(-5, 0), make_score(-10, 0), make_score(-15, 0), make_score(-15, 0), 0,
+];
+const KINGSIDE_FILES: u64 = FILE_F | FILE_G | FILE_H;
+const QUEENSIDE_FILES: u64 = FILE_A | FILE_B | FILE_C;
+
+/// Pawn shield in front of a king on its first two ranks, per file of the
+/// three around the king: pawn one or two squares ahead, or no pawn at all
+const SHIELD_PAWN_BONUS: Score = make_score(15, 0);
+const SHIELD_PAWN_ADVANCED_BONUS: Score = make_score(8, 0);
+const MISSING_SHIELD_PAWN_PENALTY: Score = make_score(-12, 0);
+/// Files next to the king without own pawns, and without any pawns
+const SEMI_OPEN_KING_FILE_PENALTY: Score = make_score(-12, 0);
+const OPEN_KING_FILE_PENALTY: Score = make_score(-20, 0);
+
+/// Attack units per king-zone square hit, by attacker type
+const KING_ATTACK_WEIGHTS: [i32; 7] = [0, 0, 2, 2, 3, 5, 0];
+/// Penalty for the attack units on a king zone, growing quadratically
+/// until it levels off; a lone attacker other than the queen is ignored
+const KING_DANGER: [i32; 100] = build_king_danger();
+const MAX_KING_DANGER: i32 = 500;
+
+const fn build_king_danger() -> [i32; 100] {
+    let mut table = [0; 100];
+    let mut units = 0;
+    while units < 100 {
+        let danger = (units * units) as i32 / 6;
+        table[units] = if danger < MAX_KING_DANGER { danger } else { MAX_KING_DANGER };
+        units += 1;
     }
-
-    (white_material, black_material)
+    table
 }
 
-/// Determine if the position is an endgame
-fn is_endgame(board: &Board) -> bool {
-    let (white_material, black_material) = count_material(board);
-    white_material <= 1300 && black_material <= 1300
-}
+/// Two heavy pieces defending each other on an open or semi-open file
+const FILE_BATTERY_BONUS: Score = make_score(15, 10);
+/// Bishop and queen lined up on a diagonal that reaches the enemy king zone
+const DIAGONAL_BATTERY_BONUS: Score = make_score(20, 0);
+/// Slider pinning or skewering through one blocker to the enemy king or queen
+const XRAY_BONUS: Score = make_score(8, 8);
+
+/// Per hole of a color complex next to a king that lost its bishop of that
+/// color, when the opponent still has such a bishop; smaller when only the
+/// queen can use the holes
+const COLOR_COMPLEX_BISHOP_BONUS: Score = make_score(8, 0);
+const COLOR_COMPLEX_QUEEN_BONUS: Score = make_score(4, 0);
+const MAX_COLOR_COMPLEX_HOLES: i32 = 4;
+
+/// Rook boxed in on the back rank by its own king, smaller while the king
+/// can still castle
+const TRAPPED_ROOK_PENALTY: Score = make_score(-50, -20);
+const TRAPPED_ROOK_CASTLING_PENALTY: Score = make_score(-25, -10);
+/// Most moves a rook may have and still count as trapped
+const TRAPPED_ROOK_MAX_MOBILITY: u32 = 3;
+
+const KNIGHT_MOBILITY_BONUS: Score = make_score(4, 4);
+const BISHOP_MOBILITY_BONUS: Score = make_score(5, 5);
+const ROOK_MOBILITY_BONUS: Score = make_score(2, 4);
+const QUEEN_MOBILITY_BONUS: Score = make_score(2, 2);
+
+/// Development terms only apply up to this move number
+const OPENING_MOVES: u16 = 12;
+const UNDEVELOPED_MINOR_PENALTY: Score = make_score(-10, 0);
+/// Per move spent moving an already developed piece again
+const WASTED_TEMPO_PENALTY: Score = make_score(-8, 0);
+const MAX_WASTED_TEMPI: i32 = 3;
+/// Per undeveloped minor while the queen is already out
+const EARLY_QUEEN_PENALTY: Score = make_score(-8, 0);
+
+/// Home squares of the minor pieces and queen (white; black is mirrored)
+const MINOR_HOME_SQUARES: [(usize, u8); 4] = [(1, KNIGHT), (6, KNIGHT), (2, BISHOP), (5, BISHOP)];
+const QUEEN_HOME_SQUARE: usize = 3;
+/// Piece types of the initial position on the first two ranks (white)
+const HOME_PIECE_TYPES: [u8; 16] = [
+    ROOK, KNIGHT, BISHOP, QUEEN, KING, BISHOP, KNIGHT, ROOK,
+    PAWN, PAWN, PAWN, PAWN, PAWN, PAWN, PAWN, PAWN,
+];
+
+const CENTER_SQUARES: [usize; 4] = [27, 28, 35, 36];
+const CENTER_PAWN_BONUS: Score = make_score(15, 5);
+
+/// Endgame scale factors, out of SCALE_NORMAL, applied to the score of the
+/// side that is ahead in known drawish material configurations
+const SCALE_NORMAL: i32 = 64;
+/// Wrong-colored bishop with rook pawns, defending king in the corner
+const SCALE_DEAD_DRAW: i32 = 0;
+/// No pawns and at most a minor piece up
+const SCALE_NO_PAWNS: i32 = 8;
+/// Opposite-colored bishops without other pieces, and with other pieces
+const SCALE_OPPOSITE_BISHOPS: i32 = 16;
+const SCALE_OPPOSITE_BISHOPS_WITH_PIECES: i32 = 46;
+/// Rook and pawn against rook with the defending king in front of the pawn
+const SCALE_ROOK_PAWN_BLOCKED: i32 = 16;
+
+// ============================================================================
+// HELPER FUNCTIONS
+// ============================================================================
 
 /// Get pawn positions for each color
 fn get_pawn_positions(board: &Board) -> (Vec<usize>, Vec<usize>) {
-    let mut white_pawns = Vec::new();
-    let mut black_pawns = Vec::new();
-
-    for sq in 0..64 {
-        let piece = board.squares[sq];
-        if piece == WHITE_PAWN {
-            white_pawns.push(sq);
-        } else if piece == BLACK_PAWN {
-            black_pawns.push(sq);
+    let squares = |mut pawns: u64| {
+        let mut list = Vec::with_capacity(popcount(pawns) as usize);
+        while pawns != 0 {
+            list.push(pop_lsb(&mut pawns));
         }
-    }
+        list
+    };
 
-    (white_pawns, black_pawns)
+    (squares(board.pieces(WHITE, PAWN)), squares(board.pieces(BLACK, PAWN)))
 }
 
 /// Evaluate pawn structure
-fn evaluate_pawn_structure(board: &Board, white_pawns: &[usize], black_pawns: &[usize]) -> i32 {
+fn evaluate_pawn_structure(board: &Board, white_pawns: &[usize], black_pawns: &[usize]) -> Score {
     let mut score = 0;
 
     // Count pawns per file for each side
@@ -231,7 +392,6 @@ fn evaluate_pawn_structure(board: &Board, white_pawns: &[usize], black_pawns: &[
     // Evaluate white pawns
     for &sq in white_pawns {
         let file = sq % 8;
-        let rank = sq / 8;
 
         // Doubled pawns
         if white_files[file] > 1 {
@@ -245,22 +405,6 @@ fn evaluate_pawn_structure(board: &Board, white_pawns: &[usize], black_pawns: &[
             score += ISOLATED_PAWN_PENALTY;
         }
 
-        // Passed pawns
-        let mut is_passed = true;
-        for check_file in file.saturating_sub(1)..=(file + 1).min(7) {
-            for check_rank in (rank + 1)..8 {
-                let check_sq = check_rank * 8 + check_file;
-                if board.squares[check_sq] == BLACK_PAWN {
-                    is_passed = false;
-                    break;
-                }
-            }
-            if !is_passed { break; }
-        }
-        if is_passed {
-            score += PASSED_PAWN_BONUS[rank];
-        }
-
         // Pawn chain
         if sq >= 9 {
             let defender1 = sq - 9;
@@ -275,7 +419,6 @@ fn evaluate_pawn_structure(board: &Board, white_pawns: &[usize], black_pawns: &[
     // Evaluate black pawns (mirror the logic)
     for &sq in black_pawns {
         let file = sq % 8;
-        let rank = sq / 8;
 
         // Doubled pawns
         if black_files[file] > 1 {
@@ -289,22 +432,6 @@ fn evaluate_pawn_structure(board: &Board, white_pawns: &[usize], black_pawns: &[
             score -= ISOLATED_PAWN_PENALTY;
         }
 
-        // Passed pawns
-        let mut is_passed = true;
-        for check_file in file.saturating_sub(1)..=(file + 1).min(7) {
-            for check_rank in 0..rank {
-                let check_sq = check_rank * 8 + check_file;
-                if board.squares[check_sq] == WHITE_PAWN {
-                    is_passed = false;
-                    break;
-                }
-            }
-            if !is_passed { break; }
-        }
-        if is_passed {
-            score -= PASSED_PAWN_BONUS[7 - rank];
-        }
-
         // Pawn chain
         if sq <= 54 {
             let defender1 = sq + 9;
@@ -319,181 +446,566 @@ fn evaluate_pawn_structure(board: &Board, white_pawns: &[usize], black_pawns: &[
     score
 }
 
-/// Evaluate piece activity
-fn evaluate_pieces(board: &Board, white_pawns: &[usize], black_pawns: &[usize]) -> i32 {
-    let mut score = 0;
-    let mut white_bishops = 0;
-    let mut black_bishops = 0;
+fn square_distance(a: usize, b: usize) -> i32 {
+    ((a % 8).abs_diff(b % 8)).max((a / 8).abs_diff(b / 8)) as i32
+}
 
-    let white_pawn_files: Vec<usize> = white_pawns.iter().map(|&sq| sq % 8).collect();
-    let black_pawn_files: Vec<usize> = black_pawns.iter().map(|&sq| sq % 8).collect();
+/// Passed pawns, from White's point of view: the rank bonus, refined by
+/// blockades, a clear and safe path, king proximity in the endgame and
+/// connected passers (rooks behind passers are scored with the pieces)
+fn evaluate_passed_pawns(board: &Board) -> Score {
+    let occupied = board.bb_white | board.bb_black;
 
-    for sq in 0..64 {
-        let piece = board.squares[sq];
-        if piece == EMPTY {
-            continue;
-        }
+    let side_score = |white: bool| {
+        let (own, enemy) = if white { (board.bb_white, board.bb_black) } else { (board.bb_black, board.bb_white) };
+        let (Some(own_king), Some(enemy_king)) = (board.find_king(white), board.find_king(!white)) else {
+            return 0;
+        };
+        let passed = passed_pawns(board, white);
 
-        let piece_type = get_piece_type(piece);
-        let is_white = get_piece_color(piece) == WHITE;
-        let file = sq % 8;
-        let rank = sq / 8;
+        let mut score = 0;
+        for sq in Squares(passed) {
+            let rank = if white { rank_of(sq) } else { 7 - rank_of(sq) };
+            score += PASSED_PAWN_BONUS[rank];
 
-        if piece_type == BISHOP {
-            if is_white { white_bishops += 1; } else { black_bishops += 1; }
-        } else if piece_type == ROOK {
-            if is_white {
-                // Rook on open file
-                if !white_pawn_files.contains(&file) && !black_pawn_files.contains(&file) {
-                    score += ROOK_ON_OPEN_FILE_BONUS;
-                } else if !white_pawn_files.contains(&file) {
-                    score += ROOK_ON_SEMI_OPEN_FILE_BONUS;
-                }
-                // Rook on 7th rank
-                if rank == 6 {
-                    score += ROOK_ON_7TH_RANK_BONUS;
-                }
+            let weight = PASSER_WEIGHT[rank];
+            if weight == 0 {
+                continue;
+            }
+            let stop = if white { sq + 8 } else { sq - 8 };
+            let path = forward_ranks(sq, white) & file_bb(file_of(sq));
+
+            if square_bb(stop) & enemy != 0 {
+                score += weight * BLOCKADED_PASSER_PENALTY;
             } else {
-                if !white_pawn_files.contains(&file) && !black_pawn_files.contains(&file) {
-                    score -= ROOK_ON_OPEN_FILE_BONUS;
-                } else if !black_pawn_files.contains(&file) {
-                    score -= ROOK_ON_SEMI_OPEN_FILE_BONUS;
+                if path & occupied == 0 {
+                    score += weight * FREE_PASSER_BONUS;
                 }
-                if rank == 1 {
-                    score -= ROOK_ON_7TH_RANK_BONUS;
+                let attackers = attackers_to(stop, occupied, board.bb_white, board.bb_black, board.bb_pawns,
+                                             board.bb_knights, board.bb_bishops, board.bb_rooks,
+                                             board.bb_queens, board.bb_kings);
+                if square_bb(stop) & own == 0 && attackers & enemy == 0 {
+                    score += weight * SAFE_PUSH_BONUS;
                 }
             }
+
+            let enemy_distance = square_distance(enemy_king, stop).min(MAX_PASSER_KING_DISTANCE);
+            let own_distance = square_distance(own_king, stop).min(MAX_PASSER_KING_DISTANCE);
+            score += make_score(0, weight * (enemy_distance * PASSER_ENEMY_KING_DISTANCE
+                                             - own_distance * PASSER_OWN_KING_DISTANCE));
+
+            let neighbors = adjacent_files(sq) & !file_bb(file_of(sq));
+            let near_ranks = rank_bb(rank_of(sq)) | shift_north(rank_bb(rank_of(sq))) | shift_south(rank_bb(rank_of(sq)));
+            if passed & neighbors & near_ranks != 0 {
+                score += weight * CONNECTED_PASSER_BONUS;
+            }
         }
+        score
+    };
+
+    side_score(true) - side_score(false)
+}
+
+/// Moves the fastest unstoppable passer of one side needs to promote, or None.
+/// Only applies when the defender has nothing but king and pawns. A passer is
+/// unstoppable when its path is empty and the defending king is outside the
+/// square of the pawn (rule of the square), counting the move of the side
+/// to move.
+fn unstoppable_passer(board: &Board, pawns: &[usize], white: bool) -> Option<i32> {
+    let (defender, enemy_pawn) = if white { (1, BLACK_PAWN) } else { (0, WHITE_PAWN) };
+    let enemy_pawn_count = (board.bb_pawns & if white { board.bb_black } else { board.bb_white }).count_ones() as i32;
+    if board.material[defender] != enemy_pawn_count * PIECE_VALUES[PAWN as usize] {
+        return None;
     }
+    let enemy_king = board.find_king(!white)?;
+    let defender_to_move = board.white_to_move != white;
 
-    // Bishop pair
-    if white_bishops >= 2 { score += BISHOP_PAIR_BONUS; }
-    if black_bishops >= 2 { score -= BISHOP_PAIR_BONUS; }
+    pawns.iter().filter_map(|&sq| {
+        let file = sq % 8;
+        let rank = sq / 8;
+        let (promotion_sq, path): (usize, Vec<usize>) = if white {
+            (56 + file, ((rank + 1)..8).map(|r| r * 8 + file).collect())
+        } else {
+            (file, (0..rank).map(|r| r * 8 + file).collect())
+        };
 
-    score
+        // Blocked pawns and pawns that can still be stopped by an enemy pawn
+        if path.iter().any(|&p| board.squares[p] != EMPTY) {
+            return None;
+        }
+        let passed = (file.saturating_sub(1)..=(file + 1).min(7)).all(|f| {
+            path.iter().all(|&p| board.squares[p / 8 * 8 + f] != enemy_pawn)
+        });
+        if !passed {
+            return None;
+        }
+
+        // A pawn on its starting rank gets a double step
+        let start_rank = if white { 1 } else { 6 };
+        let pawn_moves = path.len() as i32 - i32::from(rank == start_rank);
+        let king_moves = square_distance(enemy_king, promotion_sq) - i32::from(defender_to_move);
+        (king_moves > pawn_moves).then_some(pawn_moves)
+    }).min()
 }
 
-/// Count mobility for a piece (simplified)
-fn count_mobility(board: &Board, sq: usize, piece_type: u8, is_white: bool) -> i32 {
-    let mut moves = 0i32;
-    let file = sq % 8;
-    let color = if is_white { WHITE } else { BLACK };
-
-    const KNIGHT_OFFSETS: [i32; 8] = [17, 15, 10, 6, -6, -10, -15, -17];
-    const BISHOP_DIRS: [i32; 4] = [7, 9, -7, -9];
-    const ROOK_DIRS: [i32; 4] = [8, -8, 1, -1];
-    const QUEEN_DIRS: [i32; 8] = [8, -8, 1, -1, 7, 9, -7, -9];
-
-    match piece_type {
-        KNIGHT => {
-            for &offset in &KNIGHT_OFFSETS {
-                let to_sq_i32 = sq as i32 + offset;
-                if to_sq_i32 < 0 || to_sq_i32 >= 64 { continue; }
-                let to_sq = to_sq_i32 as usize;
-                if (to_sq % 8).abs_diff(file) > 2 { continue; }
-                let target = board.squares[to_sq];
-                if target == EMPTY || get_piece_color(target) != color {
-                    moves += 1;
-                }
-            }
+/// Score unstoppable passers in pawn endings. When both sides have one, only
+/// the side that promotes first (counting the move) is rewarded.
+fn evaluate_unstoppable_passers(board: &Board, white_pawns: &[usize], black_pawns: &[usize]) -> Score {
+    let bonus = |moves: i32| UNSTOPPABLE_PASSER_BONUS - UNSTOPPABLE_PASSER_STEP * moves;
+    let white = unstoppable_passer(board, white_pawns, true);
+    let black = unstoppable_passer(board, black_pawns, false);
+
+    match (white, black) {
+        (Some(w), None) => bonus(w),
+        (None, Some(b)) => -bonus(b),
+        (Some(w), Some(b)) => {
+            // Whoever is to move wins a tie
+            let white_first = if board.white_to_move { w <= b } else { w < b };
+            if white_first { bonus(w) } else { -bonus(b) }
+        }
+        (None, None) => 0,
+    }
+}
+
+/// Squares on the ranks in front of `sq` from the point of view of `white`
+fn forward_ranks(sq: usize, white: bool) -> u64 {
+    let rank = rank_of(sq);
+    if white {
+        if rank == 7 { 0 } else { !0u64 << (8 * (rank + 1)) }
+    } else {
+        (1u64 << (8 * rank)) - 1
+    }
+}
+
+/// The file of `sq` and the files next to it
+fn adjacent_files(sq: usize) -> u64 {
+    let file = file_bb(file_of(sq));
+    file | shift_east(file) | shift_west(file)
+}
+
+/// Passed pawns of one side: no enemy pawn in front on the same or an
+/// adjacent file
+fn passed_pawns(board: &Board, white: bool) -> u64 {
+    let (own, enemy) = if white { (board.bb_white, board.bb_black) } else { (board.bb_black, board.bb_white) };
+    let enemy_pawns = board.bb_pawns & enemy;
+
+    let mut pawns = board.bb_pawns & own;
+    let mut passed = 0;
+    while pawns != 0 {
+        let sq = pop_lsb(&mut pawns);
+        if enemy_pawns & forward_ranks(sq, white) & adjacent_files(sq) == 0 {
+            passed |= square_bb(sq);
         }
-        BISHOP => {
-            for &d in &BISHOP_DIRS {
-                let mut current = sq;
-                loop {
-                    let curr_file = current % 8;
-                    let next_sq_i32 = current as i32 + d;
-                    if next_sq_i32 < 0 || next_sq_i32 >= 64 { break; }
-                    let next_sq = next_sq_i32 as usize;
-                    if (next_sq % 8).abs_diff(curr_file) != 1 { break; }
-                    let target = board.squares[next_sq];
-                    if target == EMPTY {
-                        moves += 1;
-                        current = next_sq;
-                    } else {
-                        if get_piece_color(target) != color { moves += 1; }
-                        break;
+    }
+    passed
+}
+
+/// Squares attacked by the pawns of one side
+fn pawn_attacks(board: &Board, white: bool) -> u64 {
+    let mut pawns = board.bb_pawns & if white { board.bb_white } else { board.bb_black };
+    let mut attacks = 0;
+    while pawns != 0 {
+        attacks |= PAWN_ATTACKS[usize::from(!white)][pop_lsb(&mut pawns)];
+    }
+    attacks
+}
+
+/// Wing (files) a king on `sq` sits on, or None for the center files
+fn king_wing(sq: usize) -> Option<u64> {
+    match file_of(sq) {
+        0..=2 => Some(QUEENSIDE_FILES),
+        5..=7 => Some(KINGSIDE_FILES),
+        _ => None,
+    }
+}
+
+/// Pawn storms, from White's point of view. Only evaluated when the kings
+/// are on opposite wings: there every pawn marching on the enemy king is an
+/// attacker, and every pawn pushed in front of the own king a weakness.
+fn evaluate_pawn_storms(board: &Board) -> Score {
+    let (Some(white_king), Some(black_king)) = (board.find_king(true), board.find_king(false)) else {
+        return 0;
+    };
+    let (Some(white_wing), Some(black_wing)) = (king_wing(white_king), king_wing(black_king)) else {
+        return 0;
+    };
+    if white_wing == black_wing {
+        return 0;
+    }
+
+    let side_score = |white: bool| {
+        let (pawns, own_wing, enemy_wing) = if white {
+            (board.bb_pawns & board.bb_white, white_wing, black_wing)
+        } else {
+            (board.bb_pawns & board.bb_black, black_wing, white_wing)
+        };
+        let relative_rank = |sq: usize| if white { rank_of(sq) } else { 7 - rank_of(sq) };
+
+        let mut score = 0;
+        let mut storm = pawns & enemy_wing;
+        while storm != 0 {
+            score += PAWN_STORM_BONUS[relative_rank(pop_lsb(&mut storm))];
+        }
+        let mut shield = pawns & own_wing;
+        while shield != 0 {
+            score += OWN_SHIELD_ADVANCE_PENALTY[relative_rank(pop_lsb(&mut shield))];
+        }
+        score
+    };
+
+    side_score(true) - side_score(false)
+}
+
+/// King safety, from White's point of view: the pawn shield of a king on
+/// its first two ranks, open and semi-open files around the king, and the
+/// enemy pieces attacking the squares around it
+fn evaluate_king_safety(board: &Board) -> Score {
+    let occupied = board.bb_white | board.bb_black;
+
+    // Safety of `white`'s king
+    let side_score = |white: bool| {
+        let (own, enemy) = if white { (board.bb_white, board.bb_black) } else { (board.bb_black, board.bb_white) };
+        let king = board.bb_kings & own;
+        if king == 0 {
+            return 0;
+        }
+        let king_sq = lsb(king);
+        let own_pawns = board.bb_pawns & own;
+        let king_rank = if white { rank_of(king_sq) } else { 7 - rank_of(king_sq) };
+        let relative_rank = |sq: usize| if white { rank_of(sq) } else { 7 - rank_of(sq) };
+
+        let mut score = 0;
+        let king_file = file_of(king_sq);
+        for file in king_file.saturating_sub(1)..=(king_file + 1).min(7) {
+            let file_mask = file_bb(file);
+
+            if king_rank <= 1 {
+                let mut shield = own_pawns & file_mask;
+                let mut nearest = 8;
+                while shield != 0 {
+                    let rank = relative_rank(pop_lsb(&mut shield));
+                    if rank > king_rank {
+                        nearest = nearest.min(rank - king_rank);
                     }
                 }
+                score += match nearest {
+                    1 => SHIELD_PAWN_BONUS,
+                    2 => SHIELD_PAWN_ADVANCED_BONUS,
+                    _ => MISSING_SHIELD_PAWN_PENALTY,
+                };
+            }
+
+            if own_pawns & file_mask == 0 {
+                score += if board.bb_pawns & file_mask == 0 {
+                    OPEN_KING_FILE_PENALTY
+                } else {
+                    SEMI_OPEN_KING_FILE_PENALTY
+                };
             }
         }
-        ROOK => {
-            for &d in &ROOK_DIRS {
-                let mut current = sq;
-                loop {
-                    let curr_file = current % 8;
-                    let next_sq_i32 = current as i32 + d;
-                    if next_sq_i32 < 0 || next_sq_i32 >= 64 { break; }
-                    let next_sq = next_sq_i32 as usize;
-                    if (d == 1 || d == -1) && (next_sq % 8).abs_diff(curr_file) != 1 { break; }
-                    let target = board.squares[next_sq];
-                    if target == EMPTY {
-                        moves += 1;
-                        current = next_sq;
-                    } else {
-                        if get_piece_color(target) != color { moves += 1; }
-                        break;
-                    }
-                }
+
+        // Enemy pieces hitting the king zone
+        let king_zone = king | KING_ATTACKS[king_sq];
+        let mut attackers = 0;
+        let mut units = 0;
+        let mut queen_attacks_zone = false;
+        for sq in Squares((board.bb_knights | board.bb_bishops | board.bb_rooks | board.bb_queens) & enemy) {
+            let piece_type = get_piece_type(board.squares[sq]);
+            let attacks = match piece_type {
+                KNIGHT => KNIGHT_ATTACKS[sq],
+                BISHOP => bishop_attacks(sq, occupied),
+                ROOK => rook_attacks(sq, occupied),
+                _ => queen_attacks(sq, occupied),
+            };
+            let hits = popcount(attacks & king_zone) as i32;
+            if hits > 0 {
+                attackers += 1;
+                units += hits * KING_ATTACK_WEIGHTS[piece_type as usize];
+                queen_attacks_zone |= piece_type == QUEEN;
             }
         }
-        QUEEN => {
-            for &d in &QUEEN_DIRS {
-                let mut current = sq;
-                loop {
-                    let curr_file = current % 8;
-                    let next_sq_i32 = current as i32 + d;
-                    if next_sq_i32 < 0 || next_sq_i32 >= 64 { break; }
-                    let next_sq = next_sq_i32 as usize;
-                    if (d == 1 || d == -1 || d == 7 || d == -9 || d == 9 || d == -7) 
-                       && (next_sq % 8).abs_diff(curr_file) != 1 { break; }
-                    let target = board.squares[next_sq];
-                    if target == EMPTY {
-                        moves += 1;
-                        current = next_sq;
-                    } else {
-                        if get_piece_color(target) != color { moves += 1; }
-                        break;
-                    }
+        if attackers >= 2 || queen_attacks_zone {
+            let danger = KING_DANGER[units.min(99) as usize];
+            score += make_score(-danger, -danger / 4);
+        }
+
+        score
+    };
+
+    side_score(true) - side_score(false)
+}
+
+/// Batteries and x-rays, from White's point of view: heavy pieces doubled
+/// on files without own pawns, bishop+queen batteries aimed at the king zone
+/// and sliders that hit the enemy king or queen through a single blocker
+fn evaluate_batteries(board: &Board) -> Score {
+    let occupied = board.bb_white | board.bb_black;
+
+    let side_score = |white: bool| {
+        let (own, enemy) = if white { (board.bb_white, board.bb_black) } else { (board.bb_black, board.bb_white) };
+        let own_pawns = board.bb_pawns & own;
+        let heavy = (board.bb_rooks | board.bb_queens) & own;
+        let diagonal = (board.bb_bishops | board.bb_queens) & own;
+        let enemy_king = board.bb_kings & enemy;
+        let targets = (board.bb_kings | board.bb_queens) & enemy;
+        let king_zone = if enemy_king != 0 { enemy_king | KING_ATTACKS[lsb(enemy_king)] } else { 0 };
+
+        let mut score = 0;
+
+        // Doubled heavy pieces (each pair counted once, from the lower piece)
+        for sq in Squares(heavy) {
+            let partners = rook_attacks(sq, occupied) & heavy & file_bb(file_of(sq)) & !((1u64 << sq) - 1);
+            if partners != 0 && file_bb(file_of(sq)) & own_pawns == 0 {
+                score += FILE_BATTERY_BONUS;
+            }
+        }
+
+        // Bishop in front of or behind the queen, aimed at the king zone
+        for sq in Squares(board.bb_bishops & own) {
+            let queens = bishop_attacks(sq, occupied) & board.bb_queens & own;
+            if queens != 0 && bishop_attacks(sq, occupied & !queens) & king_zone != 0 {
+                score += DIAGONAL_BATTERY_BONUS;
+            }
+        }
+
+        // X-rays through one blocker
+        for sq in Squares(heavy | diagonal) {
+            let piece_type = get_piece_type(board.squares[sq]);
+            let attacks_with = |occ: u64| match piece_type {
+                ROOK => rook_attacks(sq, occ),
+                BISHOP => bishop_attacks(sq, occ),
+                _ => queen_attacks(sq, occ),
+            };
+            let attacks = attacks_with(occupied);
+            let xray = attacks_with(occupied & !attacks) & !attacks;
+            score += popcount(xray & targets) as i32 * XRAY_BONUS;
+        }
+
+        score
+    };
+
+    side_score(true) - side_score(false)
+}
+
+/// Color-complex weaknesses, from White's point of view. When a side is
+/// left with a bishop of only one square color, the squares of that color around
+/// its king that its pawns do not guard are holes for the opponent's
+/// bishop of that color, or failing that its queen.
+fn evaluate_color_complexes(board: &Board) -> Score {
+    // Bonus for the attacker (`white`) against the enemy king
+    let side_score = |white: bool| {
+        let (own, enemy) = if white { (board.bb_white, board.bb_black) } else { (board.bb_black, board.bb_white) };
+        let enemy_king = board.bb_kings & enemy;
+        if enemy_king == 0 {
+            return 0;
+        }
+        let king_zone = enemy_king | KING_ATTACKS[lsb(enemy_king)];
+
+        let guarded = pawn_attacks(board, !white);
+
+        let mut score = 0;
+        for (color, other) in [(LIGHT_SQUARES, DARK_SQUARES), (DARK_SQUARES, LIGHT_SQUARES)] {
+            // Only a side left with the bishop of the other color has a
+            // one-sided weakness
+            if board.bb_bishops & enemy & color != 0 || board.bb_bishops & enemy & other == 0 {
+                continue;
+            }
+            let bonus = if board.bb_bishops & own & color != 0 {
+                COLOR_COMPLEX_BISHOP_BONUS
+            } else if board.bb_queens & own != 0 {
+                COLOR_COMPLEX_QUEEN_BONUS
+            } else {
+                continue;
+            };
+            let holes = popcount(king_zone & color & !guarded) as i32;
+            score += holes.min(MAX_COLOR_COMPLEX_HOLES) * bonus;
+        }
+        score
+    };
+
+    side_score(true) - side_score(false)
+}
+
+/// Evaluate piece activity: the bishop pair, rooks on open or semi-open
+/// files and rooks on the files of passed pawns
+fn evaluate_pieces(board: &Board) -> Score {
+    let occupied = board.bb_white | board.bb_black;
+    let passers = [passed_pawns(board, false), passed_pawns(board, true)];
+
+    let side_score = |white: bool| {
+        let own = if white { board.bb_white } else { board.bb_black };
+        let own_pawns = board.bb_pawns & own;
+
+        let mut score = 0;
+        if popcount(board.bb_bishops & own) >= 2 {
+            score += BISHOP_PAIR_BONUS;
+        }
+
+        for sq in Squares(board.bb_rooks & own) {
+            let file = file_bb(file_of(sq));
+            if board.bb_pawns & file == 0 {
+                score += ROOK_ON_OPEN_FILE_BONUS;
+            } else if own_pawns & file == 0 {
+                score += ROOK_ON_SEMI_OPEN_FILE_BONUS;
+            }
+
+            // Nearest passers up and down the file, with nothing in between
+            for pawn in Squares(rook_attacks(sq, occupied) & file & (passers[0] | passers[1])) {
+                let pawn_is_white = square_bb(pawn) & passers[1] != 0;
+                // Behind a passer means on the side it moves away from
+                let behind = if pawn_is_white { sq < pawn } else { sq > pawn };
+                if behind {
+                    score += ROOK_BEHIND_PASSER_BONUS;
+                } else if pawn_is_white == white {
+                    score += ROOK_IN_FRONT_OF_PASSER_PENALTY;
                 }
             }
         }
-        _ => {}
-    }
+        score
+    };
 
-    moves
+    side_score(true) - side_score(false)
 }
 
-/// Evaluate piece mobility
-fn evaluate_mobility(board: &Board) -> i32 {
-    let mut score = 0;
+/// Penalty for a back-rank rook shut in a corner by its own uncastled king,
+/// e.g. Kf1/Rh1, Kc1/Ra1 or Ke1/Rh1, from White's point of view. The king
+/// need not stay on the back rank, so stepping up does not free the rook.
+/// A rook that still has moves (an open file in front) is not trapped; the
+/// penalty is smaller while the king can castle to the rook's side.
+fn evaluate_trapped_rooks(board: &Board) -> Score {
+    let occupied = board.bb_white | board.bb_black;
+
+    let side_penalty = |white: bool| {
+        let Some(king_sq) = board.find_king(white) else { return 0 };
+        let (rook, own, back_rank) = if white {
+            (WHITE_ROOK, board.bb_white, 0)
+        } else {
+            (BLACK_ROOK, board.bb_black, 7)
+        };
 
-    for sq in 0..64 {
-        let piece = board.squares[sq];
-        if piece == EMPTY { continue; }
+        let king_file = king_sq % 8;
+        let (corner_files, right) = match king_file {
+            4..=6 => (king_file + 1..8, if white { CASTLE_WK } else { CASTLE_BK }),
+            1..=3 => (0..king_file, if white { CASTLE_WQ } else { CASTLE_BQ }),
+            _ => return 0,
+        };
+
+        let trapped = corner_files.into_iter().any(|file| {
+            let sq = back_rank * 8 + file;
+            board.squares[sq] == rook
+                && popcount(rook_attacks(sq, occupied) & !own) <= TRAPPED_ROOK_MAX_MOBILITY
+        });
+        if !trapped {
+            0
+        } else if board.castling_rights & right != 0 {
+            TRAPPED_ROOK_CASTLING_PENALTY
+        } else {
+            TRAPPED_ROOK_PENALTY
+        }
+    };
+
+    side_penalty(true) - side_penalty(false)
+}
+
+/// Opening principles, from White's point of view: develop the minor pieces,
+/// do not move the same piece twice before that, and keep the queen home
+/// until the minors are out. Moves spent on pieces that already left their
+/// home square are the moves played beyond the number of back-rank and
+/// pawn-rank squares that changed.
+fn evaluate_development(board: &Board) -> Score {
+    let moves_played = |white: bool| {
+        let moves = board.fullmove_number as i32 - 1;
+        if white && !board.white_to_move { moves + 1 } else { moves }
+    };
 
-        let piece_type = get_piece_type(piece);
-        let is_white = get_piece_color(piece) == WHITE;
+    let side_score = |white: bool| {
+        let (color, mirror) = if white { (WHITE, 0) } else { (BLACK, 56) };
+        let mut score = 0;
 
-        let bonus_per_move = match piece_type {
-            KNIGHT => KNIGHT_MOBILITY_BONUS,
-            BISHOP => BISHOP_MOBILITY_BONUS,
-            ROOK => ROOK_MOBILITY_BONUS,
-            QUEEN => QUEEN_MOBILITY_BONUS,
-            _ => continue,
+        let undeveloped = MINOR_HOME_SQUARES.iter()
+            .filter(|&&(sq, piece_type)| board.squares[sq ^ mirror] == color | piece_type)
+            .count() as i32;
+        score += undeveloped * UNDEVELOPED_MINOR_PENALTY;
+
+        if undeveloped > 0 {
+            let changed = HOME_PIECE_TYPES.iter().enumerate()
+                .filter(|&(sq, &piece_type)| board.squares[sq ^ mirror] != color | piece_type)
+                .count() as i32;
+            let wasted = (moves_played(white) - changed).clamp(0, MAX_WASTED_TEMPI);
+            score += wasted * WASTED_TEMPO_PENALTY;
+
+            if board.squares[QUEEN_HOME_SQUARE ^ mirror] != color | QUEEN {
+                score += undeveloped * EARLY_QUEEN_PENALTY;
+            }
+        }
+
+        score
+    };
+
+    side_score(true) - side_score(false)
+}
+
+/// Heavy pieces on the 7th rank, from White's point of view. They only
+/// count when there is something to do there: enemy pawns on the rank or
+/// the enemy king cut off on the back rank. Two heavy pieces on the rank
+/// earn an extra bonus.
+fn evaluate_seventh_rank(board: &Board) -> Score {
+    let side_score = |white: bool| {
+        let (own, enemy, seventh, eighth) = if white {
+            (board.bb_white, board.bb_black, RANK_7, RANK_8)
+        } else {
+            (board.bb_black, board.bb_white, RANK_2, RANK_1)
         };
 
-        let moves = count_mobility(board, sq, piece_type, is_white);
-        let bonus = moves * bonus_per_move;
+        let enemy_pawns_on_rank = board.bb_pawns & enemy & seventh != 0;
+        let king_cut_off = board.bb_kings & enemy & eighth != 0;
+        if !enemy_pawns_on_rank && !king_cut_off {
+            return 0;
+        }
 
-        if is_white { score += bonus; } else { score -= bonus; }
-    }
+        let rooks = popcount(board.bb_rooks & own & seventh) as i32;
+        let queens = popcount(board.bb_queens & own & seventh) as i32;
 
-    score
+        let mut score = rooks * ROOK_ON_7TH_RANK_BONUS + queens * QUEEN_ON_7TH_RANK_BONUS;
+        if rooks >= 2 {
+            score += DOUBLED_ROOKS_ON_7TH_BONUS;
+        }
+        if rooks >= 1 && queens >= 1 {
+            score += QUEEN_AND_ROOK_ON_7TH_BONUS;
+        }
+        score
+    };
+
+    side_score(true) - side_score(false)
+}
+
+/// Piece mobility: squares each minor and heavy piece attacks that hold
+/// no own piece and are not guarded by an enemy pawn
+fn evaluate_mobility(board: &Board) -> Score {
+    let occupied = board.bb_white | board.bb_black;
+
+    let side_score = |white: bool| {
+        let own = if white { board.bb_white } else { board.bb_black };
+        let area = !own & !pawn_attacks(board, !white);
+
+        let mut score = 0;
+        for sq in Squares((board.bb_knights | board.bb_bishops | board.bb_rooks | board.bb_queens) & own) {
+            let (attacks, bonus) = match get_piece_type(board.squares[sq]) {
+                KNIGHT => (KNIGHT_ATTACKS[sq], KNIGHT_MOBILITY_BONUS),
+                BISHOP => (bishop_attacks(sq, occupied), BISHOP_MOBILITY_BONUS),
+                ROOK => (rook_attacks(sq, occupied), ROOK_MOBILITY_BONUS),
+                _ => (queen_attacks(sq, occupied), QUEEN_MOBILITY_BONUS),
+            };
+            score += popcount(attacks & area) as i32 * bonus;
+        }
+        score
+    };
+
+    side_score(true) - side_score(false)
 }
 
 /// Evaluate center control
-fn evaluate_center_control(board: &Board) -> i32 {
+fn evaluate_center_control(board: &Board) -> Score {
     let mut score = 0;
 
     for &sq in &CENTER_SQUARES {
@@ -511,55 +1023,182 @@ fn evaluate_center_control(board: &Board) -> i32 {
 }
 
 // ============================================================================
-// MAIN EVALUATION FUNCTION
+// ENDGAME SCALING
 // ============================================================================
 
-/// Evaluate the position from white's perspective (positive = white is better)
-pub fn evaluate(board: &Board) -> i32 {
-    let mut score = 0;
-    let endgame = is_endgame(board);
-    let (white_pawns, black_pawns) = get_pawn_positions(board);
+/// Non-pawn material of one side, in centipawns
+fn non_pawn_material(board: &Board, own: u64) -> i32 {
+    popcount(board.bb_knights & own) as i32 * PIECE_VALUES[KNIGHT as usize]
+        + popcount(board.bb_bishops & own) as i32 * PIECE_VALUES[BISHOP as usize]
+        + popcount(board.bb_rooks & own) as i32 * PIECE_VALUES[ROOK as usize]
+        + popcount(board.bb_queens & own) as i32 * PIECE_VALUES[QUEEN as usize]
+}
 
-    // Material and piece-square tables
-    for sq in 0..64 {
-        let piece = board.squares[sq];
-        if piece == EMPTY { continue; }
+/// How much of the score the side ahead (`white` when positive) keeps, out
+/// of SCALE_NORMAL. Scales the score toward a draw when the material on
+/// the board is known to be hard or impossible to convert.
+fn endgame_scale(board: &Board, white_ahead: bool) -> i32 {
+    let (strong, weak) = if white_ahead { (board.bb_white, board.bb_black) } else { (board.bb_black, board.bb_white) };
+    let strong_pawns = board.bb_pawns & strong;
+    let weak_pawns = board.bb_pawns & weak;
+    let strong_material = non_pawn_material(board, strong);
+    let weak_material = non_pawn_material(board, weak);
+    let (Some(weak_king), Some(_)) = (board.find_king(!white_ahead), board.find_king(white_ahead)) else {
+        return SCALE_NORMAL;
+    };
 
-        let piece_type = get_piece_type(piece);
-        let is_white = get_piece_color(piece) == WHITE;
+    // Bishop of the wrong color with only rook pawns, the defending king
+    // next to the promotion corner
+    let strong_bishops = board.bb_bishops & strong;
+    if strong_pawns != 0
+        && strong_material == PIECE_VALUES[BISHOP as usize]
+        && strong_bishops != 0
+        && weak_material == 0
+        && (strong_pawns & !FILE_A == 0 || strong_pawns & !FILE_H == 0)
+    {
+        let file = file_of(lsb(strong_pawns));
+        let promotion = if white_ahead { 56 + file } else { file };
+        let bishop_color = if strong_bishops & LIGHT_SQUARES != 0 { LIGHT_SQUARES } else { DARK_SQUARES };
+        if square_bb(promotion) & bishop_color == 0 && square_distance(weak_king, promotion) <= 1 {
+            return SCALE_DEAD_DRAW;
+        }
+    }
 
-        let material_value = PIECE_VALUES[piece_type as usize];
-        let pst_value = get_pst_value(piece_type, sq, is_white, endgame);
+    // Minor pieces that cannot force mate against a bare king, such as
+    // two knights
+    if strong_pawns == 0 && weak_pawns == 0 && !board.can_force_mate(white_ahead) {
+        return SCALE_DEAD_DRAW;
+    }
 
-        if is_white {
-            score += material_value + pst_value;
+    // No pawns left to promote and at most a minor piece up
+    if strong_pawns == 0 && strong_material - weak_material <= PIECE_VALUES[BISHOP as usize] {
+        return SCALE_NO_PAWNS;
+    }
+
+    // Opposite-colored bishops
+    let weak_bishops = board.bb_bishops & weak;
+    if popcount(strong_bishops) == 1
+        && popcount(weak_bishops) == 1
+        && (strong_bishops & LIGHT_SQUARES == 0) != (weak_bishops & LIGHT_SQUARES == 0)
+    {
+        let bishop = PIECE_VALUES[BISHOP as usize];
+        return if strong_material == bishop && weak_material == bishop {
+            SCALE_OPPOSITE_BISHOPS
         } else {
-            score -= material_value + pst_value;
+            SCALE_OPPOSITE_BISHOPS_WITH_PIECES
+        };
+    }
+
+    // Rook and pawn against rook, the defending king blocking the pawn
+    let rook = PIECE_VALUES[ROOK as usize];
+    if strong_material == rook
+        && weak_material == rook
+        && board.bb_rooks & strong != 0
+        && board.bb_rooks & weak != 0
+        && popcount(strong_pawns) == 1
+        && weak_pawns == 0
+    {
+        let pawn = lsb(strong_pawns);
+        let ahead = if white_ahead { rank_of(weak_king) > rank_of(pawn) } else { rank_of(weak_king) < rank_of(pawn) };
+        if ahead && file_of(weak_king).abs_diff(file_of(pawn)) <= 1 {
+            return SCALE_ROOK_PAWN_BLOCKED;
         }
     }
 
+    SCALE_NORMAL
+}
+
+// ============================================================================
+// MAIN EVALUATION FUNCTION
+// ============================================================================
+
+/// Pass every evaluation term of the position, tapered by the game phase,
+/// from white's perspective (positive = white is better) to `report`
+/// together with its name
+#[inline(always)]
+fn for_each_term<F: FnMut(&'static str, i32)>(board: &Board, mut report: F) {
+    let phase = board.phase();
+    let mut total = 0;
+    let mut term = |name: &'static str, score: Score| {
+        let value = taper(score, phase);
+        total += value;
+        report(name, value);
+    };
+    let (white_pawns, black_pawns) = get_pawn_positions(board);
+
+    // Material and piece-square tables, maintained incrementally by the board
+    term("material", board.psqt);
+
     // Pawn structure
-    score += evaluate_pawn_structure(board, &white_pawns, &black_pawns);
+    term("pawn structure", evaluate_pawn_structure(board, &white_pawns, &black_pawns));
+
+    // Passed pawns
+    term("passed pawns", evaluate_passed_pawns(board));
+
+    // Pawn storms
+    term("pawn storms", evaluate_pawn_storms(board));
+
+    // Pawn races
+    term("passed pawn races", evaluate_unstoppable_passers(board, &white_pawns, &black_pawns));
 
     // Piece activity
-    score += evaluate_pieces(board, &white_pawns, &black_pawns);
+    term("piece placement", evaluate_pieces(board));
+
+    // Heavy pieces on the 7th rank
+    term("seventh rank", evaluate_seventh_rank(board));
+
+    // Rooks shut in by their own king
+    term("trapped rooks", evaluate_trapped_rooks(board));
+
+    // Development
+    if board.fullmove_number <= OPENING_MOVES {
+        term("development", evaluate_development(board));
+    }
+
+    // King shelter and attacks on the king zone
+    term("king safety", evaluate_king_safety(board));
+
+    // Weak color complexes around the kings
+    term("color complexes", evaluate_color_complexes(board));
+
+    // Batteries and x-rays
+    term("batteries", evaluate_batteries(board));
 
     // Mobility
-    score += evaluate_mobility(board);
+    term("mobility", evaluate_mobility(board));
 
     // Center control
-    score += evaluate_center_control(board);
+    term("center control", evaluate_center_control(board));
+
+    // Drawish endgames, reported as the amount taken off the total
+    let scale = endgame_scale(board, total > 0);
+    if scale != SCALE_NORMAL {
+        report("endgame scaling", total * scale / SCALE_NORMAL - total);
+    }
+}
+
+/// Evaluate the position from the side to move's perspective
+pub fn evaluate(board: &Board) -> i32 {
+    let mut score = 0;
+    for_each_term(board, |_, value| score += value);
 
     // Return score from the perspective of the side to move
     if board.white_to_move { score } else { -score }
 }
 
+/// The terms summed by `evaluate`, by name, from white's perspective
+pub fn evaluation_terms(board: &Board) -> Vec<(&'static str, i32)> {
+    let mut terms = Vec::new();
+    for_each_term(board, |name, value| terms.push((name, value)));
+    terms
+}
+
 /// Evaluate a move for move ordering (captures, promotions)
-pub fn evaluate_move(board: &Board, mv: &crate::board::Move) -> i32 {
+pub fn evaluate_move(board: &Board, mv: &Move) -> i32 {
     let mut score = 0;
 
     // Captures - MVV-LVA (Most Valuable Victim - Least Valuable Attacker)
-    let victim = board.squares[mv.to_sq];
+    let victim = board.captured_piece(mv);
     if victim != EMPTY {
         let victim_value = PIECE_VALUES[get_piece_type(victim) as usize];
         let attacker = board.squares[mv.from_sq];
@@ -574,3 +1213,70 @@ pub fn evaluate_move(board: &Board, mv: &crate::board::Move) -> i32 {
 
     score
 }
+
+/// Static exchange evaluation: the material (centipawns) the side to move
+/// wins when `mv` starts a series of captures on its target square, both
+/// sides recapturing with their least valuable piece and free to stop.
+/// Pins are ignored; x-ray attackers join as the pieces in front leave.
+pub fn see(board: &Board, mv: &Move) -> i32 {
+    if mv.is_castling {
+        return 0;
+    }
+
+    let to = mv.to_sq;
+    let mut occupied = board.occupied() ^ square_bb(mv.from_sq);
+    let mut gain = [0i32; 32];
+
+    gain[0] = if mv.is_en_passant {
+        occupied ^= square_bb(if board.white_to_move { to - 8 } else { to + 8 });
+        PIECE_VALUES[PAWN as usize]
+    } else {
+        PIECE_VALUES[get_piece_type(board.squares[to]) as usize]
+    };
+    // Value of the piece standing on the square, next to be captured
+    let mut on_square = PIECE_VALUES[get_piece_type(board.squares[mv.from_sq]) as usize];
+    if mv.promotion != 0 {
+        gain[0] += PIECE_VALUES[mv.promotion as usize] - PIECE_VALUES[PAWN as usize];
+        on_square = PIECE_VALUES[mv.promotion as usize];
+    }
+
+    let by_type = [
+        (PAWN, board.bb_pawns), (KNIGHT, board.bb_knights), (BISHOP, board.bb_bishops),
+        (ROOK, board.bb_rooks), (QUEEN, board.bb_queens), (KING, board.bb_kings),
+    ];
+    let mut white = !board.white_to_move;
+    let mut depth = 0;
+
+    while depth + 1 < gain.len() {
+        let attackers = attackers_to(to, occupied, board.bb_white, board.bb_black, board.bb_pawns,
+                                     board.bb_knights, board.bb_bishops, board.bb_rooks,
+                                     board.bb_queens, board.bb_kings) & occupied;
+        let own = attackers & if white { board.bb_white } else { board.bb_black };
+        let Some((sq, piece_type)) = by_type.iter()
+            .find(|&&(_, pieces)| own & pieces != 0)
+            .map(|&(piece_type, pieces)| (lsb(own & pieces), piece_type)) else { break };
+
+        // The king may only take last
+        if piece_type == KING && attackers & !own != 0 {
+            break;
+        }
+
+        // Stop once neither standing pat nor capturing can bring this side
+        // back to even
+        if (on_square - gain[depth]).max(-gain[depth]) < 0 {
+            break;
+        }
+        depth += 1;
+        gain[depth] = on_square - gain[depth - 1];
+
+        on_square = PIECE_VALUES[piece_type as usize];
+        occupied ^= square_bb(sq);
+        white = !white;
+    }
+
+    while depth > 0 {
+        gain[depth - 1] = -(-gain[depth - 1]).max(gain[depth]);
+        depth -= 1;
+    }
+    gain[0]
+}
@@ -0,0 +1,122 @@
+//! OpusChess - Move Explanation Module
+//!
+//! This module turns the result of a search into a short, human-readable
+//! justification of the chosen move, aimed at club players using the engine
+//! as a trainer:
+//! - the main line in SAN
+//! - how much better the move scored than the best alternative
+//! - the evaluation terms that changed the most along the main line
+
+use crate::board::{Board, Move};
+use crate::evaluation::evaluation_terms;
+use crate::search::mate_distance;
+use crate::wdl::WdlModel;
+
+/// Evaluation terms listed at most
+const MAX_TERMS: usize = 3;
+
+/// Term changes (centipawns) too small to mention
+const MIN_TERM_CHANGE: i32 = 10;
+
+/// Lines explaining why `pv[0]` was chosen in `board`. `score` is the score
+/// of the move and `runner_up` the best other move with its score, both
+/// from the side to move's perspective and from the same search. Scores are
+/// reported in the centipawns of `wdl_model`, like UCI info lines.
+pub fn explain(board: &Board, pv: &[Move], score: i32, runner_up: Option<(Move, i32)>,
+               wdl_model: &WdlModel) -> Vec<String> {
+    let Some(&best) = pv.first() else {
+        return vec!["no move to explain".to_string()];
+    };
+    let best_san = best.to_san(board);
+
+    let mut lines = vec![
+        format!("best move {} score {}", best_san, format_pawns(score, wdl_model)),
+        format!("main line {}", line_to_san(board, pv)),
+    ];
+
+    lines.push(match runner_up {
+        Some((mv, other)) => format!(
+            "{} is {} better than {} ({})",
+            best_san, format_delta(score, other, wdl_model), mv.to_san(board), format_pawns(other, wdl_model)
+        ),
+        None => format!("{} is the only legal move", best_san),
+    });
+
+    let changes = term_changes(board, pv);
+    if changes.is_empty() {
+        lines.push("no evaluation term changes much along the main line".to_string());
+    } else {
+        let changes: Vec<String> = changes.iter()
+            .map(|(name, change)| format!("{} {:+.2}", name, *change as f64 / 100.0))
+            .collect();
+        lines.push(format!("along the main line: {}", changes.join(", ")));
+    }
+
+    lines
+}
+
+/// Score in normalized pawns, or the mate distance in moves
+fn format_pawns(score: i32, wdl_model: &WdlModel) -> String {
+    match mate_distance(score) {
+        Some(plies) if score > 0 => format!("mate in {}", (plies + 1) / 2),
+        Some(plies) => format!("mated in {}", (plies + 1) / 2),
+        None => format!("{:+.2}", wdl_model.normalize(score) as f64 / 100.0),
+    }
+}
+
+/// How much better `score` is than `other`, in normalized pawns
+fn format_delta(score: i32, other: i32, wdl_model: &WdlModel) -> String {
+    if mate_distance(score).is_some() || mate_distance(other).is_some() {
+        "decisively".to_string()
+    } else {
+        let delta = wdl_model.normalize(score) - wdl_model.normalize(other);
+        format!("{:.2} pawns", delta as f64 / 100.0)
+    }
+}
+
+/// Evaluation terms that changed the most between `board` and the end of
+/// `pv`, from the side to move's perspective, largest change first
+fn term_changes(board: &Board, pv: &[Move]) -> Vec<(&'static str, i32)> {
+    let mut end = board.clone();
+    for mv in pv {
+        end.make_move(mv);
+    }
+
+    let before = evaluation_terms(board);
+    let after = evaluation_terms(&end);
+    let sign = if board.white_to_move { 1 } else { -1 };
+
+    // Terms present in only one of the positions count as 0 in the other
+    let value_of = |terms: &[(&'static str, i32)], name: &str| {
+        terms.iter().find(|&&(n, _)| n == name).map_or(0, |&(_, v)| v)
+    };
+    let mut changes: Vec<(&'static str, i32)> = Vec::new();
+    for &(name, _) in before.iter().chain(&after) {
+        if !changes.iter().any(|&(seen, _)| seen == name) {
+            changes.push((name, sign * (value_of(&after, name) - value_of(&before, name))));
+        }
+    }
+
+    changes.retain(|&(_, change)| change.abs() >= MIN_TERM_CHANGE);
+    changes.sort_by_key(|&(_, change)| -change.abs());
+    changes.truncate(MAX_TERMS);
+    changes
+}
+
+/// `moves` played from `board` in SAN with move numbers
+fn line_to_san(board: &Board, moves: &[Move]) -> String {
+    let mut board = board.clone();
+    let mut parts = Vec::new();
+
+    for (i, mv) in moves.iter().enumerate() {
+        if board.white_to_move {
+            parts.push(format!("{}.", board.fullmove_number));
+        } else if i == 0 {
+            parts.push(format!("{}...", board.fullmove_number));
+        }
+        parts.push(mv.to_san(&board));
+        board.make_move(mv);
+    }
+
+    parts.join(" ")
+}
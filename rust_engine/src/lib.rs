@@ -7,14 +7,35 @@
 //! - Transposition table with Zobrist hashing
 //! - Advanced pruning techniques (NMP, LMR, etc.)
 //! - Multi-threaded search (Lazy SMP)
+//! - Optional NNUE evaluation
 //! - Bitboard representation for fast move generation
+//! - A library API (`engine::Engine`) for embedding without UCI
 
 pub mod types;
+pub mod version;
+mod trace;
 pub mod bitboard;
+pub mod zobrist;
 pub mod board;
+pub mod san;
+pub mod pgn;
+#[cfg(feature = "serde")]
+mod serde_support;
 pub mod move_generator;
+mod move_picker;
+pub mod perft;
+pub mod book;
+pub mod tt;
 pub mod evaluation;
+pub mod nnue;
+pub mod time_manager;
 pub mod search;
+pub mod strength;
+pub mod search_tree;
+pub mod wdl;
+pub mod explain;
 pub mod parallel_search;
+pub mod cluster;
+pub mod engine;
 pub mod uci;
 
@@ -9,13 +9,38 @@
 //!
 //! Usage:
 //!     opus_chess
+//!     opus_chess --cluster-worker [address]
 //!
 //! The engine reads UCI commands from stdin and writes responses to stdout.
 //! Compatible with any UCI chess GUI (Arena, CuteChess, etc.)
+//!
+//! With `--cluster-worker` the engine instead serves search requests from a
+//! master engine over TCP (default address 0.0.0.0:7700); list the worker in
+//! the master's ClusterHosts option.
 
+use opus_chess::cluster;
 use opus_chess::uci::UCIProtocol;
 
 fn main() {
+    // Search tracing goes to stderr, stdout is reserved for UCI
+    #[cfg(feature = "trace")]
+    tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .with_writer(std::io::stderr)
+        .init();
+
+    let args: Vec<String> = std::env::args().collect();
+
+    if args.get(1).map(String::as_str) == Some("--cluster-worker") {
+        let default_addr = format!("0.0.0.0:{}", cluster::DEFAULT_PORT);
+        let addr = args.get(2).cloned().unwrap_or(default_addr);
+        if let Err(e) = cluster::run_worker(&addr, 64, 0) {
+            eprintln!("cluster worker failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let mut uci = UCIProtocol::new();
     uci.run();
 }
@@ -2,17 +2,56 @@
 //!
 //! This module handles the generation of legal chess moves, including
 //! all special moves (castling, en passant, pawn promotion).
+//!
+//! Legal moves are generated without making them: the pieces giving check
+//! restrict where other pieces may move, pinned pieces may only move along
+//! the line of the pin, and the king may only step to squares no enemy
+//! piece attacks once the king itself is lifted off the board.
 
 use crate::types::*;
-use crate::board::{Board, Move};
-use crate::bitboard::{KNIGHT_ATTACKS, KING_ATTACKS, PAWN_ATTACKS, rook_attacks, bishop_attacks};
+use crate::board::{castling_index, Board, Move};
+use crate::bitboard::*;
+
+/// Promotion pieces, in the order they are generated
+const PROMOTION_PIECES: [u8; 4] = [QUEEN, ROOK, BISHOP, KNIGHT];
 
-/// Direction offsets for sliding pieces
-const ROOK_DIRECTIONS: [i32; 4] = [8, -8, -1, 1];
-const BISHOP_DIRECTIONS: [i32; 4] = [7, 9, -7, -9];
-const QUEEN_DIRECTIONS: [i32; 8] = [8, -8, -1, 1, 7, 9, -7, -9];
-const KING_DIRECTIONS: [i32; 8] = [8, -8, -1, 1, 7, 9, -7, -9];
-const KNIGHT_OFFSETS: [i32; 8] = [17, 15, 10, 6, -6, -10, -15, -17];
+/// Push a move from `from` to every square of `targets`
+#[inline]
+fn push_moves(from: usize, mut targets: u64, moves: &mut Vec<Move>) {
+    while targets != 0 {
+        moves.push(Move::new(from, pop_lsb(&mut targets)));
+    }
+}
+
+/// Push the pawn moves landing on `targets`, each coming from `step`
+/// squares back, with all promotions for those reaching `promo_rank`
+#[inline]
+fn push_pawn_moves(mut targets: u64, step: i32, promo_rank: u64, moves: &mut Vec<Move>) {
+    while targets != 0 {
+        let to = pop_lsb(&mut targets);
+        let from = (to as i32 - step) as usize;
+        if square_bb(to) & promo_rank != 0 {
+            for promo in PROMOTION_PIECES {
+                moves.push(Move::with_promotion(from, to, promo));
+            }
+        } else {
+            moves.push(Move::new(from, to));
+        }
+    }
+}
+
+/// Check and pin information of the side to move
+struct Legality {
+    king: usize,
+    /// Enemy pieces giving check
+    checkers: u64,
+    /// Squares a move other than a king move must land on: everywhere out
+    /// of check, the checker or a square between it and the king in single
+    /// check, nowhere in double check
+    check_mask: u64,
+    /// Own pieces pinned to the king
+    pinned: u64,
+}
 
 /// Move generator for chess positions
 pub struct MoveGenerator;
@@ -25,185 +64,251 @@ impl MoveGenerator {
 
     /// Generate all legal moves for the current position
     pub fn generate_legal_moves(&self, board: &Board) -> Vec<Move> {
-        let pseudo_legal = self.generate_pseudo_legal_moves(board);
-        let mut legal_moves = Vec::with_capacity(pseudo_legal.len());
+        let mut moves = Vec::with_capacity(64);
+        let Some(legality) = self.legality(board) else {
+            return moves;
+        };
 
-        for mv in pseudo_legal {
-            if self.is_legal(board, &mv) {
-                legal_moves.push(mv);
-            }
+        if legality.checkers != 0 {
+            self.generate_evasions_into(board, &legality, &mut moves);
+            return moves;
         }
 
-        legal_moves
+        self.generate_moves_from(board, board.get_own_pieces(), !board.get_own_pieces(), &mut moves);
+        moves.retain(|mv| self.is_legal_with(board, &legality, mv));
+        moves
     }
 
-    /// Generate all pseudo-legal moves (may leave king in check)
-    pub fn generate_pseudo_legal_moves(&self, board: &Board) -> Vec<Move> {
-        let mut moves = Vec::with_capacity(64);
-        let color = if board.white_to_move { WHITE } else { BLACK };
-
-        for sq in 0..64 {
-            let piece = board.squares[sq];
-            if piece == EMPTY || get_piece_color(piece) != color {
-                continue;
+    /// Generate the legal moves out of check: king steps and, in single
+    /// check, captures of the checking piece and interpositions. Empty when
+    /// the side to move is not in check.
+    pub fn generate_evasions(&self, board: &Board) -> Vec<Move> {
+        let mut moves = Vec::with_capacity(32);
+        if let Some(legality) = self.legality(board) {
+            if legality.checkers != 0 {
+                self.generate_evasions_into(board, &legality, &mut moves);
             }
+        }
+        moves
+    }
 
-            let piece_type = get_piece_type(piece);
+    /// Generate the legal captures, en passant included, and promotions
+    /// (captures or not), for the quiescence search
+    pub fn generate_captures(&self, board: &Board) -> Vec<Move> {
+        let mut moves = Vec::with_capacity(16);
+        let Some(legality) = self.legality(board) else {
+            return moves;
+        };
 
-            match piece_type {
-                PAWN => self.generate_pawn_moves(board, sq, &mut moves),
-                KNIGHT => self.generate_knight_moves(board, sq, &mut moves),
-                BISHOP => self.generate_sliding_moves(board, sq, &BISHOP_DIRECTIONS, &mut moves),
-                ROOK => self.generate_sliding_moves(board, sq, &ROOK_DIRECTIONS, &mut moves),
-                QUEEN => self.generate_sliding_moves(board, sq, &QUEEN_DIRECTIONS, &mut moves),
-                KING => self.generate_king_moves(board, sq, &mut moves),
-                _ => {}
-            }
+        let own = board.get_own_pieces();
+        let enemy = board.get_enemy_pieces();
+        if legality.check_mask != 0 {
+            let promo_rank = if board.white_to_move { RANK_8 } else { RANK_1 };
+            let pawn_targets = (enemy | promo_rank) & legality.check_mask;
+            self.generate_pawn_moves(board, board.bb_pawns & own, pawn_targets, &mut moves);
+            let pieces = own & !board.bb_pawns & !board.bb_kings;
+            self.generate_moves_from(board, pieces, enemy & legality.check_mask, &mut moves);
         }
+        push_moves(legality.king, KING_ATTACKS[legality.king] & enemy, &mut moves);
 
+        moves.retain(|mv| self.is_legal_with(board, &legality, mv));
         moves
     }
 
-    /// Generate pawn moves from the given square
-    fn generate_pawn_moves(&self, board: &Board, sq: usize, moves: &mut Vec<Move>) {
-        let color = get_piece_color(board.squares[sq]);
-        let is_white_pawn = color == WHITE;
-
-        let direction: i32 = if is_white_pawn { 8 } else { -8 };
-        let start_rank = if is_white_pawn { 1 } else { 6 };
-        let promo_rank = if is_white_pawn { 7 } else { 0 };
+    /// Generate the legal quiet moves: neither captures nor promotions,
+    /// castling included. Together with `generate_captures` these are
+    /// exactly the legal moves.
+    pub fn generate_quiets(&self, board: &Board) -> Vec<Move> {
+        let mut moves = Vec::with_capacity(48);
+        let Some(legality) = self.legality(board) else {
+            return moves;
+        };
 
-        let file = sq % 8;
-        let rank = sq / 8;
-
-        // Single push
-        let to_sq = (sq as i32 + direction) as usize;
-        if to_sq < 64 && board.squares[to_sq] == EMPTY {
-            if to_sq / 8 == promo_rank {
-                // Promotion
-                for promo in [QUEEN, ROOK, BISHOP, KNIGHT] {
-                    moves.push(Move::with_promotion(sq, to_sq, promo));
-                }
-            } else {
-                moves.push(Move::new(sq, to_sq));
-
-                // Double push from starting rank
-                if rank == start_rank {
-                    let to_sq2 = (sq as i32 + 2 * direction) as usize;
-                    if to_sq2 < 64 && board.squares[to_sq2] == EMPTY {
-                        moves.push(Move::new(sq, to_sq2));
-                    }
-                }
+        let own = board.get_own_pieces();
+        let empty = !board.occupied();
+        let promo_rank = if board.white_to_move { RANK_8 } else { RANK_1 };
+        let pawns = board.bb_pawns & own;
+
+        if legality.checkers != 0 {
+            push_moves(legality.king, KING_ATTACKS[legality.king] & empty, &mut moves);
+            if legality.check_mask != 0 {
+                let targets = legality.check_mask & empty;
+                self.generate_pawn_moves(board, pawns, targets & !promo_rank, &mut moves);
+                self.generate_moves_from(board, own & !board.bb_pawns & !board.bb_kings, targets, &mut moves);
             }
+        } else {
+            self.generate_pawn_moves(board, pawns, empty & !promo_rank, &mut moves);
+            self.generate_moves_from(board, own & !board.bb_pawns, empty, &mut moves);
         }
 
-        // Captures
-        let capture_offsets = [direction - 1, direction + 1];
-        for offset in capture_offsets {
-            let to_sq_i32 = sq as i32 + offset;
-            if to_sq_i32 < 0 || to_sq_i32 >= 64 {
-                continue;
-            }
-            let to_sq = to_sq_i32 as usize;
-            let to_file = to_sq % 8;
+        // En passant and king captures ignore the targets
+        moves.retain(|mv| {
+            (mv.is_castling || (!mv.is_en_passant && board.squares[mv.to_sq] == EMPTY))
+                && self.is_legal_with(board, &legality, mv)
+        });
+        moves
+    }
 
-            // Check if move wraps around the board
-            if (to_file as i32 - file as i32).abs() != 1 {
-                continue;
-            }
+    fn generate_evasions_into(&self, board: &Board, legality: &Legality, moves: &mut Vec<Move>) {
+        let own = board.get_own_pieces();
+
+        // Castling is never an evasion, so the king only steps
+        push_moves(legality.king, KING_ATTACKS[legality.king] & !own, moves);
+        if legality.check_mask != 0 {
+            self.generate_moves_from(board, own & !board.bb_kings, legality.check_mask & !own, moves);
+        }
 
-            let target = board.squares[to_sq];
+        moves.retain(|mv| self.is_legal_with(board, legality, mv));
+    }
 
-            // Regular capture
-            if target != EMPTY && get_piece_color(target) != color {
-                if to_sq / 8 == promo_rank {
-                    for promo in [QUEEN, ROOK, BISHOP, KNIGHT] {
-                        moves.push(Move::with_promotion(sq, to_sq, promo));
-                    }
-                } else {
-                    moves.push(Move::new(sq, to_sq));
-                }
-            }
+    /// Generate all pseudo-legal moves (may leave king in check)
+    pub fn generate_pseudo_legal_moves(&self, board: &Board) -> Vec<Move> {
+        let mut moves = Vec::with_capacity(64);
+        self.generate_moves_from(board, board.get_own_pieces(), !board.get_own_pieces(), &mut moves);
+        moves
+    }
+
+    /// Whether the position has at least one legal move
+    pub fn has_legal_move(&self, board: &Board) -> bool {
+        !self.generate_legal_moves(board).is_empty()
+    }
+
+    /// Pieces giving check to the side to move, the squares that answer a
+    /// check and the pinned pieces, or None without a king
+    fn legality(&self, board: &Board) -> Option<Legality> {
+        let white = board.white_to_move;
+        let (own, enemy) = if white { (board.bb_white, board.bb_black) } else { (board.bb_black, board.bb_white) };
+        let king = board.find_king(white)?;
+        let occupied = board.occupied();
+
+        let checkers = self.attackers_to(board, king, occupied) & enemy;
+        let check_mask = match popcount(checkers) {
+            0 => !0,
+            1 => checkers | between(king, lsb(checkers)),
+            _ => 0,
+        };
 
-            // En passant capture
-            if board.en_passant_square >= 0 && to_sq == board.en_passant_square as usize {
-                moves.push(Move::en_passant(sq, to_sq));
+        // A piece is pinned when it is the only one between the king and an
+        // enemy slider aimed at it
+        let mut snipers = ((rook_attacks(king, 0) & (board.bb_rooks | board.bb_queens))
+            | (bishop_attacks(king, 0) & (board.bb_bishops | board.bb_queens))) & enemy;
+        let mut pinned = 0;
+        while snipers != 0 {
+            let blockers = between(king, pop_lsb(&mut snipers)) & occupied;
+            if popcount(blockers) == 1 {
+                pinned |= blockers & own;
             }
         }
+
+        Some(Legality { king, checkers, check_mask, pinned })
     }
 
-    /// Generate knight moves from the given square
-    fn generate_knight_moves(&self, board: &Board, sq: usize, moves: &mut Vec<Move>) {
-        let color = get_piece_color(board.squares[sq]);
-        let file = sq % 8;
-        let rank = sq / 8;
+    /// All pieces of both colors attacking `sq` through the `occupied` squares
+    fn attackers_to(&self, board: &Board, sq: usize, occupied: u64) -> u64 {
+        attackers_to(sq, occupied, board.bb_white, board.bb_black, board.bb_pawns, board.bb_knights,
+                     board.bb_bishops, board.bb_rooks, board.bb_queens, board.bb_kings)
+    }
 
-        for &offset in &KNIGHT_OFFSETS {
-            let to_sq_i32 = sq as i32 + offset;
-            if to_sq_i32 < 0 || to_sq_i32 >= 64 {
-                continue;
-            }
-            let to_sq = to_sq_i32 as usize;
-            let to_file = to_sq % 8;
-            let to_rank = to_sq / 8;
-
-            // Check for wraparound
-            if (to_file as i32 - file as i32).abs() > 2 
-               || (to_rank as i32 - rank as i32).abs() > 2 {
-                continue;
-            }
+    /// Whether the pseudo-legal move `mv` is legal, given the check and pin
+    /// information of the position
+    fn is_legal_with(&self, board: &Board, legality: &Legality, mv: &Move) -> bool {
+        let (from, to) = (square_bb(mv.from_sq), square_bb(mv.to_sq));
+        let occupied = board.occupied();
+        let enemy = board.get_enemy_pieces();
+
+        if mv.from_sq == legality.king {
+            // Castling is only generated when the king's path is safe
+            return mv.is_castling
+                || self.attackers_to(board, mv.to_sq, occupied & !from) & enemy & !to == 0;
+        }
 
-            let target = board.squares[to_sq];
-            if target == EMPTY || get_piece_color(target) != color {
-                moves.push(Move::new(sq, to_sq));
-            }
+        if mv.is_en_passant {
+            // The capture empties two squares of the same rank at once, so
+            // play it out on the occupancy
+            let captured = square_bb(if board.white_to_move { mv.to_sq - 8 } else { mv.to_sq + 8 });
+            let after = (occupied & !from & !captured) | to;
+            return self.attackers_to(board, legality.king, after) & enemy & !captured == 0;
         }
+
+        to & legality.check_mask != 0
+            && (legality.pinned & from == 0 || line(legality.king, mv.from_sq) & to != 0)
     }
 
-    /// Generate moves for sliding pieces (bishop, rook, queen)
-    fn generate_sliding_moves(&self, board: &Board, sq: usize, directions: &[i32], moves: &mut Vec<Move>) {
-        let color = get_piece_color(board.squares[sq]);
+    /// Whether `mv` is a pseudo-legal move of the side to move, checked by
+    /// generating only the moves of the piece on its start square (for moves
+    /// that may come from another position, such as hash moves)
+    pub fn is_pseudo_legal(&self, board: &Board, mv: &Move) -> bool {
+        let from = square_bb(mv.from_sq);
+        if board.get_own_pieces() & from == 0 {
+            return false;
+        }
 
-        for &direction in directions {
-            let mut current_sq = sq;
-            loop {
-                let current_file = current_sq % 8;
-                let next_sq_i32 = current_sq as i32 + direction;
+        let mut moves = Vec::new();
+        self.generate_moves_from(board, from, !board.get_own_pieces(), &mut moves);
+        moves.contains(mv)
+    }
 
-                if next_sq_i32 < 0 || next_sq_i32 >= 64 {
-                    break;
-                }
-                let next_sq = next_sq_i32 as usize;
-                let next_file = next_sq % 8;
-
-                // Check for wraparound
-                let file_diff = (next_file as i32 - current_file as i32).abs();
-                if direction == -1 || direction == 1 {
-                    if file_diff != 1 {
-                        break;
-                    }
-                } else if direction == 7 || direction == -9 {
-                    if next_file as i32 != current_file as i32 - 1 {
-                        break;
-                    }
-                } else if direction == 9 || direction == -7 {
-                    if next_file as i32 != current_file as i32 + 1 {
-                        break;
-                    }
-                }
+    /// Generate the pseudo-legal moves of the own pieces on the squares of
+    /// `from` that land on `targets` (which should not hold own pieces).
+    /// King moves and en passant ignore `targets`.
+    fn generate_moves_from(&self, board: &Board, from: u64, targets: u64, moves: &mut Vec<Move>) {
+        let own = board.get_own_pieces() & from;
+        let occupied = board.occupied();
 
-                let target = board.squares[next_sq];
+        self.generate_pawn_moves(board, board.bb_pawns & own, targets, moves);
 
-                if target == EMPTY {
-                    moves.push(Move::new(sq, next_sq));
-                } else if get_piece_color(target) != color {
-                    moves.push(Move::new(sq, next_sq));
-                    break;
-                } else {
-                    break;
-                }
+        for sq in Squares(board.bb_knights & own) {
+            push_moves(sq, KNIGHT_ATTACKS[sq] & targets, moves);
+        }
+
+        for sq in Squares(board.bb_bishops & own) {
+            push_moves(sq, bishop_attacks(sq, occupied) & targets, moves);
+        }
+
+        for sq in Squares(board.bb_rooks & own) {
+            push_moves(sq, rook_attacks(sq, occupied) & targets, moves);
+        }
 
-                current_sq = next_sq;
+        for sq in Squares(board.bb_queens & own) {
+            push_moves(sq, queen_attacks(sq, occupied) & targets, moves);
+        }
+
+        let mut kings = board.bb_kings & own;
+        while kings != 0 {
+            self.generate_king_moves(board, pop_lsb(&mut kings), moves);
+        }
+    }
+
+    /// Generate the moves of the given pawns of the side to move landing on
+    /// `targets`, a whole set at a time: every push or capture direction is
+    /// one shift of the pawn bitboard
+    fn generate_pawn_moves(&self, board: &Board, pawns: u64, targets: u64, moves: &mut Vec<Move>) {
+        if pawns == 0 {
+            return;
+        }
+        let white = board.white_to_move;
+        let empty = !board.occupied();
+        let enemy = board.get_enemy_pieces() & targets;
+        let (promo_rank, double_rank) = if white { (RANK_8, RANK_3) } else { (RANK_1, RANK_6) };
+
+        // Destinations of each direction, and the step back to the origin
+        let (single, left, right, forward, left_step, right_step) = if white {
+            (pawns << 8 & empty, (pawns & NOT_FILE_A) << 7 & enemy, (pawns & NOT_FILE_H) << 9 & enemy, 8, 7, 9)
+        } else {
+            (pawns >> 8 & empty, (pawns & NOT_FILE_H) >> 7 & enemy, (pawns & NOT_FILE_A) >> 9 & enemy, -8, -7, -9)
+        };
+        let double = if white { (single & double_rank) << 8 & empty } else { (single & double_rank) >> 8 & empty };
+
+        push_pawn_moves(single & targets, forward, promo_rank, moves);
+        push_pawn_moves(double & targets, 2 * forward, promo_rank, moves);
+        push_pawn_moves(left, left_step, promo_rank, moves);
+        push_pawn_moves(right, right_step, promo_rank, moves);
+
+        if board.en_passant_square >= 0 {
+            let ep = board.en_passant_square as usize;
+            let mut capturers = PAWN_ATTACKS[usize::from(white)][ep] & pawns;
+            while capturers != 0 {
+                moves.push(Move::en_passant(pop_lsb(&mut capturers), ep));
             }
         }
     }
@@ -214,71 +319,41 @@ impl MoveGenerator {
         let file = sq % 8;
 
         // Normal king moves
-        for &direction in &KING_DIRECTIONS {
-            let to_sq_i32 = sq as i32 + direction;
-            if to_sq_i32 < 0 || to_sq_i32 >= 64 {
-                continue;
-            }
-            let to_sq = to_sq_i32 as usize;
-            let to_file = to_sq % 8;
-
-            // Check for wraparound
-            if (to_file as i32 - file as i32).abs() > 1 {
-                continue;
-            }
-
-            let target = board.squares[to_sq];
-            if target == EMPTY || get_piece_color(target) != color {
-                moves.push(Move::new(sq, to_sq));
-            }
-        }
-
-        // Castling
-        let is_white_king = color == WHITE;
-        let enemy_is_white = !is_white_king;
-
-        if !self.is_square_attacked(board, sq, enemy_is_white) {
-            if is_white_king {
-                // Kingside castling (O-O) - white
-                if (board.castling_rights & CASTLE_WK) != 0
-                    && board.squares[5] == EMPTY
-                    && board.squares[6] == EMPTY
-                    && !self.is_square_attacked(board, 5, false)
-                    && !self.is_square_attacked(board, 6, false)
-                {
-                    moves.push(Move::castling(sq, 6));
+        push_moves(sq, KING_ATTACKS[sq] & !board.get_own_pieces(), moves);
+
+        // Castling: every square the king and rook cross or land on must
+        // be empty (apart from the two of them), and the king may not start
+        // on, cross or land on an attacked square
+        let white = color == WHITE;
+        if board.castling_rights != 0 && !self.is_square_attacked(board, sq, !white) {
+            let base = sq - file;
+            let own_rook = color | ROOK;
+
+            for kingside in [true, false] {
+                let index = castling_index(white, kingside);
+                if board.castling_rights & (1 << index) == 0 {
+                    continue;
                 }
 
-                // Queenside castling (O-O-O) - white
-                if (board.castling_rights & CASTLE_WQ) != 0
-                    && board.squares[1] == EMPTY
-                    && board.squares[2] == EMPTY
-                    && board.squares[3] == EMPTY
-                    && !self.is_square_attacked(board, 2, false)
-                    && !self.is_square_attacked(board, 3, false)
-                {
-                    moves.push(Move::castling(sq, 2));
+                let rook_from = board.castling_rooks[index];
+                if board.squares[rook_from] != own_rook {
+                    continue;
                 }
-            } else {
-                // Kingside castling (O-O) - black
-                if (board.castling_rights & CASTLE_BK) != 0
-                    && board.squares[61] == EMPTY
-                    && board.squares[62] == EMPTY
-                    && !self.is_square_attacked(board, 61, true)
-                    && !self.is_square_attacked(board, 62, true)
-                {
-                    moves.push(Move::castling(sq, 62));
-                }
-
-                // Queenside castling (O-O-O) - black
-                if (board.castling_rights & CASTLE_BQ) != 0
-                    && board.squares[57] == EMPTY
-                    && board.squares[58] == EMPTY
-                    && board.squares[59] == EMPTY
-                    && !self.is_square_attacked(board, 58, true)
-                    && !self.is_square_attacked(board, 59, true)
-                {
-                    moves.push(Move::castling(sq, 58));
+                let (king_to, rook_to) = if kingside { (base + 6, base + 5) } else { (base + 2, base + 3) };
+
+                let low = sq.min(king_to).min(rook_from).min(rook_to);
+                let high = sq.max(king_to).max(rook_from).max(rook_to);
+                let blocked = (low..=high)
+                    .any(|s| s != sq && s != rook_from && board.squares[s] != EMPTY);
+                // The castling rook may be shielding the king's path (Chess960)
+                let occupied = board.occupied() & !square_bb(rook_from);
+                let enemy = board.get_enemy_pieces();
+                let attacked = (sq.min(king_to)..=sq.max(king_to))
+                    .any(|s| s != sq && self.attackers_to(board, s, occupied) & enemy != 0);
+
+                if !blocked && !attacked {
+                    let to_sq = if board.chess960 { rook_from } else { king_to };
+                    moves.push(Move::castling(sq, to_sq));
                 }
             }
         }
@@ -286,7 +361,7 @@ impl MoveGenerator {
 
     /// Check if a square is attacked by the specified color (bitboard version)
     pub fn is_square_attacked(&self, board: &Board, sq: usize, by_white: bool) -> bool {
-        let occupied = board.get_occupied();
+        let occupied = board.occupied();
         
         // Get attacker pieces
         let (pawns, knights, bishops, rooks, queens, kings) = if by_white {
@@ -344,21 +419,54 @@ impl MoveGenerator {
         false
     }
 
-    /// Check if a move is legal (doesn't leave own king in check)
-    fn is_legal(&self, board: &Board, mv: &Move) -> bool {
-        let mut temp_board = board.clone();
-        let undo = temp_board.make_move(mv);
+    /// Check if a pseudo-legal move is legal (doesn't leave own king in check)
+    pub fn is_legal(&self, board: &Board, mv: &Move) -> bool {
+        self.legality(board).is_some_and(|legality| self.is_legal_with(board, &legality, mv))
+    }
+
+    /// Whether the legal move `mv` gives check, directly or by uncovering a
+    /// slider, worked out on the occupancy without making the move
+    pub fn gives_check(&self, board: &Board, mv: &Move) -> bool {
+        let white = board.white_to_move;
+        let Some(king) = board.find_king(!white) else {
+            return false;
+        };
 
-        // Check if own king is in check after the move
-        let king_sq = temp_board.find_king(!temp_board.white_to_move);
-        let in_check = match king_sq {
-            Some(sq) => self.is_square_attacked(&temp_board, sq, temp_board.white_to_move),
-            None => true,
+        // Occupancy after the move, the own squares it empties, and the
+        // piece that could check directly from its new square
+        let from = square_bb(mv.from_sq);
+        let mut occupied = board.occupied() & !from;
+        let mut vacated = from;
+        let (piece_sq, piece_type) = if mv.is_castling {
+            let (king_to, rook_from, rook_to) = board.castling_squares(mv);
+            occupied = (occupied & !square_bb(rook_from)) | square_bb(king_to) | square_bb(rook_to);
+            vacated |= square_bb(rook_from);
+            (rook_to, ROOK)
+        } else {
+            if mv.is_en_passant {
+                occupied &= !square_bb(if white { mv.to_sq - 8 } else { mv.to_sq + 8 });
+            }
+            occupied |= square_bb(mv.to_sq);
+            let piece_type = if mv.promotion != 0 { mv.promotion } else { get_piece_type(board.squares[mv.from_sq]) };
+            (mv.to_sq, piece_type)
         };
 
-        temp_board.unmake_move(mv, &undo);
+        let direct = match piece_type {
+            PAWN => PAWN_ATTACKS[usize::from(!white)][piece_sq],
+            KNIGHT => KNIGHT_ATTACKS[piece_sq],
+            BISHOP => bishop_attacks(piece_sq, occupied),
+            ROOK => rook_attacks(piece_sq, occupied),
+            QUEEN => queen_attacks(piece_sq, occupied),
+            _ => 0,
+        };
+        if direct & square_bb(king) != 0 {
+            return true;
+        }
 
-        !in_check
+        // Discovered check by an own slider that stayed in place
+        let sliders = board.get_own_pieces() & !vacated;
+        rook_attacks(king, occupied) & (board.bb_rooks | board.bb_queens) & sliders != 0
+            || bishop_attacks(king, occupied) & (board.bb_bishops | board.bb_queens) & sliders != 0
     }
 
     /// Check if the current side's king is in check
@@ -385,10 +493,11 @@ impl MoveGenerator {
         self.generate_legal_moves(board).is_empty()
     }
 
-    /// Check if the position is a draw
+    /// Check if the position is a draw. Checkmate takes precedence, so a
+    /// mate delivered on the 100th halfmove still wins.
     pub fn is_draw(&self, board: &Board) -> bool {
-        if self.is_stalemate(board) {
-            return true;
+        if !self.has_legal_move(board) {
+            return !self.is_in_check(board);
         }
         if board.is_fifty_moves() {
             return true;
@@ -0,0 +1,194 @@
+//! OpusChess - Move Picker Module
+//!
+//! This module hands out the moves of a search node one at a time, in
+//! stages, so a node that cuts off early does not pay for generating,
+//! checking and sorting moves it never searches:
+//! 1. the hash move, checked without generating anything
+//! 2. good captures and promotions, by MVV-LVA
+//! 3. the killer moves and the countermove (the quiet move that last
+//!    refuted the opponent's previous move), checked without generating
+//!    anything
+//! 4. quiet moves, by history score
+//! 5. bad captures (losing material by static exchange evaluation)
+//!
+//! Captures and promotions are generated when the capture stage is reached,
+//! and each one's exchange evaluation only runs when it comes up, moving a
+//! losing capture to the bad captures. Quiet moves are generated when the
+//! quiet stage is reached. The hash move, the killers and the countermove,
+//! which come from other positions, get a legality check of their own.
+//!
+//! ProbCut uses a capture-only picker that skips the hash move and every
+//! capture whose exchange evaluation is below a threshold, and the root uses
+//! one that hands out its ranked legal moves as given.
+
+use crate::board::{Board, Move, PackedMove};
+use crate::evaluation::{evaluate_move, see};
+use crate::move_generator::MoveGenerator;
+use crate::types::*;
+
+/// Stages of the move picker, in the order they are visited
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Stage {
+    Given,
+    TtMove,
+    GoodCaptures,
+    Refutations,
+    Quiets,
+    BadCaptures,
+    Done,
+}
+
+/// Staged, lazily generated move list of one node
+pub(crate) struct MovePicker {
+    stage: Stage,
+    tt_move: Option<Move>,
+    /// The two killer moves, then the countermove
+    refutations: [PackedMove; 3],
+    /// Refutations handed out (or skipped) so far
+    refutation_index: usize,
+    /// Exchange evaluation a capture needs in a capture-only picker
+    capture_threshold: Option<i32>,
+    /// Legal moves still to hand out in order, for the root picker
+    given: std::vec::IntoIter<Move>,
+    captures_generated: bool,
+    /// Captures and promotions not yet handed out or found losing
+    captures: Vec<(Move, i32)>,
+    bad_captures: Vec<(Move, i32)>,
+    quiets: Vec<(Move, i32)>,
+}
+
+impl MovePicker {
+    pub(crate) fn new(tt_move: Option<Move>, killers: [PackedMove; 2], countermove: PackedMove) -> Self {
+        MovePicker {
+            stage: Stage::TtMove,
+            tt_move,
+            refutations: [killers[0], killers[1], countermove],
+            refutation_index: 0,
+            capture_threshold: None,
+            given: Vec::new().into_iter(),
+            captures_generated: false,
+            captures: Vec::new(),
+            bad_captures: Vec::new(),
+            quiets: Vec::new(),
+        }
+    }
+
+    /// Picker of the captures and promotions whose exchange evaluation is at
+    /// least `threshold`
+    pub(crate) fn captures(threshold: i32) -> Self {
+        MovePicker {
+            stage: Stage::GoodCaptures,
+            capture_threshold: Some(threshold),
+            ..MovePicker::new(None, [PackedMove::NONE; 2], PackedMove::NONE)
+        }
+    }
+
+    /// Picker of `moves`, already legal, in the given order
+    pub(crate) fn given(moves: Vec<Move>) -> Self {
+        MovePicker {
+            stage: Stage::Given,
+            given: moves.into_iter(),
+            ..MovePicker::new(None, [PackedMove::NONE; 2], PackedMove::NONE)
+        }
+    }
+
+    /// Next legal move of `board`, or None once all have been picked.
+    /// `history` scores the quiet moves.
+    pub(crate) fn next<H>(&mut self, board: &Board, move_generator: &MoveGenerator, history: H) -> Option<Move>
+    where H: Fn(&Move) -> i32
+    {
+        loop {
+            match self.stage {
+                Stage::Given => return self.given.next(),
+                Stage::TtMove => {
+                    self.stage = Stage::GoodCaptures;
+                    if let Some(mv) = self.tt_move {
+                        if move_generator.is_pseudo_legal(board, &mv) && move_generator.is_legal(board, &mv) {
+                            return Some(mv);
+                        }
+                    }
+                }
+                Stage::GoodCaptures => {
+                    if !self.captures_generated {
+                        self.captures_generated = true;
+                        self.captures = move_generator.generate_captures(board).into_iter()
+                            .map(|mv| (mv, evaluate_move(board, &mv)))
+                            .collect();
+                    }
+                    match pick_best_entry(&mut self.captures) {
+                        Some((mv, _)) if Some(mv) == self.tt_move => {}
+                        Some((mv, score)) => {
+                            let exchange = see(board, &mv);
+                            match self.capture_threshold {
+                                Some(threshold) if exchange < threshold => {}
+                                Some(_) => return Some(mv),
+                                None if exchange >= 0 => return Some(mv),
+                                None => self.bad_captures.push((mv, score)),
+                            }
+                        }
+                        None if self.capture_threshold.is_some() => self.stage = Stage::Done,
+                        None => self.stage = Stage::Refutations,
+                    }
+                }
+                Stage::Refutations => {
+                    if self.refutation_index == self.refutations.len() {
+                        self.quiets = move_generator.generate_quiets(board).into_iter()
+                            .map(|mv| (mv, history(&mv)))
+                            .collect();
+                        self.stage = Stage::Quiets;
+                        continue;
+                    }
+
+                    let index = self.refutation_index;
+                    self.refutation_index += 1;
+                    let Some(mv) = self.refutations[index].to_move() else { continue };
+                    // Refutations come from other positions, so they must be
+                    // legal quiet moves here too
+                    let duplicate = self.refutations[..index].contains(&self.refutations[index]);
+                    if !duplicate && Some(mv) != self.tt_move && is_quiet(board, &mv)
+                        && move_generator.is_pseudo_legal(board, &mv) && move_generator.is_legal(board, &mv) {
+                        return Some(mv);
+                    }
+                }
+                Stage::Quiets => match pick_best(&mut self.quiets) {
+                    Some(mv) => {
+                        if Some(mv) != self.tt_move && !self.refutations.contains(&PackedMove::new(mv)) {
+                            return Some(mv);
+                        }
+                    }
+                    None => self.stage = Stage::BadCaptures,
+                },
+                Stage::BadCaptures => match pick_best(&mut self.bad_captures) {
+                    Some(mv) => {
+                        if Some(mv) != self.tt_move {
+                            return Some(mv);
+                        }
+                    }
+                    None => self.stage = Stage::Done,
+                },
+                Stage::Done => return None,
+            }
+        }
+    }
+}
+
+/// Whether `mv` neither captures nor promotes in `board`
+fn is_quiet(board: &Board, mv: &Move) -> bool {
+    board.captured_piece(mv) == EMPTY && !mv.is_en_passant && mv.promotion == 0
+}
+
+/// Remove and return the best scored move (the first one among equals)
+fn pick_best(moves: &mut Vec<(Move, i32)>) -> Option<Move> {
+    pick_best_entry(moves).map(|(mv, _)| mv)
+}
+
+/// Remove and return the best scored move with its score
+fn pick_best_entry(moves: &mut Vec<(Move, i32)>) -> Option<(Move, i32)> {
+    let mut best: Option<(usize, i32)> = None;
+    for (index, &(_, score)) in moves.iter().enumerate() {
+        if best.is_none_or(|(_, best_score)| score > best_score) {
+            best = Some((index, score));
+        }
+    }
+    best.map(|(index, _)| moves.remove(index))
+}
@@ -0,0 +1,469 @@
+//! OpusChess - NNUE Evaluation Module
+//!
+//! This module provides an efficiently updatable neural network evaluation,
+//! an alternative to the handcrafted `evaluate` selected with the Use NNUE
+//! option. The network uses the HalfKP feature set: for each side, every
+//! non-king piece is one feature, indexed by the square of that side's king
+//! and the piece's kind, color and square (mirrored vertically for black, so
+//! both sides see the board from their own first rank).
+//!
+//! Layers, all quantized to integers:
+//! - feature transformer: 40960 features to 256 values per side (i16)
+//! - both sides concatenated, side to move first, clipped to 0..127
+//! - two hidden layers of 32 values (i8 weights), clipped to 0..127
+//! - one output, scaled down to centipawns
+//!
+//! The search keeps one accumulator per ply on its stack. Making a move
+//! builds the child's accumulator from the parent's by subtracting the
+//! features the move removed and adding those it placed; unmaking simply
+//! returns to the parent's. Only a king move changes every feature of its
+//! own side, so that side alone is refreshed from the whole board. The
+//! accumulator updates and the dot products of the hidden layers use AVX2 or SSSE3
+//! when the CPU supports them (detected at run time, so one binary serves
+//! every x86-64 machine), with scalar code elsewhere. All paths compute the
+//! same integers, so the evaluation does not depend on the machine.
+//!
+//! The network is read from the file named by the EvalFile option. Layout
+//! (little endian): the magic `OPNN`, the format version and the
+//! architecture hash as u32, then the parameters of each layer in order,
+//! biases before weights. A file with another version or architecture is
+//! rejected rather than misread.
+
+use std::fs;
+use std::io;
+
+use crate::board::{Board, Move, UndoInfo};
+use crate::types::*;
+
+/// Default of the EvalFile option
+pub const DEFAULT_EVAL_FILE: &str = "opus.nnue";
+
+const MAGIC: &[u8; 4] = b"OPNN";
+const VERSION: u32 = 1;
+
+/// HalfKP: king square x 10 piece kinds x 64 squares
+const PIECE_KINDS: usize = 10;
+const FEATURES: usize = 64 * PIECE_KINDS * 64;
+
+/// Layer sizes
+const L1: usize = 256;
+const L2: usize = 32;
+const L3: usize = 32;
+
+/// Hash of the layer sizes stored in the header, so a network of another
+/// shape is rejected instead of misread
+const ARCHITECTURE: u32 = architecture_hash();
+
+/// FNV-1a over the layer sizes
+const fn architecture_hash() -> u32 {
+    let sizes = [FEATURES, L1, L2, L3];
+    let mut hash: u32 = 0x811c_9dc5;
+    let mut index = 0;
+    while index < sizes.len() {
+        hash = (hash ^ sizes[index] as u32).wrapping_mul(0x0100_0193);
+        index += 1;
+    }
+    hash
+}
+
+/// Clipped activations lie in 0..=ACTIVATION_MAX
+const ACTIVATION_MAX: i32 = 127;
+
+/// Hidden layer outputs are shifted right by this many bits
+const WEIGHT_SCALE_BITS: u32 = 6;
+
+/// Raw network output per centipawn
+const OUTPUT_SCALE: i32 = 16;
+
+/// Instruction set used for inference
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Simd {
+    Scalar,
+    #[cfg(target_arch = "x86_64")]
+    Ssse3,
+    #[cfg(target_arch = "x86_64")]
+    Avx2,
+}
+
+impl Simd {
+    /// Best instruction set supported by the running CPU
+    fn detect() -> Self {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("avx2") {
+                return Simd::Avx2;
+            }
+            if is_x86_feature_detected!("ssse3") {
+                return Simd::Ssse3;
+            }
+        }
+        Simd::Scalar
+    }
+
+    /// Add a row of feature weights to an accumulator
+    #[inline]
+    fn add(self, accumulator: &mut [i16; L1], weights: &[i16]) {
+        // SAFETY: the SIMD variants are only chosen when the CPU has them
+        match self {
+            #[cfg(target_arch = "x86_64")]
+            Simd::Avx2 => unsafe { x86::add_avx2(accumulator, weights) },
+            #[cfg(target_arch = "x86_64")]
+            Simd::Ssse3 => unsafe { x86::add_sse(accumulator, weights) },
+            Simd::Scalar => {
+                for (sum, &weight) in accumulator.iter_mut().zip(weights) {
+                    *sum = sum.wrapping_add(weight);
+                }
+            }
+        }
+    }
+
+    /// Subtract a row of feature weights from an accumulator
+    #[inline]
+    fn sub(self, accumulator: &mut [i16; L1], weights: &[i16]) {
+        // SAFETY: as in `add`
+        match self {
+            #[cfg(target_arch = "x86_64")]
+            Simd::Avx2 => unsafe { x86::sub_avx2(accumulator, weights) },
+            #[cfg(target_arch = "x86_64")]
+            Simd::Ssse3 => unsafe { x86::sub_sse(accumulator, weights) },
+            Simd::Scalar => {
+                for (sum, &weight) in accumulator.iter_mut().zip(weights) {
+                    *sum = sum.wrapping_sub(weight);
+                }
+            }
+        }
+    }
+
+    /// Dot product of clipped activations and a row of weights; the length
+    /// is a multiple of 32
+    #[inline]
+    fn dot(self, input: &[u8], weights: &[i8]) -> i32 {
+        // SAFETY: as in `add`
+        match self {
+            #[cfg(target_arch = "x86_64")]
+            Simd::Avx2 => unsafe { x86::dot_avx2(input, weights) },
+            #[cfg(target_arch = "x86_64")]
+            Simd::Ssse3 => unsafe { x86::dot_ssse3(input, weights) },
+            Simd::Scalar => input.iter()
+                .zip(weights)
+                .map(|(&x, &weight)| x as i32 * weight as i32)
+                .sum(),
+        }
+    }
+}
+
+/// Feature transformer outputs of both sides (white first) for one
+/// position
+#[derive(Clone)]
+pub(crate) struct Accumulator {
+    values: [[i16; L1]; 2],
+}
+
+impl Default for Accumulator {
+    fn default() -> Self {
+        Accumulator { values: [[0; L1]; 2] }
+    }
+}
+
+/// Quantized network parameters
+pub struct Network {
+    simd: Simd,
+    ft_biases: Vec<i16>,
+    /// [feature][L1]
+    ft_weights: Vec<i16>,
+    l1_biases: Vec<i32>,
+    /// [L2][2 * L1]
+    l1_weights: Vec<i8>,
+    l2_biases: Vec<i32>,
+    /// [L3][L2]
+    l2_weights: Vec<i8>,
+    output_bias: i32,
+    output_weights: Vec<i8>,
+}
+
+/// Sequential little-endian reader over the bytes of a network file
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.data.len() < len {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "network file truncated"));
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn i8s(&mut self, count: usize) -> io::Result<Vec<i8>> {
+        Ok(self.take(count)?.iter().map(|&b| b as i8).collect())
+    }
+
+    fn i16s(&mut self, count: usize) -> io::Result<Vec<i16>> {
+        Ok(self.take(count * 2)?.chunks_exact(2).map(|b| i16::from_le_bytes([b[0], b[1]])).collect())
+    }
+
+    fn i32s(&mut self, count: usize) -> io::Result<Vec<i32>> {
+        Ok(self.take(count * 4)?.chunks_exact(4).map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect())
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl Network {
+    pub fn load(path: &str) -> io::Result<Self> {
+        Network::from_bytes(&fs::read(path)?)
+    }
+
+    /// Parse a network file, checking its header
+    pub fn from_bytes(data: &[u8]) -> io::Result<Self> {
+        let mut reader = Reader { data };
+
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(invalid("not an OpusChess network".to_string()));
+        }
+        let version = reader.u32()?;
+        if version != VERSION {
+            return Err(invalid(format!("network format version {}, expected {}", version, VERSION)));
+        }
+        let architecture = reader.u32()?;
+        if architecture != ARCHITECTURE {
+            return Err(invalid(format!("network architecture {:#010x}, expected {:#010x}",
+                                       architecture, ARCHITECTURE)));
+        }
+
+        let network = Network {
+            simd: Simd::detect(),
+            ft_biases: reader.i16s(L1)?,
+            ft_weights: reader.i16s(FEATURES * L1)?,
+            l1_biases: reader.i32s(L2)?,
+            l1_weights: reader.i8s(L2 * 2 * L1)?,
+            l2_biases: reader.i32s(L3)?,
+            l2_weights: reader.i8s(L3 * L2)?,
+            output_bias: reader.u32()? as i32,
+            output_weights: reader.i8s(L3)?,
+        };
+
+        if !reader.data.is_empty() {
+            return Err(invalid(format!("{} unexpected bytes after the network", reader.data.len())));
+        }
+        Ok(network)
+    }
+
+    /// Evaluation in centipawns from the perspective of the side to move,
+    /// computed from scratch
+    pub fn evaluate(&self, board: &Board) -> i32 {
+        let mut accumulator = Accumulator::default();
+        self.refresh(board, &mut accumulator);
+        self.evaluate_accumulator(&accumulator, board.white_to_move)
+    }
+
+    /// Evaluation in centipawns from the perspective of the side to move,
+    /// given the accumulator of the position
+    pub(crate) fn evaluate_accumulator(&self, accumulator: &Accumulator, white_to_move: bool) -> i32 {
+        let (us, them) = if white_to_move { (WHITE, BLACK) } else { (BLACK, WHITE) };
+
+        let mut input = [0u8; 2 * L1];
+        for (half, perspective) in input.chunks_exact_mut(L1).zip([us, them]) {
+            let values = &accumulator.values[side_index(perspective)];
+            for (value, &sum) in half.iter_mut().zip(values.iter()) {
+                *value = (sum as i32).clamp(0, ACTIVATION_MAX) as u8;
+            }
+        }
+
+        let hidden1 = self.dense::<{ 2 * L1 }, L2>(&input, &self.l1_weights, &self.l1_biases);
+        let hidden2 = self.dense::<L2, L3>(&hidden1, &self.l2_weights, &self.l2_biases);
+
+        let output = self.output_bias + self.simd.dot(&hidden2, &self.output_weights);
+        output / OUTPUT_SCALE
+    }
+
+    /// Fully connected layer with clipped, scaled-down outputs
+    fn dense<const IN: usize, const OUT: usize>(&self, input: &[u8; IN], weights: &[i8], biases: &[i32])
+        -> [u8; OUT]
+    {
+        let mut output = [0u8; OUT];
+        for (index, value) in output.iter_mut().enumerate() {
+            let row = &weights[index * IN..(index + 1) * IN];
+            let sum = biases[index] + self.simd.dot(input, row);
+            *value = (sum >> WEIGHT_SCALE_BITS).clamp(0, ACTIVATION_MAX) as u8;
+        }
+        output
+    }
+
+    /// Accumulator of `board`, computed from the whole board
+    pub(crate) fn refresh(&self, board: &Board, accumulator: &mut Accumulator) {
+        for perspective in [WHITE, BLACK] {
+            self.refresh_side(board, perspective, &mut accumulator.values[side_index(perspective)]);
+        }
+    }
+
+    /// Accumulator of `board` right after `mv` was made (`undo` being what
+    /// make returned), from the accumulator `parent` of the position before
+    /// it. The side whose king moved is refreshed; otherwise only the
+    /// features the move removed and placed change.
+    pub(crate) fn update(&self, board: &Board, mv: &Move, undo: &UndoInfo, parent: &Accumulator,
+                         child: &mut Accumulator) {
+        let mover = get_piece_color(undo.moved_piece);
+
+        // At most two pieces leave a square and two land on one (castling
+        // moves the rook too); kings are not features
+        let mut removed = [(EMPTY, 0); 2];
+        let mut added = [(EMPTY, 0); 2];
+        if mv.is_castling {
+            let (_, rook_from, rook_to) = board.castling_squares(mv);
+            let rook = board.squares[rook_to];
+            removed[0] = (rook, rook_from);
+            added[0] = (rook, rook_to);
+        } else {
+            if get_piece_type(undo.moved_piece) != KING {
+                removed[0] = (undo.moved_piece, mv.from_sq);
+                added[0] = (board.squares[mv.to_sq], mv.to_sq);
+            }
+            if undo.captured_piece != EMPTY {
+                let captured_sq = match (mv.is_en_passant, mover == WHITE) {
+                    (true, true) => mv.to_sq - 8,
+                    (true, false) => mv.to_sq + 8,
+                    (false, _) => mv.to_sq,
+                };
+                removed[1] = (undo.captured_piece, captured_sq);
+            }
+        }
+
+        for perspective in [WHITE, BLACK] {
+            let side = side_index(perspective);
+            let values = &mut child.values[side];
+            if perspective == mover && get_piece_type(undo.moved_piece) == KING {
+                self.refresh_side(board, perspective, values);
+                continue;
+            }
+
+            values.copy_from_slice(&parent.values[side]);
+            let Some(king) = board.find_king(perspective == WHITE) else { continue };
+            let king = orient(king, perspective);
+            for &(piece, sq) in removed.iter().filter(|&&(piece, _)| piece != EMPTY) {
+                self.simd.sub(values, self.feature_weights(king, piece, sq, perspective));
+            }
+            for &(piece, sq) in added.iter().filter(|&&(piece, _)| piece != EMPTY) {
+                self.simd.add(values, self.feature_weights(king, piece, sq, perspective));
+            }
+        }
+    }
+
+    /// Feature transformer output for `perspective`, summed over every
+    /// non-king piece on the board
+    fn refresh_side(&self, board: &Board, perspective: u8, values: &mut [i16; L1]) {
+        values.copy_from_slice(&self.ft_biases);
+
+        let Some(king) = board.find_king(perspective == WHITE) else { return };
+        let king = orient(king, perspective);
+
+        for (sq, piece) in board.all_pieces().filter(|&(_, piece)| get_piece_type(piece) != KING) {
+            self.simd.add(values, self.feature_weights(king, piece, sq, perspective));
+        }
+    }
+
+    /// Feature transformer weights of `piece` on `sq`, seen by
+    /// `perspective` with its king on the (oriented) square `king`
+    fn feature_weights(&self, king: usize, piece: u8, sq: usize, perspective: u8) -> &[i16] {
+        let kind = (get_piece_type(piece) as usize - 1) * 2 + (get_piece_color(piece) != perspective) as usize;
+        let feature = (king * PIECE_KINDS + kind) * 64 + orient(sq, perspective);
+        &self.ft_weights[feature * L1..(feature + 1) * L1]
+    }
+}
+
+/// Index of `perspective` in `Accumulator::values`
+fn side_index(perspective: u8) -> usize {
+    (perspective == BLACK) as usize
+}
+
+/// Square as seen by `perspective`: black's board is mirrored vertically
+fn orient(sq: usize, perspective: u8) -> usize {
+    if perspective == WHITE { sq } else { sq ^ 56 }
+}
+
+/// x86-64 kernels. Activations are at most 127, so the pairwise sums of
+/// `maddubs` (at most 2 * 127 * 128) never saturate and match the scalar
+/// code exactly.
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use std::arch::x86_64::*;
+
+    use super::L1;
+
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn add_avx2(accumulator: &mut [i16; L1], weights: &[i16]) {
+        for (sum, row) in accumulator.chunks_exact_mut(16).zip(weights.chunks_exact(16)) {
+            let a = _mm256_loadu_si256(sum.as_ptr() as *const __m256i);
+            let b = _mm256_loadu_si256(row.as_ptr() as *const __m256i);
+            _mm256_storeu_si256(sum.as_mut_ptr() as *mut __m256i, _mm256_add_epi16(a, b));
+        }
+    }
+
+    #[target_feature(enable = "ssse3")]
+    pub(super) unsafe fn add_sse(accumulator: &mut [i16; L1], weights: &[i16]) {
+        for (sum, row) in accumulator.chunks_exact_mut(8).zip(weights.chunks_exact(8)) {
+            let a = _mm_loadu_si128(sum.as_ptr() as *const __m128i);
+            let b = _mm_loadu_si128(row.as_ptr() as *const __m128i);
+            _mm_storeu_si128(sum.as_mut_ptr() as *mut __m128i, _mm_add_epi16(a, b));
+        }
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn sub_avx2(accumulator: &mut [i16; L1], weights: &[i16]) {
+        for (sum, row) in accumulator.chunks_exact_mut(16).zip(weights.chunks_exact(16)) {
+            let a = _mm256_loadu_si256(sum.as_ptr() as *const __m256i);
+            let b = _mm256_loadu_si256(row.as_ptr() as *const __m256i);
+            _mm256_storeu_si256(sum.as_mut_ptr() as *mut __m256i, _mm256_sub_epi16(a, b));
+        }
+    }
+
+    #[target_feature(enable = "ssse3")]
+    pub(super) unsafe fn sub_sse(accumulator: &mut [i16; L1], weights: &[i16]) {
+        for (sum, row) in accumulator.chunks_exact_mut(8).zip(weights.chunks_exact(8)) {
+            let a = _mm_loadu_si128(sum.as_ptr() as *const __m128i);
+            let b = _mm_loadu_si128(row.as_ptr() as *const __m128i);
+            _mm_storeu_si128(sum.as_mut_ptr() as *mut __m128i, _mm_sub_epi16(a, b));
+        }
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn dot_avx2(input: &[u8], weights: &[i8]) -> i32 {
+        let ones = _mm256_set1_epi16(1);
+        let mut sum = _mm256_setzero_si256();
+        for (x, w) in input.chunks_exact(32).zip(weights.chunks_exact(32)) {
+            let x = _mm256_loadu_si256(x.as_ptr() as *const __m256i);
+            let w = _mm256_loadu_si256(w.as_ptr() as *const __m256i);
+            sum = _mm256_add_epi32(sum, _mm256_madd_epi16(_mm256_maddubs_epi16(x, w), ones));
+        }
+        let sum = _mm_add_epi32(_mm256_castsi256_si128(sum), _mm256_extracti128_si256::<1>(sum));
+        horizontal_sum(sum)
+    }
+
+    #[target_feature(enable = "ssse3")]
+    pub(super) unsafe fn dot_ssse3(input: &[u8], weights: &[i8]) -> i32 {
+        let ones = _mm_set1_epi16(1);
+        let mut sum = _mm_setzero_si128();
+        for (x, w) in input.chunks_exact(16).zip(weights.chunks_exact(16)) {
+            let x = _mm_loadu_si128(x.as_ptr() as *const __m128i);
+            let w = _mm_loadu_si128(w.as_ptr() as *const __m128i);
+            sum = _mm_add_epi32(sum, _mm_madd_epi16(_mm_maddubs_epi16(x, w), ones));
+        }
+        horizontal_sum(sum)
+    }
+
+    #[target_feature(enable = "ssse3")]
+    unsafe fn horizontal_sum(sum: __m128i) -> i32 {
+        let sum = _mm_add_epi32(sum, _mm_shuffle_epi32::<0b01_00_11_10>(sum));
+        let sum = _mm_add_epi32(sum, _mm_shuffle_epi32::<0b10_11_00_01>(sum));
+        _mm_cvtsi128_si32(sum)
+    }
+}
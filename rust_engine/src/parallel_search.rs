@@ -3,504 +3,173 @@
 //! This module implements multi-threaded search using the Lazy SMP algorithm.
 //! Each thread searches the same position independently with slightly different
 //! parameters, sharing the transposition table.
+//!
+//! An alternative root-splitting mode hands out root moves to threads one at a
+//! time and shares alpha between them, which avoids duplicated work at shallow
+//! depths.
 
-use std::sync::{Arc, Mutex, atomic::{AtomicBool, AtomicU64, Ordering}};
+use std::sync::{mpsc, Arc, Mutex, atomic::{AtomicBool, AtomicI32, AtomicU16, AtomicU64, AtomicUsize, Ordering}};
 use std::thread;
-use std::collections::HashMap;
-
-use crate::types::*;
-use crate::board::{Board, Move};
-use crate::move_generator::MoveGenerator;
-use crate::evaluation::{evaluate, evaluate_move, PIECE_VALUES};
-use crate::search::{INFINITY, MATE_SCORE, ZobristHash};
-
-const MAX_DEPTH: usize = 100;
-const TT_EXACT: u8 = 0;
-const TT_ALPHA: u8 = 1;
-const TT_BETA: u8 = 2;
-const NULL_MOVE_REDUCTION: i32 = 2;
-const LMR_FULL_DEPTH_MOVES: usize = 4;
-const LMR_REDUCTION_LIMIT: i32 = 3;
-const ASPIRATION_WINDOW: i32 = 50;
-const FUTILITY_MARGIN: [i32; 4] = [0, 200, 300, 500];
-const CHECK_EXTENSION: i32 = 1;
-const CONTEMPT: i32 = 25;
-
-/// Shared transposition table entry
-#[derive(Clone)]
-struct SharedTTEntry {
-    hash_key: u64,
-    depth: i32,
-    score: i32,
-    flag: u8,
-    best_move: Option<Move>,
-}
 
-/// Thread-safe transposition table
-pub struct SharedTranspositionTable {
-    table: Mutex<HashMap<u64, SharedTTEntry>>,
-    size: usize,
-    mask: u64,
-    hits: AtomicU64,
-    writes: AtomicU64,
+use crate::board::{Board, Move, PackedMove};
+use crate::nnue::Network;
+use crate::search::{history_gravity, INFINITY, CHECK_EXTENSION, NodeType, ScoreBound, SearchEvent, SearchOptions,
+                    HistoryTable, SearchReport, SearchThread};
+use crate::tt::TranspositionTable;
+use crate::time_manager::SearchLimits;
+use crate::trace::trace_event;
+
+/// Root moves are only reported once the search has run this long, so
+/// short searches stay quiet
+const CURRMOVE_DELAY_MS: u64 = 1000;
+
+/// Lazy SMP depth skipping: helper `i` (from 0) leaves out the depths d
+/// where (d + SKIP_PHASE[i]) / SKIP_SIZE[i] is odd, so the helpers spread
+/// over neighbouring depths instead of all repeating the main thread's.
+/// The pattern repeats after 20 helpers.
+const SKIP_SIZE: [i32; 20] = [1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 3, 3, 4, 4, 4, 4, 4, 4, 4, 4];
+const SKIP_PHASE: [i32; 20] = [0, 1, 0, 1, 2, 3, 0, 1, 2, 3, 4, 5, 0, 1, 2, 3, 4, 5, 6, 7];
+
+/// Called with the depth, move and move number (from 1) of every root move
+/// the main thread starts searching
+pub type CurrentMoveCallback = Box<dyn FnMut(i32, Move, usize) + Send>;
+
+/// History heuristic and countermove tables shared by all search threads.
+/// They are kept across searches and cleared for a new game; the history
+/// scores are halved at the start of each search.
+pub struct SharedHistory {
+    /// Flattened [side][from_sq][to_sq] scores
+    table: Vec<AtomicI32>,
+    /// Flattened [from_sq][to_sq] of a move to the bits of the quiet move
+    /// that last cut off in reply to it
+    countermoves: Vec<AtomicU16>,
 }
 
-impl SharedTranspositionTable {
-    pub fn new(size_mb: usize) -> Self {
-        let num_entries = (size_mb * 1024 * 1024) / 50;
-        let mut size = 1usize;
-        while size * 2 <= num_entries {
-            size *= 2;
-        }
-        let mask = (size - 1) as u64;
-
-        SharedTranspositionTable {
-            table: Mutex::new(HashMap::with_capacity(size)),
-            size,
-            mask,
-            hits: AtomicU64::new(0),
-            writes: AtomicU64::new(0),
+impl SharedHistory {
+    pub fn new() -> Self {
+        SharedHistory {
+            table: (0..2 * 64 * 64).map(|_| AtomicI32::new(0)).collect(),
+            countermoves: (0..64 * 64).map(|_| AtomicU16::new(0)).collect(),
         }
     }
 
-    fn probe(&self, hash_key: u64) -> Option<SharedTTEntry> {
-        let table = self.table.lock().unwrap();
-        if let Some(entry) = table.get(&(hash_key & self.mask)) {
-            if entry.hash_key == hash_key {
-                self.hits.fetch_add(1, Ordering::Relaxed);
-                return Some(entry.clone());
-            }
-        }
-        None
+    #[inline]
+    pub(crate) fn countermove(&self, from_sq: usize, to_sq: usize) -> PackedMove {
+        PackedMove::from_bits(self.countermoves[from_sq * 64 + to_sq].load(Ordering::Relaxed))
     }
 
-    fn store(&self, hash_key: u64, depth: i32, score: i32, flag: u8, best_move: Option<Move>) {
-        let index = hash_key & self.mask;
-        let mut table = self.table.lock().unwrap();
-        
-        let should_replace = match table.get(&index) {
-            None => true,
-            Some(existing) => depth >= existing.depth || hash_key == existing.hash_key,
-        };
-
-        if should_replace {
-            table.insert(index, SharedTTEntry { hash_key, depth, score, flag, best_move });
-            self.writes.fetch_add(1, Ordering::Relaxed);
-        }
+    #[inline]
+    pub(crate) fn set_countermove(&self, from_sq: usize, to_sq: usize, mv: PackedMove) {
+        self.countermoves[from_sq * 64 + to_sq].store(mv.bits(), Ordering::Relaxed);
     }
 
-    pub fn clear(&self) {
-        self.table.lock().unwrap().clear();
-        self.hits.store(0, Ordering::Relaxed);
-        self.writes.store(0, Ordering::Relaxed);
+    #[inline]
+    pub(crate) fn get(&self, side: usize, from_sq: usize, to_sq: usize) -> i32 {
+        self.table[(side * 64 + from_sq) * 64 + to_sq].load(Ordering::Relaxed)
     }
 
-    pub fn hashfull(&self) -> usize {
-        if self.size == 0 { return 0; }
-        ((self.writes.load(Ordering::Relaxed) as usize * 1000) / self.size).min(1000)
+    /// Apply `bonus` with the gravity formula. Concurrent updates of one
+    /// entry may lose one of them, which the heuristic tolerates.
+    #[inline]
+    pub(crate) fn update(&self, side: usize, from_sq: usize, to_sq: usize, bonus: i32) {
+        let entry = &self.table[(side * 64 + from_sq) * 64 + to_sq];
+        entry.store(history_gravity(entry.load(Ordering::Relaxed), bonus), Ordering::Relaxed);
     }
-}
-
-/// Worker thread search state
-struct WorkerSearch {
-    move_generator: MoveGenerator,
-    zobrist: ZobristHash,
-    nodes_searched: u64,
-    best_move: Option<Move>,
-    stop_search: Arc<AtomicBool>,
-    tt: Arc<SharedTranspositionTable>,
-    killer_moves: [[Option<Move>; 2]; MAX_DEPTH],
-    history: [[i32; 64]; 32],
-    use_tt: bool,
-    use_null_move: bool,
-    use_lmr: bool,
-    thread_id: usize,
-}
 
-impl WorkerSearch {
-    fn new(
-        thread_id: usize,
-        stop_search: Arc<AtomicBool>,
-        tt: Arc<SharedTranspositionTable>,
-        use_tt: bool,
-        use_null_move: bool,
-        use_lmr: bool,
-    ) -> Self {
-        WorkerSearch {
-            move_generator: MoveGenerator::new(),
-            zobrist: ZobristHash::new(),
-            nodes_searched: 0,
-            best_move: None,
-            stop_search,
-            tt,
-            killer_moves: [[None; 2]; MAX_DEPTH],
-            history: [[0; 64]; 32],
-            use_tt,
-            use_null_move,
-            use_lmr,
-            thread_id,
+    /// Halve every score, so the previous search still guides move ordering
+    /// without outweighing what the new one finds
+    pub fn decay(&self) {
+        for entry in &self.table {
+            entry.store(entry.load(Ordering::Relaxed) / 2, Ordering::Relaxed);
         }
     }
 
-    fn search(&mut self, board: &Board, depth: i32) -> (Option<Move>, i32) {
-        self.nodes_searched = 0;
-        self.best_move = None;
-        self.killer_moves = [[None; 2]; MAX_DEPTH];
-
-        let position_hash = self.zobrist.hash_position(board);
-        let mut best_move = None;
-        let mut best_score = -INFINITY;
-
-        // Add thread-specific depth variation for Lazy SMP
-        let thread_depth_offset = if self.thread_id % 2 == 1 { 1 } else { 0 };
-
-        // Initial search at depth 1
-        let mut temp_board = board.clone();
-        let score = self.alphabeta(&mut temp_board, 1, -INFINITY, INFINITY, 0, true, position_hash, true);
-        if self.best_move.is_some() {
-            best_move = self.best_move;
-            best_score = score;
+    pub fn clear(&self) {
+        for entry in &self.table {
+            entry.store(0, Ordering::Relaxed);
         }
-
-        // Iterative deepening with aspiration windows
-        for current_depth in 2..=depth {
-            if self.stop_search.load(Ordering::Relaxed) {
-                break;
-            }
-
-            // Lazy SMP: threads search with slightly different depths
-            let effective_depth = current_depth + thread_depth_offset;
-
-            let mut alpha = best_score - ASPIRATION_WINDOW;
-            let mut beta = best_score + ASPIRATION_WINDOW;
-
-            loop {
-                let mut temp_board = board.clone();
-                let score = self.alphabeta(
-                    &mut temp_board, effective_depth, alpha, beta,
-                    0, true, position_hash, true
-                );
-
-                if self.stop_search.load(Ordering::Relaxed) {
-                    break;
-                }
-
-                if score <= alpha {
-                    alpha = -INFINITY;
-                } else if score >= beta {
-                    beta = INFINITY;
-                } else {
-                    break;
-                }
-            }
-
-            if !self.stop_search.load(Ordering::Relaxed) && self.best_move.is_some() {
-                best_move = self.best_move;
-                best_score = self.alphabeta(
-                    &mut board.clone(), effective_depth, -INFINITY, INFINITY,
-                    0, true, position_hash, true
-                );
-            }
+        for entry in &self.countermoves {
+            entry.store(0, Ordering::Relaxed);
         }
-
-        (best_move, best_score)
     }
+}
 
-    fn alphabeta(
-        &mut self, board: &mut Board, depth: i32, mut alpha: i32, beta: i32,
-        ply: usize, is_root: bool, position_hash: u64, allow_null: bool
-    ) -> i32 {
-        if self.stop_search.load(Ordering::Relaxed) {
-            return 0;
-        }
-
-        self.nodes_searched += 1;
-        let original_alpha = alpha;
-
-        // Draw detection
-        if !is_root {
-            if board.is_fifty_moves() || board.is_repetition() {
-                return -CONTEMPT;
-            }
-            if board.has_insufficient_material() {
-                return -CONTEMPT;
-            }
-            if board.repetition_count() >= 2 {
-                return -CONTEMPT * 2;
-            }
-        }
-
-        // Probe TT
-        let mut tt_move: Option<Move> = None;
-
-        if self.use_tt {
-            if let Some(entry) = self.tt.probe(position_hash) {
-                if !is_root && entry.depth >= depth {
-                    match entry.flag {
-                        TT_EXACT => return entry.score,
-                        TT_ALPHA if entry.score <= alpha => return alpha,
-                        TT_BETA if entry.score >= beta => return beta,
-                        _ => {}
-                    }
-                }
-                tt_move = entry.best_move;
-            }
-        }
-
-        // Check detection
-        let in_check = self.move_generator.is_in_check(board);
-        let extended_depth = if in_check { depth + CHECK_EXTENSION } else { depth };
-
-        // Generate moves
-        let moves = self.move_generator.generate_legal_moves(board);
-
-        // Checkmate / Stalemate
-        if moves.is_empty() {
-            return if in_check { -MATE_SCORE + ply as i32 } else { 0 };
-        }
-
-        // Quiescence at leaf
-        if extended_depth <= 0 {
-            return self.quiescence(board, alpha, beta);
-        }
-
-        // Static evaluation for pruning
-        let static_eval = if extended_depth <= 4 && !in_check && alpha.abs() < MATE_SCORE - 100 {
-            Some(evaluate(board))
-        } else {
-            None
-        };
-
-        // Null Move Pruning
-        if self.use_null_move && allow_null && !is_root && !in_check
-           && extended_depth >= 3 && self.has_big_pieces(board) {
-
-            board.white_to_move = !board.white_to_move;
-            let null_hash = position_hash ^ self.zobrist.side_key;
-
-            let null_score = -self.alphabeta(
-                board, extended_depth - 1 - NULL_MOVE_REDUCTION,
-                -beta, -beta + 1, ply + 1, false, null_hash, false
-            );
-
-            board.white_to_move = !board.white_to_move;
-
-            if null_score >= beta {
-                return beta;
-            }
-        }
-
-        // Order moves
-        let ordered_moves = self.order_moves(board, moves, tt_move, ply);
+impl Default for SharedHistory {
+    fn default() -> Self {
+        SharedHistory::new()
+    }
+}
 
-        let mut best_score = -INFINITY;
-        let mut best_move_at_node: Option<Move> = None;
-        let mut moves_searched = 0;
+impl SearchThread {
+    /// Lazy SMP helper: iterative deepening over the depths of this
+    /// thread's skip pattern
+    fn helper_search(&mut self, board: &Board, depth: i32) {
+        let index = (self.thread_id - 1) % SKIP_SIZE.len();
+        let (size, phase) = (SKIP_SIZE[index], SKIP_PHASE[index]);
+        self.iterative_deepening(board, depth, |d| ((d + phase) / size) % 2 == 1, |_, _, _| {});
+    }
 
-        for mv in ordered_moves {
-            if self.stop_search.load(Ordering::Relaxed) {
+    /// Root-splitting worker loop: claim root moves from the shared counter
+    /// until none are left, searching each against the shared alpha. The
+    /// score of every move goes to `scores`, and its line to `lines` (the
+    /// move alone unless it was searched with an open window).
+    #[allow(clippy::too_many_arguments)]
+    fn search_root_moves(
+        &mut self, board: &Board, depth: i32, root_moves: &[Move],
+        next_move: &AtomicUsize, shared_alpha: &AtomicI32, scores: &[AtomicI32], lines: &[Mutex<Vec<Move>>]
+    ) {
+        let mut board = board.search_copy();
+        self.refresh_accumulator(&board, 0);
+        self.root_depth = depth;
+        let extension = if self.move_generator.is_in_check(&board) { CHECK_EXTENSION } else { 0 };
+        let child_depth = self.extend(0, depth, extension) - 1;
+
+        loop {
+            if self.is_stopped() {
                 break;
             }
 
-            let is_capture = board.squares[mv.to_sq] != EMPTY || mv.is_en_passant;
-            let is_quiet = !is_capture && mv.promotion == 0;
-
-            // Futility Pruning
-            if let Some(se) = static_eval {
-                if moves_searched > 0 && extended_depth <= 3 && !in_check && is_quiet {
-                    let futility_value = se + FUTILITY_MARGIN[extended_depth as usize];
-                    if futility_value <= alpha {
-                        moves_searched += 1;
-                        continue;
-                    }
-                }
+            let index = next_move.fetch_add(1, Ordering::Relaxed);
+            if index >= root_moves.len() {
+                break;
             }
 
-            // Make move
-            let undo = board.make_move(&mv);
-            let new_hash = self.zobrist.hash_position(board);
+            let mv = root_moves[index];
+            let alpha = shared_alpha.load(Ordering::Relaxed);
 
-            // Late Move Reductions
-            let score;
-            if self.use_lmr && moves_searched >= LMR_FULL_DEPTH_MOVES
-               && extended_depth >= LMR_REDUCTION_LIMIT && is_quiet && !in_check {
+            self.stack[0].current_move = mv;
+            let undo = self.make_move(&mut board, &mv, 0);
+            let new_hash = self.zobrist.hash_position(&board);
 
-                let reduction = 1 + (moves_searched as i32 / 6);
-                let reduced_depth = (extended_depth - 1 - reduction).max(1);
-
-                let mut lmr_score = -self.alphabeta(
-                    board, reduced_depth, -alpha - 1, -alpha,
-                    ply + 1, false, new_hash, true
-                );
-
-                if lmr_score > alpha {
-                    lmr_score = -self.alphabeta(
-                        board, extended_depth - 1, -beta, -alpha,
-                        ply + 1, false, new_hash, true
-                    );
-                }
-                score = lmr_score;
-            } else if moves_searched > 0 {
-                // PVS
-                let mut pvs_score = -self.alphabeta(
-                    board, extended_depth - 1, -alpha - 1, -alpha,
-                    ply + 1, false, new_hash, true
-                );
-
-                if pvs_score > alpha && pvs_score < beta {
-                    pvs_score = -self.alphabeta(
-                        board, extended_depth - 1, -beta, -alpha,
-                        ply + 1, false, new_hash, true
-                    );
-                }
-                score = pvs_score;
+            let mut score;
+            let mut open_window = true;
+            if alpha == -INFINITY {
+                score = -self.alphabeta(&mut board, child_depth, -INFINITY, INFINITY, 1, new_hash, NodeType::Pv);
             } else {
-                score = -self.alphabeta(
-                    board, extended_depth - 1, -beta, -alpha,
-                    ply + 1, false, new_hash, true
-                );
-            }
-
-            // Unmake move
-            board.unmake_move(&mv, &undo);
-
-            if score > best_score {
-                best_score = score;
-                best_move_at_node = Some(mv);
-
-                if is_root {
-                    self.best_move = Some(mv);
-                }
-            }
-
-            if score > alpha {
-                alpha = score;
-            }
-
-            if alpha >= beta {
-                if is_quiet && ply < MAX_DEPTH {
-                    self.killer_moves[ply][1] = self.killer_moves[ply][0];
-                    self.killer_moves[ply][0] = Some(mv);
-
-                    let piece = undo.moved_piece as usize;
-                    self.history[piece][mv.to_sq] += extended_depth * extended_depth;
+                // Null window against the best score found by any thread
+                score = -self.alphabeta(&mut board, child_depth, -alpha - 1, -alpha, 1, new_hash, NodeType::Cut);
+                open_window = score > alpha;
+                if open_window {
+                    score = -self.alphabeta(&mut board, child_depth, -INFINITY, -alpha, 1, new_hash, NodeType::Pv);
                 }
-                break;
             }
 
-            moves_searched += 1;
-        }
-
-        // Store in TT
-        if self.use_tt && !self.stop_search.load(Ordering::Relaxed) {
-            let flag = if best_score <= original_alpha {
-                TT_ALPHA
-            } else if best_score >= beta {
-                TT_BETA
-            } else {
-                TT_EXACT
-            };
-
-            self.tt.store(position_hash, extended_depth, best_score, flag, best_move_at_node);
-        }
-
-        best_score
-    }
-
-    fn quiescence(&mut self, board: &mut Board, mut alpha: i32, beta: i32) -> i32 {
-        self.nodes_searched += 1;
-
-        let stand_pat = evaluate(board);
-
-        if stand_pat >= beta {
-            return beta;
-        }
-
-        if stand_pat > alpha {
-            alpha = stand_pat;
-        }
-
-        let moves = self.move_generator.generate_legal_moves(board);
-
-        let mut captures: Vec<Move> = moves.into_iter()
-            .filter(|m| board.squares[m.to_sq] != EMPTY || m.is_en_passant || m.promotion != 0)
-            .collect();
-
-        captures.sort_by_key(|m| -evaluate_move(board, m));
-
-        for mv in captures {
-            if self.stop_search.load(Ordering::Relaxed) {
-                break;
-            }
-
-            let undo = board.make_move(&mv);
-            let score = -self.quiescence(board, -beta, -alpha);
             board.unmake_move(&mv, &undo);
 
-            if score >= beta {
-                return beta;
-            }
-            if score > alpha {
-                alpha = score;
-            }
-        }
-
-        alpha
-    }
-
-    fn order_moves(&self, board: &Board, moves: Vec<Move>, tt_move: Option<Move>, ply: usize) -> Vec<Move> {
-        let mut scored_moves: Vec<(Move, i32)> = moves.into_iter().map(|m| {
-            let mut score = 0i32;
-
-            if Some(m) == tt_move {
-                score += 10000000;
-            }
-
-            let victim = board.squares[m.to_sq];
-            if victim != EMPTY {
-                let victim_value = PIECE_VALUES[get_piece_type(victim) as usize];
-                let attacker = board.squares[m.from_sq];
-                let attacker_value = PIECE_VALUES[get_piece_type(attacker) as usize];
-                score += 1000000 + 10 * victim_value - attacker_value;
-            }
-
-            if m.promotion != 0 {
-                score += 900000 + PIECE_VALUES[m.promotion as usize];
-            }
-
-            if ply < MAX_DEPTH {
-                if Some(m) == self.killer_moves[ply][0] {
-                    score += 800000;
-                } else if Some(m) == self.killer_moves[ply][1] {
-                    score += 700000;
-                }
-            }
-
-            let piece = board.squares[m.from_sq] as usize;
-            if piece < 32 {
-                score += self.history[piece][m.to_sq];
+            if self.is_stopped() {
+                break;
             }
 
-            (m, score)
-        }).collect();
-
-        scored_moves.sort_by(|a, b| b.1.cmp(&a.1));
-        scored_moves.into_iter().map(|(m, _)| m).collect()
-    }
-
-    fn has_big_pieces(&self, board: &Board) -> bool {
-        let color = if board.white_to_move { WHITE } else { BLACK };
+            trace_event!(debug, thread = self.thread_id, depth, mv = %mv.to_uci(), score, alpha, "root move");
 
-        for sq in 0..64 {
-            let piece = board.squares[sq];
-            if piece != EMPTY && get_piece_color(piece) == color {
-                let pt = get_piece_type(piece);
-                if pt == KNIGHT || pt == BISHOP || pt == ROOK || pt == QUEEN {
-                    return true;
-                }
+            let mut line = vec![mv];
+            if open_window {
+                line.extend_from_slice(self.stack.pv(1));
             }
+            *lines[index].lock().unwrap() = line;
+            scores[index].store(score, Ordering::Relaxed);
+            shared_alpha.fetch_max(score, Ordering::Relaxed);
         }
-        false
     }
 }
 
@@ -511,184 +180,477 @@ pub struct ParallelSearchResult {
     pub nodes: u64,
 }
 
+/// Work distribution strategy for the parallel search
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParallelMode {
+    /// Every thread searches the full tree, sharing the transposition table
+    LazySmp,
+    /// Root moves are distributed among threads with a shared alpha
+    RootSplit,
+}
+
+impl ParallelMode {
+    /// Parse the UCI combo value
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "LazySMP" => Some(ParallelMode::LazySmp),
+            "RootSplit" => Some(ParallelMode::RootSplit),
+            _ => None,
+        }
+    }
+
+    /// UCI combo value for this mode
+    pub fn name(&self) -> &'static str {
+        match self {
+            ParallelMode::LazySmp => "LazySMP",
+            ParallelMode::RootSplit => "RootSplit",
+        }
+    }
+}
+
 /// Parallel search engine using Lazy SMP
 pub struct ParallelSearchEngine {
     pub num_threads: usize,
-    tt: Arc<SharedTranspositionTable>,
+    pub parallel_mode: ParallelMode,
+    tt: Arc<TranspositionTable>,
     stop_search: Arc<AtomicBool>,
-    pub use_tt: bool,
-    pub use_null_move: bool,
-    pub use_lmr: bool,
+    pub options: SearchOptions,
+    /// Network for `options.use_nnue`, shared by all threads
+    pub network: Option<Arc<Network>>,
+    /// Restrict the search to these root moves (all legal moves when empty)
+    pub root_moves: Vec<Move>,
+    /// Share the history table between threads instead of per-thread tables
+    pub use_shared_history: bool,
+    history: Arc<SharedHistory>,
+    /// Own history tables of the threads by thread id, used when the table
+    /// is not shared. Like the shared one they are kept across searches,
+    /// halved at the start of each one and cleared for a new game.
+    thread_histories: Vec<Option<HistoryTable>>,
+    /// Nodes of all threads during a search, for live progress reports
+    shared_nodes: Arc<AtomicU64>,
+    /// Depth, node and time limits of the next search (consumed by it)
+    pub limits: SearchLimits,
+    /// Stop flag of the next search alone, used in place of the engine's
+    /// own (consumed by it). It is not cleared when the search starts, so a
+    /// stop raised before then still applies.
+    pub search_stop: Option<Arc<AtomicBool>>,
+    /// Progress through the root moves (Lazy SMP only)
+    pub on_current_move: Option<CurrentMoveCallback>,
+    /// Receives the progress of every search; root moves are sent without
+    /// the delay of `on_current_move`, and `SearchEvent::Finished` is left
+    /// to the caller
+    pub events: Option<mpsc::Sender<SearchEvent>>,
     pub nodes_searched: u64,
+    /// Depth of the last iteration the main thread completed
+    pub depth: i32,
     pub best_move: Option<Move>,
     pub pv: Vec<Move>,
+    /// Refuting lines of root moves that failed low in the last search
+    /// (Lazy SMP main thread only, when `options.collect_refutations` is set)
+    pub refutations: Vec<(Move, Vec<Move>)>,
     search_start_time: std::time::Instant,
 }
 
+/// Thread count used for Threads = 0: one per physical core (hyperthreads
+/// add little to an alpha-beta search), minus one when `leave_one_free` so
+/// pondering leaves the machine usable
+pub fn auto_thread_count(leave_one_free: bool) -> usize {
+    let cores = num_cpus::get_physical().max(1);
+    if leave_one_free && cores > 1 { cores - 1 } else { cores }
+}
+
 impl ParallelSearchEngine {
+    /// `num_threads` = 0 picks the count automatically
     pub fn new(tt_size_mb: usize, num_threads: usize) -> Self {
-        let threads = if num_threads == 0 { num_cpus::get() } else { num_threads };
-        
+        let threads = if num_threads == 0 { auto_thread_count(false) } else { num_threads };
+
         ParallelSearchEngine {
             num_threads: threads.max(1),
-            tt: Arc::new(SharedTranspositionTable::new(tt_size_mb)),
+            parallel_mode: ParallelMode::LazySmp,
+            tt: Arc::new(TranspositionTable::with_threads(tt_size_mb, threads.max(1))),
             stop_search: Arc::new(AtomicBool::new(false)),
-            use_tt: true,
-            use_null_move: true,
-            use_lmr: true,
+            options: SearchOptions::default(),
+            network: None,
+            root_moves: Vec::new(),
+            use_shared_history: false,
+            history: Arc::new(SharedHistory::new()),
+            thread_histories: Vec::new(),
+            shared_nodes: Arc::new(AtomicU64::new(0)),
+            search_stop: None,
+            limits: SearchLimits::default(),
+            on_current_move: None,
+            events: None,
             nodes_searched: 0,
+            depth: 0,
             best_move: None,
             pv: Vec::new(),
+            refutations: Vec::new(),
             search_start_time: std::time::Instant::now(),
         }
     }
 
     /// Search with multiple threads
-    pub fn search<F>(&mut self, board: &Board, depth: i32, mut info_callback: Option<F>)
+    pub fn search<F>(&mut self, board: &Board, depth: i32, info_callback: Option<F>)
         -> (Option<Move>, i32)
-    where F: FnMut(i32, i32, u64, u64, &str, usize, u64)
+    where F: FnMut(i32, i32, ScoreBound, u64, u64, &str, usize, u64)
+    {
+        let engine_stop = match self.search_stop.take() {
+            Some(flag) => Some(std::mem::replace(&mut self.stop_search, flag)),
+            None => {
+                self.stop_search.store(false, Ordering::SeqCst);
+                None
+            }
+        };
+
+        let result = self.search_threads(board, depth, info_callback);
+
+        if let Some(flag) = engine_stop {
+            self.stop_search = flag;
+        }
+        result
+    }
+
+    fn search_threads<F>(&mut self, board: &Board, depth: i32, mut info_callback: Option<F>)
+        -> (Option<Move>, i32)
+    where F: FnMut(i32, i32, ScoreBound, u64, u64, &str, usize, u64)
     {
-        self.stop_search.store(false, Ordering::SeqCst);
         self.nodes_searched = 0;
+        self.shared_nodes.store(0, Ordering::Relaxed);
+        self.depth = 0;
         self.best_move = None;
         self.pv.clear();
+        self.refutations.clear();
         self.search_start_time = std::time::Instant::now();
+        self.history.decay();
+        self.tt.new_search();
+        let mut limits = std::mem::take(&mut self.limits);
+        let depth = limits.depth.map_or(depth, |limit| depth.min(limit));
 
-        let tt = Arc::clone(&self.tt);
-        let stop = Arc::clone(&self.stop_search);
-        let use_tt = self.use_tt;
-        let use_null_move = self.use_null_move;
-        let use_lmr = self.use_lmr;
-        let num_threads = self.num_threads;
-
-        // Spawn helper threads (threads 1..N) - they run full search in background
-        let board_clone = board.clone();
-        let helper_handles: Vec<_> = (1..num_threads).map(|thread_id| {
-            let board = board_clone.clone();
-            let tt = Arc::clone(&tt);
-            let stop = Arc::clone(&stop);
+        if self.parallel_mode == ParallelMode::RootSplit {
+            return self.search_root_split(board, depth, limits, info_callback);
+        }
+
+        // Spawn helper threads (threads 1..N) - they run full search in background,
+        // stopping at the same node and time limits as the main thread
+        let helper_handles: Vec<_> = (1..self.num_threads).map(|thread_id| {
+            let board = board.search_copy();
+            let mut worker = self.resume_thread(thread_id);
+            worker.set_limits(&limits);
 
             thread::spawn(move || {
-                let mut worker = WorkerSearch::new(
-                    thread_id, stop, tt, use_tt, use_null_move, use_lmr
-                );
-                let result = worker.search(&board, depth);
-                (result.0, result.1, worker.nodes_searched)
+                worker.helper_search(&board, depth);
+                worker
             })
         }).collect();
 
         // Main thread (thread 0) does iterative deepening with progress reports
-        let mut main_worker = WorkerSearch::new(
-            0, Arc::clone(&stop), Arc::clone(&tt), use_tt, use_null_move, use_lmr
-        );
+        let mut main_worker = self.resume_thread(0);
+        main_worker.set_limits(&limits);
+        let start_time = self.search_start_time;
+        let tt = Arc::clone(&self.tt);
 
-        let position_hash = main_worker.zobrist.hash_position(board);
-        let mut best_move = None;
-        let mut best_score = -INFINITY;
+        // The main worker searches on its own thread and sends its progress
+        // here, so best-move changes are reported while an iteration runs
+        let (reports, received) = mpsc::channel();
+        main_worker.reports = Some(reports);
+        let on_current_move = &mut self.on_current_move;
+        let events = &self.events;
+
+        let mut main_pv = Vec::new();
+        let mut completed_depth = 0;
+
+        let (best_move, best_score) = thread::scope(|scope| {
+            let worker = scope.spawn(|| {
+                let result = main_worker.iterative_deepening(board, depth, |_| false, |thread, d, score| {
+                    main_pv = thread.stack.pv(0).to_vec();
+                    completed_depth = d;
+                    thread.report(d, score, ScoreBound::Exact, main_pv.clone());
+
+                    if !limits.continue_after(score, thread.fail_lows, thread.best_move) {
+                        thread.stop_search.store(true, Ordering::SeqCst);
+                    }
+                });
+                main_worker.reports = None;
+                result
+            });
+
+            for report in received {
+                let time_ms = start_time.elapsed().as_millis() as u64;
+                match report {
+                    SearchReport::Line { depth, score, bound, nodes, pv } => {
+                        if let Some(ref mut cb) = info_callback {
+                            let nps = (nodes * 1000).checked_div(time_ms).unwrap_or(0);
+                            let pv: Vec<String> = pv.iter().map(|m| m.to_uci()).collect();
+                            cb(depth, score, bound, nodes, time_ms, &pv.join(" "), tt.hashfull(), nps);
+                        }
+                        if let Some(events) = events {
+                            let _ = events.send(SearchEvent::NewPv { depth, score, bound, nodes, time_ms, pv });
+                        }
+                    }
+                    SearchReport::CurrentMove { depth, mv, number } => {
+                        if let Some(cb) = on_current_move.as_mut().filter(|_| time_ms >= CURRMOVE_DELAY_MS) {
+                            cb(depth, mv, number);
+                        }
+                        if let Some(events) = events {
+                            let _ = events.send(SearchEvent::CurrMove { depth, mv, number });
+                        }
+                    }
+                    SearchReport::Iteration { depth } => {
+                        if let Some(events) = events {
+                            let _ = events.send(SearchEvent::NewDepth(depth));
+                        }
+                    }
+                }
+            }
 
-        // Initial search at depth 1
-        let mut temp_board = board.clone();
-        let score = main_worker.alphabeta(&mut temp_board, 1, -INFINITY, INFINITY, 0, true, position_hash, true);
-        if main_worker.best_move.is_some() {
-            best_move = main_worker.best_move;
-            best_score = score;
-            
-            // Report depth 1
-            if let Some(ref mut cb) = info_callback {
-                let elapsed = self.search_start_time.elapsed();
-                let time_ms = elapsed.as_millis() as u64;
-                let nps = if time_ms > 0 { (main_worker.nodes_searched * 1000) / time_ms } else { 0 };
-                let hashfull = self.tt.hashfull();
-                let pv_str = best_move.map(|m| m.to_uci()).unwrap_or_default();
-                cb(1, score, main_worker.nodes_searched, time_ms, &pv_str, hashfull, nps);
+            worker.join().expect("main search thread panicked")
+        });
+
+        // Stop helper threads
+        self.stop_search.store(true, Ordering::SeqCst);
+
+        // Helpers only fill the hash table: they skip depths and are stopped
+        // mid-iteration, so their results are not comparable with the main
+        // thread's last completed iteration
+        let mut total_nodes = main_worker.nodes_searched;
+        for handle in helper_handles {
+            if let Ok(worker) = handle.join() {
+                total_nodes += worker.nodes_searched;
+                self.keep_history(worker);
             }
         }
 
-        // Iterative deepening with progress reports
-        for current_depth in 2..=depth {
-            if self.stop_search.load(Ordering::Relaxed) {
-                break;
+        self.nodes_searched = total_nodes;
+        self.depth = completed_depth;
+        self.best_move = best_move;
+        self.refutations = std::mem::take(&mut main_worker.refutations);
+        self.keep_history(main_worker);
+
+        // The main line of the last completed iteration, unless an
+        // interrupted iteration changed the move
+        if let Some(mv) = best_move {
+            if main_pv.first() == Some(&mv) {
+                self.pv = main_pv;
+            } else {
+                self.pv.push(mv);
             }
+        }
 
-            let mut alpha = best_score - ASPIRATION_WINDOW;
-            let mut beta = best_score + ASPIRATION_WINDOW;
+        (best_move, best_score)
+    }
 
-            loop {
-                let mut temp_board = board.clone();
-                let score = main_worker.alphabeta(
-                    &mut temp_board, current_depth, alpha, beta,
-                    0, true, position_hash, true
-                );
+    /// Iterative deepening where each depth distributes the root moves among
+    /// all threads (dynamic work stealing via a shared move counter)
+    fn search_root_split<F>(&mut self, board: &Board, depth: i32, mut limits: SearchLimits,
+                            mut info_callback: Option<F>) -> (Option<Move>, i32)
+    where F: FnMut(i32, i32, ScoreBound, u64, u64, &str, usize, u64)
+    {
+        let mut workers: Vec<SearchThread> = (0..self.num_threads)
+            .map(|thread_id| self.resume_thread(thread_id))
+            .collect();
+        // Every worker checks the limits, since worker 0 may run out of root
+        // moves first
+        for worker in workers.iter_mut() {
+            worker.set_limits(&limits);
+        }
 
-                if self.stop_search.load(Ordering::Relaxed) {
-                    break;
-                }
+        let mut moves = workers[0].move_generator.generate_legal_moves(board);
+        if !self.root_moves.is_empty() {
+            moves.retain(|mv| self.root_moves.contains(mv));
+        }
+        workers[0].order_moves(board, &mut moves, None, 0);
+        let mut root_moves = moves;
 
-                if score <= alpha {
-                    alpha = -INFINITY;
-                } else if score >= beta {
-                    beta = INFINITY;
-                } else {
-                    break;
-                }
+        let mut best_move = None;
+        let mut best_score = -INFINITY;
+        let mut best_line = Vec::new();
+
+        for current_depth in 1..=depth {
+            if root_moves.is_empty() || self.stop_search.load(Ordering::Relaxed) {
+                break;
+            }
+
+            if let Some(ref events) = self.events {
+                let _ = events.send(SearchEvent::NewDepth(current_depth));
             }
 
-            if !self.stop_search.load(Ordering::Relaxed) && main_worker.best_move.is_some() {
-                best_move = main_worker.best_move;
-                best_score = main_worker.alphabeta(
-                    &mut board.clone(), current_depth, -INFINITY, INFINITY,
-                    0, true, position_hash, true
-                );
-
-                // Report progress after each depth
-                if let Some(ref mut cb) = info_callback {
-                    let elapsed = self.search_start_time.elapsed();
-                    let time_ms = elapsed.as_millis() as u64;
-                    let nps = if time_ms > 0 { (main_worker.nodes_searched * 1000) / time_ms } else { 0 };
-                    let hashfull = self.tt.hashfull();
-                    let pv_str = best_move.map(|m| m.to_uci()).unwrap_or_default();
-                    cb(current_depth, best_score, main_worker.nodes_searched, time_ms, &pv_str, hashfull, nps);
+            let next_move = AtomicUsize::new(0);
+            let shared_alpha = AtomicI32::new(-INFINITY);
+            let scores: Vec<AtomicI32> = root_moves.iter().map(|_| AtomicI32::new(-INFINITY)).collect();
+            let lines: Vec<Mutex<Vec<Move>>> = root_moves.iter().map(|_| Mutex::new(Vec::new())).collect();
+
+            thread::scope(|scope| {
+                for worker in workers.iter_mut() {
+                    let (root_moves, next_move, shared_alpha, scores, lines) =
+                        (&root_moves, &next_move, &shared_alpha, &scores, &lines);
+                    scope.spawn(move || {
+                        worker.search_root_moves(board, current_depth, root_moves, next_move, shared_alpha,
+                                                 scores, lines);
+                    });
                 }
+            });
+
+            // A partially searched iteration is discarded
+            if self.stop_search.load(Ordering::Relaxed) {
+                break;
             }
-        }
 
-        // Stop helper threads
-        self.stop_search.store(true, Ordering::SeqCst);
+            // Best move first; the stable sort keeps the previous order among equal scores
+            let mut scored: Vec<(Move, i32, Vec<Move>)> = root_moves.iter()
+                .zip(&scores)
+                .zip(lines)
+                .map(|((mv, score), line)| (*mv, score.load(Ordering::Relaxed), line.into_inner().unwrap()))
+                .collect();
+            scored.sort_by_key(|&(_, score, _)| -score);
+            root_moves = scored.iter().map(|&(mv, _, _)| mv).collect();
+
+            let (mv, score, line) = scored.swap_remove(0);
+            best_move = Some(mv);
+            best_score = score;
+            best_line = line;
+            self.depth = current_depth;
 
-        // Collect results from helper threads
-        let mut total_nodes = main_worker.nodes_searched;
-        for handle in helper_handles {
-            if let Ok((mv, score, nodes)) = handle.join() {
-                total_nodes += nodes;
-                // If a helper found a better score, use it
-                if score > best_score && mv.is_some() {
-                    best_move = mv;
-                    best_score = score;
-                }
+            if let Some(ref mut cb) = info_callback {
+                let nodes: u64 = workers.iter().map(|w| w.nodes_searched).sum();
+                let elapsed = self.search_start_time.elapsed();
+                let time_ms = elapsed.as_millis() as u64;
+                let nps = (nodes * 1000).checked_div(time_ms).unwrap_or(0);
+                let hashfull = self.tt.hashfull();
+                let pv: Vec<String> = best_line.iter().map(|m| m.to_uci()).collect();
+                cb(current_depth, best_score, ScoreBound::Exact, nodes, time_ms, &pv.join(" "), hashfull, nps);
+            }
+            if let Some(ref events) = self.events {
+                let nodes = workers.iter().map(|w| w.nodes_searched).sum();
+                let time_ms = self.search_start_time.elapsed().as_millis() as u64;
+                let pv = best_line.clone();
+                let _ = events.send(SearchEvent::NewPv { depth: current_depth, score: best_score,
+                                                         bound: ScoreBound::Exact, nodes, time_ms, pv });
+            }
+
+            if !limits.continue_after(best_score, 0, best_move) {
+                break;
             }
         }
 
-        self.nodes_searched = total_nodes;
+        self.nodes_searched = workers.iter().map(|w| w.nodes_searched).sum();
         self.best_move = best_move;
-
-        // Extract PV
-        if let Some(mv) = best_move {
-            self.pv.push(mv);
+        self.pv = best_line;
+        for worker in workers {
+            self.keep_history(worker);
         }
 
         (best_move, best_score)
     }
 
+    /// Create the search state for one thread of a search, continuing from
+    /// the history table the same thread left in the previous one
+    fn resume_thread(&mut self, thread_id: usize) -> SearchThread {
+        let mut thread = self.new_thread(thread_id);
+        if let Some(history) = self.thread_histories.get_mut(thread_id).and_then(Option::take) {
+            thread.resume_history(history);
+        }
+        thread
+    }
+
+    /// Keep the history table of a finished search thread for the next
+    /// search
+    fn keep_history(&mut self, thread: SearchThread) {
+        let thread_id = thread.thread_id;
+        if self.thread_histories.len() <= thread_id {
+            self.thread_histories.resize_with(thread_id + 1, || None);
+        }
+        self.thread_histories[thread_id] = Some(thread.into_history());
+    }
+
+    /// Create the search state for one thread
+    fn new_thread(&self, thread_id: usize) -> SearchThread {
+        let shared_history = if self.use_shared_history {
+            Some(Arc::clone(&self.history))
+        } else {
+            None
+        };
+
+        let mut thread = SearchThread::new(
+            thread_id, Arc::clone(&self.stop_search), Arc::clone(&self.tt),
+            self.options, shared_history
+        );
+        thread.root_moves = self.root_moves.clone();
+        thread.network = self.network.clone();
+        thread.shared_nodes = Some(Arc::clone(&self.shared_nodes));
+        thread
+    }
+
+    /// Score every legal root move with a fixed-depth full-window search
+    /// (`depth` 0 or 1 = quiescence after the move), best first
+    pub fn score_root_moves(&mut self, board: &Board, depth: i32) -> Vec<(Move, i32)> {
+        self.stop_search.store(false, Ordering::SeqCst);
+        let mut thread = self.new_thread(0);
+        let scores = thread.score_root_moves(board, depth);
+        self.nodes_searched = thread.nodes_searched;
+        scores
+    }
+
+    /// Static and quiescence evaluation (side to move) of every position,
+    /// spread over all threads
+    pub fn evaluate_positions(&mut self, boards: &[Board]) -> Vec<(i32, i32)> {
+        self.stop_search.store(false, Ordering::SeqCst);
+        let chunk_size = boards.len().div_ceil(self.num_threads).max(1);
+
+        let results: Vec<Vec<(i32, i32)>> = thread::scope(|scope| {
+            let handles: Vec<_> = boards.chunks(chunk_size).enumerate().map(|(thread_id, chunk)| {
+                let mut worker = self.new_thread(thread_id);
+                scope.spawn(move || {
+                    chunk.iter().map(|board| {
+                        let mut board = board.clone();
+                        worker.refresh_accumulator(&board, 0);
+                        let static_eval = worker.evaluate(&board);
+                        let qsearch_eval = worker.quiescence(&mut board, -INFINITY, INFINITY, 0);
+                        (static_eval, qsearch_eval)
+                    }).collect()
+                })
+            }).collect();
+
+            handles.into_iter().map(|handle| handle.join().unwrap_or_default()).collect()
+        });
+
+        results.into_iter().flatten().collect()
+    }
+
     pub fn stop(&self) {
         self.stop_search.store(true, Ordering::SeqCst);
     }
 
+    /// The flag `stop` sets, for stopping a search running on another thread
+    pub fn stop_handle(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.stop_search)
+    }
+
+    /// Zero the TT using all configured threads. Returns once the table is
+    /// clear, so a following isready is only answered when it is usable.
     pub fn clear_tt(&self) {
-        self.tt.clear();
+        self.tt.clear_with_threads(self.num_threads);
+    }
+
+    /// Resize the TT in place, keeping every other setting. A table still
+    /// shared with another owner is replaced instead.
+    pub fn resize_tt(&mut self, size_mb: usize) {
+        if self.tt.size_mb() == size_mb {
+            return;
+        }
+        match Arc::get_mut(&mut self.tt) {
+            Some(tt) => tt.resize(size_mb, self.num_threads),
+            None => self.tt = Arc::new(TranspositionTable::with_threads(size_mb, self.num_threads)),
+        }
+    }
+
+    /// Forget the move ordering history of the previous game
+    pub fn clear_history(&mut self) {
+        self.history.clear();
+        self.thread_histories.clear();
     }
 
     pub fn set_threads(&mut self, threads: usize) {
-        self.num_threads = if threads == 0 { num_cpus::get() } else { threads.max(1) };
+        self.num_threads = if threads == 0 { auto_thread_count(false) } else { threads };
     }
 }
 
@@ -0,0 +1,331 @@
+//! OpusChess - Perft Module
+//!
+//! This module provides move generation verification tools: perft node
+//! counting, per-move divide output, and an automated comparison against an
+//! external UCI reference engine that bisects down to the first position
+//! where the two move generators disagree.
+//!
+//! Deep perft runs count the last ply in bulk (the number of legal moves)
+//! and keep the counts of subtrees in a hash table keyed by position and
+//! depth, so transpositions are only counted once.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+use crate::board::{Board, Move};
+use crate::move_generator::MoveGenerator;
+
+/// Size of the perft hash table, and the shallowest perft that uses one
+const PERFT_HASH_MB: usize = 64;
+const PERFT_HASH_MIN_DEPTH: usize = 4;
+
+/// Node count of one subtree
+#[derive(Clone, Copy, Default)]
+struct PerftEntry {
+    key: u64,
+    depth: u8,
+    nodes: u64,
+}
+
+/// Always-replace hash table of subtree node counts, keyed by the zobrist
+/// hash of the position and the remaining depth
+pub struct PerftTable {
+    entries: Vec<PerftEntry>,
+}
+
+impl PerftTable {
+    /// Table of `size_mb` megabytes (rounded down to a power of two number
+    /// of entries); 0 disables it
+    pub fn new(size_mb: usize) -> Self {
+        let count = size_mb * 1024 * 1024 / std::mem::size_of::<PerftEntry>();
+        let count = if count == 0 { 0 } else { 1 << count.ilog2() };
+        PerftTable { entries: vec![PerftEntry::default(); count] }
+    }
+
+    fn index(&self, key: u64, depth: usize) -> usize {
+        (key ^ (depth as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)) as usize & (self.entries.len() - 1)
+    }
+
+    fn probe(&self, key: u64, depth: usize) -> Option<u64> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        let entry = self.entries[self.index(key, depth)];
+        (entry.key == key && entry.depth as usize == depth).then_some(entry.nodes)
+    }
+
+    fn store(&mut self, key: u64, depth: usize, nodes: u64) {
+        if !self.entries.is_empty() {
+            let index = self.index(key, depth);
+            self.entries[index] = PerftEntry { key, depth: depth as u8, nodes };
+        }
+    }
+}
+
+/// Table for a perft of the given depth, empty when too shallow to gain
+fn table_for(depth: usize) -> PerftTable {
+    PerftTable::new(if depth >= PERFT_HASH_MIN_DEPTH { PERFT_HASH_MB } else { 0 })
+}
+
+/// Count leaf nodes of the legal move tree to the given depth
+pub fn perft(move_generator: &MoveGenerator, board: &mut Board, depth: usize) -> u64 {
+    perft_hashed(move_generator, board, depth, &mut table_for(depth))
+}
+
+/// Perft reusing the subtree counts of `table`
+pub fn perft_hashed(move_generator: &MoveGenerator, board: &mut Board, depth: usize, table: &mut PerftTable) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+
+    let moves = move_generator.generate_legal_moves(board);
+
+    // Bulk count the last ply
+    if depth == 1 {
+        return moves.len() as u64;
+    }
+
+    let key = board.position_history.last().copied().unwrap_or_else(|| board.compute_hash());
+    if let Some(nodes) = table.probe(key, depth) {
+        return nodes;
+    }
+
+    let mut nodes = 0u64;
+    for mv in moves {
+        let undo = board.make_move(&mv);
+        nodes += perft_hashed(move_generator, board, depth - 1, table);
+        board.unmake_move(&mv, &undo);
+    }
+
+    table.store(key, depth, nodes);
+    nodes
+}
+
+/// Perft split by root move
+pub fn divide(move_generator: &MoveGenerator, board: &mut Board, depth: usize) -> Vec<(Move, u64)> {
+    let moves = move_generator.generate_legal_moves(board);
+    let mut results = Vec::with_capacity(moves.len());
+    let mut table = table_for(depth);
+
+    for mv in moves {
+        let undo = board.make_move(&mv);
+        let nodes = if depth > 1 { perft_hashed(move_generator, board, depth - 1, &mut table) } else { 1 };
+        board.unmake_move(&mv, &undo);
+        results.push((mv, nodes));
+    }
+
+    results
+}
+
+// ============================================================================
+// EPD SUITES
+// ============================================================================
+
+/// One position of a perft EPD suite with its expected node counts
+#[derive(Debug, Clone)]
+pub struct EpdPerftCase {
+    pub fen: String,
+    /// (depth, expected nodes) in file order
+    pub expected: Vec<(usize, u64)>,
+}
+
+/// Parse a suite line such as `<fen> ;D1 20 ;D2 400 ;D3 8902`.
+/// Returns None for blank lines, comments and lines without D opcodes.
+pub fn parse_epd_line(line: &str) -> Option<EpdPerftCase> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    let mut fields = line.split(';');
+    let fen = fields.next()?.trim().to_string();
+
+    let expected: Vec<(usize, u64)> = fields
+        .filter_map(|field| {
+            let mut tokens = field.split_whitespace();
+            let depth = tokens.next()?.strip_prefix('D')?.parse().ok()?;
+            let nodes = tokens.next()?.parse().ok()?;
+            Some((depth, nodes))
+        })
+        .collect();
+
+    if expected.is_empty() {
+        None
+    } else {
+        Some(EpdPerftCase { fen, expected })
+    }
+}
+
+// ============================================================================
+// REFERENCE ENGINE
+// ============================================================================
+
+/// External UCI engine used as a perft oracle.
+///
+/// The engine must understand `go perft <depth>` and answer in the
+/// Stockfish divide format (`e2e4: 20` lines followed by `Nodes searched: N`).
+pub struct ReferenceEngine {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl ReferenceEngine {
+    /// Spawn the engine and complete the UCI handshake
+    pub fn spawn(path: &str) -> io::Result<Self> {
+        let mut child = Command::new(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        let stdin = child.stdin.take().ok_or_else(|| io::Error::other("no stdin"))?;
+        let stdout = child.stdout.take().ok_or_else(|| io::Error::other("no stdout"))?;
+
+        let mut engine = ReferenceEngine {
+            child,
+            stdin,
+            stdout: BufReader::new(stdout),
+        };
+
+        engine.send("uci")?;
+        engine.wait_for("uciok")?;
+
+        Ok(engine)
+    }
+
+    fn send(&mut self, command: &str) -> io::Result<()> {
+        writeln!(self.stdin, "{}", command)?;
+        self.stdin.flush()
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut line = String::new();
+        if self.stdout.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "reference engine exited"));
+        }
+        Ok(line.trim().to_string())
+    }
+
+    fn wait_for(&mut self, token: &str) -> io::Result<()> {
+        loop {
+            if self.read_line()? == token {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Run divide on `fen` after playing `moves` (UCI notation)
+    pub fn divide(&mut self, fen: &str, moves: &[String], depth: usize) -> io::Result<HashMap<String, u64>> {
+        let mut position = format!("position fen {}", fen);
+        if !moves.is_empty() {
+            position.push_str(" moves ");
+            position.push_str(&moves.join(" "));
+        }
+        self.send(&position)?;
+        self.send(&format!("go perft {}", depth))?;
+
+        let mut counts = HashMap::new();
+        loop {
+            let line = self.read_line()?;
+            if line.starts_with("Nodes searched") {
+                return Ok(counts);
+            }
+            if let Some((mv, nodes)) = line.split_once(':') {
+                let mv = mv.trim();
+                if (4..=5).contains(&mv.len()) {
+                    if let Ok(n) = nodes.trim().parse::<u64>() {
+                        counts.insert(mv.to_string(), n);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Drop for ReferenceEngine {
+    fn drop(&mut self) {
+        if self.send("quit").is_err() {
+            let _ = self.child.kill();
+        }
+        let _ = self.child.wait();
+    }
+}
+
+// ============================================================================
+// COMPARISON
+// ============================================================================
+
+/// Outcome of a perft comparison against the reference engine
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PerftDiff {
+    /// Both generators agree at every root move
+    Match,
+    /// We generate a move the reference does not consider legal
+    ExtraMove { path: Vec<String>, mv: String },
+    /// We fail to generate a move the reference considers legal
+    MissingMove { path: Vec<String>, mv: String },
+}
+
+/// Compare divide results with the reference engine, descending into the
+/// first move whose node count differs until the faulty position is found.
+///
+/// `report` receives one progress line per examined level.
+pub fn compare_with_reference<F>(
+    move_generator: &MoveGenerator,
+    board: &Board,
+    depth: usize,
+    reference: &mut ReferenceEngine,
+    mut report: F,
+) -> io::Result<PerftDiff>
+where F: FnMut(&str)
+{
+    let root_fen = board.to_fen();
+    let mut board = board.clone();
+    let mut path: Vec<String> = Vec::new();
+    let mut depth = depth.max(1);
+
+    loop {
+        let local = divide(move_generator, &mut board, depth);
+        let remote = reference.divide(&root_fen, &path, depth)?;
+
+        for (mv, _) in &local {
+            let uci = mv.to_uci();
+            if !remote.contains_key(&uci) {
+                report(&format!("Extra move {} in {}", uci, board.to_fen()));
+                return Ok(PerftDiff::ExtraMove { path, mv: uci });
+            }
+        }
+
+        let mut missing: Vec<&String> = remote.keys()
+            .filter(|uci| !local.iter().any(|(mv, _)| mv.to_uci() == **uci))
+            .collect();
+        if !missing.is_empty() {
+            missing.sort();
+            let uci = missing[0].clone();
+            report(&format!("Missing move {} in {}", uci, board.to_fen()));
+            return Ok(PerftDiff::MissingMove { path, mv: uci });
+        }
+
+        let mismatch = local.iter()
+            .map(|(mv, nodes)| (*mv, *nodes, remote[&mv.to_uci()]))
+            .find(|(_, ours, theirs)| ours != theirs);
+
+        match mismatch {
+            Some((mv, ours, theirs)) => {
+                report(&format!(
+                    "depth {} move {}: local {} reference {}",
+                    depth, mv.to_uci(), ours, theirs
+                ));
+                path.push(mv.to_uci());
+                board.make_move(&mv);
+                depth -= 1;
+            }
+            None => {
+                report(&format!("depth {}: all {} moves match", depth, local.len()));
+                return Ok(PerftDiff::Match);
+            }
+        }
+    }
+}
@@ -0,0 +1,381 @@
+//! OpusChess - PGN Module
+//!
+//! This module records a game as a start position and the moves played
+//! from it, and exports it in PGN export format:
+//! - the seven tag roster, plus SetUp/FEN for games not started from the
+//!   initial position and Variant for Chess960
+//! - SAN movetext with move numbers, wrapped at 80 columns, carrying the
+//!   NAG, comment and variation attached to each move
+//! - the result, read from the final position, or from the Result tag
+//!   while the position is undecided
+//!
+//! `read_games` imports the games of a PGN file. Comments, NAGs and
+//! variations of the input are skipped, so only the main line is kept.
+
+use std::fmt;
+
+use crate::board::{Board, Move, STARTING_FEN};
+use crate::move_generator::MoveGenerator;
+use crate::search::mate_distance;
+
+/// Longest movetext line
+const LINE_WIDTH: usize = 80;
+
+/// Tags written by the exporter itself rather than from `Game::tags`
+const SETUP_TAGS: [&str; 3] = ["Variant", "SetUp", "FEN"];
+
+/// Annotations attached to one move of a game
+#[derive(Clone, Debug, Default)]
+pub struct MoveNote {
+    /// Numeric annotation glyph ($2 is `?`, $4 `??`, $6 `?!`)
+    pub nag: Option<u8>,
+    pub comment: Option<String>,
+    /// Alternative line played instead of the move
+    pub variation: Vec<Move>,
+}
+
+/// A game: start position, moves played and PGN tags
+#[derive(Clone)]
+pub struct Game {
+    pub start: Board,
+    pub moves: Vec<Move>,
+    /// Notes of the moves, by move index (may be shorter than `moves`)
+    pub notes: Vec<MoveNote>,
+    /// Tags in output order, starting with the seven tag roster
+    pub tags: Vec<(String, String)>,
+}
+
+/// Why a PGN file could not be imported; games are numbered from 1
+#[derive(Clone, Debug)]
+pub enum PgnError {
+    InvalidFen { game: usize, fen: String },
+    IllegalMove { game: usize, san: String },
+}
+
+impl fmt::Display for PgnError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PgnError::InvalidFen { game, fen } => write!(f, "game {}: invalid FEN {}", game, fen),
+            PgnError::IllegalMove { game, san } => write!(f, "game {}: illegal move {}", game, san),
+        }
+    }
+}
+
+impl Default for Game {
+    fn default() -> Self {
+        Game::new(Board::new())
+    }
+}
+
+impl Game {
+    /// Game starting at `start`, with the seven tag roster left unknown
+    pub fn new(start: Board) -> Self {
+        let tags = [
+            ("Event", "?"),
+            ("Site", "?"),
+            ("Date", "????.??.??"),
+            ("Round", "?"),
+            ("White", "?"),
+            ("Black", "?"),
+            ("Result", "*"),
+        ];
+        Game {
+            start,
+            moves: Vec::new(),
+            notes: Vec::new(),
+            tags: tags.iter().map(|&(name, value)| (name.to_string(), value.to_string())).collect(),
+        }
+    }
+
+    /// Set tag `name`, replacing its value if present
+    pub fn set_tag(&mut self, name: &str, value: &str) {
+        match self.tags.iter_mut().find(|(tag, _)| tag == name) {
+            Some(tag) => tag.1 = value.to_string(),
+            None => self.tags.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    /// Value of tag `name`
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|(tag, _)| tag == name).map(|(_, value)| value.as_str())
+    }
+
+    /// Note of move `index`, created empty if missing
+    pub fn note_mut(&mut self, index: usize) -> &mut MoveNote {
+        if self.notes.len() <= index {
+            self.notes.resize(index + 1, MoveNote::default());
+        }
+        &mut self.notes[index]
+    }
+
+    /// Whether the game started from the initial position
+    pub fn from_initial_position(&self) -> bool {
+        !self.start.chess960 && self.start.to_fen() == STARTING_FEN
+    }
+
+    /// Position after all moves
+    pub fn position(&self) -> Board {
+        let mut board = self.start.clone();
+        for mv in &self.moves {
+            board.make_move(mv);
+        }
+        board
+    }
+
+    /// PGN result: "1-0", "0-1", "1/2-1/2" or "*". A finished final
+    /// position decides it, otherwise the Result tag (a resignation or an
+    /// agreed draw) does.
+    pub fn result(&self) -> &str {
+        let move_generator = MoveGenerator::new();
+        let board = self.position();
+
+        if move_generator.is_checkmate(&board) {
+            if board.white_to_move { "0-1" } else { "1-0" }
+        } else if move_generator.is_draw(&board) {
+            "1/2-1/2"
+        } else {
+            match self.tag("Result") {
+                Some(result @ ("1-0" | "0-1" | "1/2-1/2")) => result,
+                _ => "*",
+            }
+        }
+    }
+
+    /// The game in PGN export format
+    pub fn to_pgn(&self) -> String {
+        let result = self.result();
+        let mut pgn = String::new();
+
+        for (name, value) in &self.tags {
+            if name == "Result" {
+                pgn.push_str(&tag_pair(name, result));
+            } else if !SETUP_TAGS.contains(&name.as_str()) {
+                pgn.push_str(&tag_pair(name, value));
+            }
+        }
+        if self.start.chess960 {
+            pgn.push_str(&tag_pair("Variant", "Chess960"));
+        }
+        if !self.from_initial_position() {
+            pgn.push_str(&tag_pair("SetUp", "1"));
+            pgn.push_str(&tag_pair("FEN", &self.start.to_fen()));
+        }
+        pgn.push('\n');
+
+        let mut board = self.start.clone();
+        let mut tokens = Vec::new();
+        // A black move gets its own number at the start and after a note
+        let mut numbered = false;
+        for (i, mv) in self.moves.iter().enumerate() {
+            if board.white_to_move {
+                tokens.push(format!("{}.", board.fullmove_number));
+            } else if !numbered {
+                tokens.push(format!("{}...", board.fullmove_number));
+            }
+            tokens.push(mv.to_san(&board));
+            numbered = true;
+
+            if let Some(note) = self.notes.get(i) {
+                numbered = note.comment.is_none() && note.variation.is_empty();
+                if let Some(nag) = note.nag {
+                    tokens.push(format!("${}", nag));
+                }
+                if let Some(ref comment) = note.comment {
+                    tokens.push(format!("{{{}}}", comment.replace('}', ")")));
+                }
+                if !note.variation.is_empty() {
+                    let mut line = line_tokens(&board, &note.variation);
+                    line[0].insert(0, '(');
+                    if let Some(last) = line.last_mut() {
+                        last.push(')');
+                    }
+                    tokens.extend(line);
+                }
+            }
+            board.make_move(mv);
+        }
+        tokens.push(result.to_string());
+
+        let mut line = String::new();
+        for token in tokens {
+            if !line.is_empty() && line.len() + 1 + token.len() > LINE_WIDTH {
+                pgn.push_str(&line);
+                pgn.push('\n');
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&token);
+        }
+        pgn.push_str(&line);
+        pgn.push('\n');
+
+        pgn
+    }
+}
+
+/// Movetext tokens of `moves` played from `board`, with move numbers
+fn line_tokens(board: &Board, moves: &[Move]) -> Vec<String> {
+    let mut board = board.clone();
+    let mut tokens = Vec::new();
+
+    for (i, mv) in moves.iter().enumerate() {
+        if board.white_to_move {
+            tokens.push(format!("{}.", board.fullmove_number));
+        } else if i == 0 {
+            tokens.push(format!("{}...", board.fullmove_number));
+        }
+        tokens.push(mv.to_san(&board));
+        board.make_move(mv);
+    }
+
+    tokens
+}
+
+/// Score from White's point of view in PGN comment style: pawns with a
+/// sign, or `#n`/`#-n` for mates
+pub fn format_eval(score: i32) -> String {
+    match mate_distance(score) {
+        Some(plies) if score > 0 => format!("#{}", (plies + 1) / 2),
+        Some(plies) => format!("#-{}", (plies + 1) / 2),
+        None => format!("{:+.2}", score as f64 / 100.0),
+    }
+}
+
+/// Games of a PGN file, main lines only
+pub fn read_games(text: &str) -> Result<Vec<Game>, PgnError> {
+    let mut games = Vec::new();
+    let mut tags: Vec<(String, String)> = Vec::new();
+    let mut movetext = String::new();
+
+    for line in text.lines() {
+        let line = line.trim();
+        if line.starts_with('%') {
+            continue;
+        }
+        if let Some(tag) = line.strip_prefix('[') {
+            // A tag after movetext starts the next game
+            if !movetext.trim().is_empty() {
+                games.push(build_game(games.len() + 1, &tags, &movetext)?);
+                tags.clear();
+                movetext.clear();
+            }
+            if let Some(pair) = parse_tag(tag) {
+                tags.push(pair);
+            }
+        } else {
+            movetext.push_str(line);
+            movetext.push('\n');
+        }
+    }
+    if !movetext.trim().is_empty() || !tags.is_empty() {
+        games.push(build_game(games.len() + 1, &tags, &movetext)?);
+    }
+
+    Ok(games)
+}
+
+/// Name and value of a tag line, without the leading `[`
+fn parse_tag(tag: &str) -> Option<(String, String)> {
+    let tag = tag.trim_end().strip_suffix(']')?;
+    let (name, value) = tag.split_once(char::is_whitespace)?;
+    let value = value.trim().strip_prefix('"')?.strip_suffix('"')?;
+    Some((name.to_string(), value.replace("\\\"", "\"").replace("\\\\", "\\")))
+}
+
+/// Replay the main line of `movetext` from the position the tags set up
+fn build_game(number: usize, tags: &[(String, String)], movetext: &str) -> Result<Game, PgnError> {
+    let tag = |name: &str| tags.iter().find(|(tag, _)| tag == name).map(|(_, value)| value.as_str());
+
+    let mut start = match tag("FEN") {
+        Some(fen) => Board::from_fen(fen)
+            .map_err(|_| PgnError::InvalidFen { game: number, fen: fen.to_string() })?,
+        None => Board::new(),
+    };
+    if tag("Variant").is_some_and(|variant| variant.to_ascii_lowercase().contains("960")) {
+        start.chess960 = true;
+    }
+
+    let mut game = Game::new(start);
+    for (name, value) in tags {
+        game.set_tag(name, value);
+    }
+
+    let mut board = game.start.clone();
+    for token in main_line_tokens(movetext) {
+        if matches!(token.as_str(), "1-0" | "0-1" | "1/2-1/2" | "*") {
+            game.set_tag("Result", &token);
+            break;
+        }
+        let mv = board.parse_san(&token)
+            .ok_or_else(|| PgnError::IllegalMove { game: number, san: token.clone() })?;
+        board.make_move(&mv);
+        game.moves.push(mv);
+    }
+
+    Ok(game)
+}
+
+/// Move and result tokens of the main line: comments, variations, NAGs and
+/// move numbers are dropped
+fn main_line_tokens(movetext: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+    let mut variation_depth = 0;
+    let mut chars = movetext.chars();
+
+    let flush = |token: &mut String, tokens: &mut Vec<String>| {
+        // Move numbers may be glued to the move ("12.e4", "12...Nf6")
+        let text = if token.contains('.') {
+            token.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.')
+        } else {
+            token.as_str()
+        };
+        if !text.is_empty() && !text.starts_with('$') {
+            tokens.push(text.to_string());
+        }
+        token.clear();
+    };
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => {
+                flush(&mut token, &mut tokens);
+                for c in chars.by_ref() {
+                    if c == '}' {
+                        break;
+                    }
+                }
+            }
+            ';' => {
+                flush(&mut token, &mut tokens);
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '(' => {
+                flush(&mut token, &mut tokens);
+                variation_depth += 1;
+            }
+            ')' => {
+                token.clear();
+                variation_depth -= 1;
+            }
+            _ if variation_depth > 0 => {}
+            c if c.is_whitespace() => flush(&mut token, &mut tokens),
+            c => token.push(c),
+        }
+    }
+    flush(&mut token, &mut tokens);
+
+    tokens
+}
+
+/// `[Name "value"]` line, escaping quotes and backslashes
+fn tag_pair(name: &str, value: &str) -> String {
+    let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+    format!("[{} \"{}\"]\n", name, value)
+}
@@ -0,0 +1,154 @@
+//! OpusChess - Standard Algebraic Notation Module
+//!
+//! This module converts between moves and SAN, the notation used by PGN and
+//! by human readers:
+//! - `Move::to_san` writes a legal move with the minimal disambiguation and
+//!   a check or mate suffix
+//! - `Board::parse_san` reads a SAN move, tolerating missing or extra
+//!   suffixes, `0-0` castling and promotions written without `=`
+
+use crate::board::{Board, Move};
+use crate::move_generator::MoveGenerator;
+use crate::types::*;
+
+impl Move {
+    /// Standard algebraic notation of this move, which must be legal in
+    /// `board`
+    pub fn to_san(&self, board: &Board) -> String {
+        let move_generator = MoveGenerator::new();
+        let mv = *self;
+
+        let mut text = if mv.is_castling {
+            if mv.to_sq > mv.from_sq { "O-O".to_string() } else { "O-O-O".to_string() }
+        } else {
+            let piece_type = get_piece_type(board.squares[mv.from_sq]);
+            let capture = board.captured_piece(&mv) != EMPTY || mv.is_en_passant;
+            let mut text = String::new();
+
+            if piece_type == PAWN {
+                if capture {
+                    text.push(FILE_NAMES[mv.from_sq % 8] as char);
+                }
+            } else {
+                text.push(piece_to_fen(WHITE | piece_type).unwrap_or('?'));
+
+                // Disambiguate between pieces of the same type reaching the square
+                let rivals: Vec<usize> = move_generator.generate_legal_moves(board).iter()
+                    .filter(|m| m.to_sq == mv.to_sq && m.from_sq != mv.from_sq && !m.is_castling)
+                    .filter(|m| get_piece_type(board.squares[m.from_sq]) == piece_type)
+                    .map(|m| m.from_sq)
+                    .collect();
+                if !rivals.is_empty() {
+                    let same_file = rivals.iter().any(|&sq| sq % 8 == mv.from_sq % 8);
+                    let same_rank = rivals.iter().any(|&sq| sq / 8 == mv.from_sq / 8);
+                    if !same_file {
+                        text.push(FILE_NAMES[mv.from_sq % 8] as char);
+                    } else if !same_rank {
+                        text.push(RANK_NAMES[mv.from_sq / 8] as char);
+                    } else {
+                        text.push_str(&square_name(mv.from_sq));
+                    }
+                }
+            }
+
+            if capture {
+                text.push('x');
+            }
+            text.push_str(&square_name(mv.to_sq));
+
+            if mv.promotion != 0 {
+                text.push('=');
+                text.push(piece_to_fen(WHITE | mv.promotion).unwrap_or('?'));
+            }
+            text
+        };
+
+        let mut after = board.clone();
+        after.make_move(&mv);
+        if move_generator.is_in_check(&after) {
+            text.push(if move_generator.has_legal_move(&after) { '+' } else { '#' });
+        }
+
+        text
+    }
+}
+
+impl Board {
+    /// Legal move written as `san` in this position, or None when the text
+    /// is malformed, illegal or ambiguous
+    pub fn parse_san(&self, san: &str) -> Option<Move> {
+        let text = san.trim().trim_end_matches(['+', '#', '!', '?']);
+        let legal_moves = MoveGenerator::new().generate_legal_moves(self);
+
+        let castling_side = match text {
+            "O-O" | "0-0" => Some(true),
+            "O-O-O" | "0-0-0" => Some(false),
+            _ => None,
+        };
+        if let Some(king_side) = castling_side {
+            return legal_moves.into_iter()
+                .find(|m| m.is_castling && (m.to_sq > m.from_sq) == king_side);
+        }
+
+        let mut chars: Vec<char> = text.chars().collect();
+
+        // Leading piece letter; a missing one means a pawn move
+        let piece_type = match chars.first() {
+            Some(&c) if c.is_ascii_uppercase() => {
+                chars.remove(0);
+                get_piece_type(fen_to_piece(c)?)
+            }
+            _ => PAWN,
+        };
+
+        // Trailing promotion piece, with or without '='; the destination
+        // rank comes before it, so any trailing letter is a promotion
+        let mut promotion = 0;
+        if let Some(&c) = chars.last() {
+            if c.is_ascii_alphabetic() {
+                promotion = get_piece_type(fen_to_piece(c.to_ascii_uppercase())?);
+                chars.pop();
+                if chars.last() == Some(&'=') {
+                    chars.pop();
+                }
+            }
+        }
+
+        // Destination square is the last two characters
+        if chars.len() < 2 {
+            return None;
+        }
+        let to_name: String = chars.split_off(chars.len() - 2).into_iter().collect();
+        let to_sq = parse_square(&to_name)?;
+
+        if matches!(chars.last(), Some('x') | Some('-') | Some(':')) {
+            chars.pop();
+        }
+
+        // Whatever is left disambiguates the origin square
+        let mut from_file = None;
+        let mut from_rank = None;
+        for c in chars {
+            match c {
+                'a'..='h' => from_file = Some(c as usize - 'a' as usize),
+                '1'..='8' => from_rank = Some(c as usize - '1' as usize),
+                _ => return None,
+            }
+        }
+
+        let mut candidates = legal_moves.into_iter().filter(|m| {
+            !m.is_castling
+                && m.to_sq == to_sq
+                && m.promotion == promotion
+                && get_piece_type(self.squares[m.from_sq]) == piece_type
+                && from_file.is_none_or(|file| m.from_sq % 8 == file)
+                && from_rank.is_none_or(|rank| m.from_sq / 8 == rank)
+        });
+
+        let mv = candidates.next()?;
+        if candidates.next().is_some() {
+            return None;
+        }
+        Some(mv)
+    }
+}
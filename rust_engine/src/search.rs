@@ -5,27 +5,42 @@
 //! - Transposition table with Zobrist hashing
 //! - Null Move Pruning (NMP)
 //! - Late Move Reductions (LMR)
+//! - Internal Iterative Reductions (IIR)
 //! - Aspiration Windows
 //! - Futility Pruning
+//! - Razoring
+//! - ProbCut
 //! - Check Extensions
 //! - Killer/History heuristics
+//! - Staged move picking
+//!
+//! The alpha-beta implementation lives in `SearchThread`, which keeps its
+//! per-ply state in a `SearchStack` and is shared by the single-threaded
+//! `SearchEngine` and the workers of the parallel search.
+
+use std::ops::{Index, IndexMut};
+use std::sync::{mpsc, Arc};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Instant;
 
 use crate::types::*;
-use crate::board::{Board, Move};
+use crate::board::{Board, Move, PackedMove, UndoInfo};
 use crate::move_generator::MoveGenerator;
-use crate::evaluation::{evaluate, evaluate_move, PIECE_VALUES};
-use rand::prelude::*;
-use std::collections::HashMap;
+use crate::move_picker::MovePicker;
+use crate::evaluation::{evaluate, evaluate_move, see, PIECE_VALUES};
+use crate::nnue::{Accumulator, Network};
+use crate::parallel_search::SharedHistory;
+use crate::time_manager::SearchLimits;
+use crate::tt::{TranspositionTable, TT_EXACT, TT_ALPHA, TT_BETA};
+use crate::trace::{trace_event, trace_span};
+use crate::search_tree::{SearchTree, TreeBudget};
+use crate::zobrist::ZobristHash;
 
 // Constants for search
 pub const INFINITY: i32 = 100000;
 pub const MATE_SCORE: i32 = 50000;
-const MAX_DEPTH: usize = 100;
-
-// Transposition table entry types
-const TT_EXACT: u8 = 0;
-const TT_ALPHA: u8 = 1;
-const TT_BETA: u8 = 2;
+/// Hard limit on the distance from the root, including quiescence plies
+pub const MAX_PLY: usize = 128;
 
 // Null Move Pruning
 const NULL_MOVE_REDUCTION: i32 = 2;
@@ -34,390 +49,801 @@ const NULL_MOVE_REDUCTION: i32 = 2;
 const LMR_FULL_DEPTH_MOVES: usize = 4;
 const LMR_REDUCTION_LIMIT: i32 = 3;
 
+// Internal Iterative Reductions
+const IIR_MIN_DEPTH: i32 = 4;
+
 // Aspiration Windows
 const ASPIRATION_WINDOW: i32 = 50;
 
 // Futility Pruning margins
 const FUTILITY_MARGIN: [i32; 4] = [0, 200, 300, 500];
 
+// Razoring margins by depth, up to RAZOR_DEPTH
+const RAZOR_DEPTH: i32 = 3;
+const RAZOR_MARGIN: [i32; 4] = [0, 300, 450, 600];
+
+// ProbCut: minimum depth, margin over beta and depth reduction of the
+// verification search
+const PROBCUT_DEPTH: i32 = 5;
+const PROBCUT_MARGIN: i32 = 200;
+const PROBCUT_REDUCTION: i32 = 4;
+
+// Quiescence depths: the first ply also tries quiet checks, later ones only
+// captures (stored as depths in the TT, so a check-less result is never used
+// in place of one with checks)
+const QS_DEPTH_CHECKS: i32 = 0;
+const QS_DEPTH_NO_CHECKS: i32 = -1;
+
+// Extensions are measured in fractions of a ply
+pub const ONE_PLY: i32 = 4;
+
 // Check Extension
-const CHECK_EXTENSION: i32 = 1;
+pub const CHECK_EXTENSION: i32 = ONE_PLY;
+
+// Extensions stop once a path would exceed this multiple of the root depth
+const MAX_EXTENSION_FACTOR: i32 = 2;
+
+// Nodes between checks of the hard time limit, and between additions to
+// the node count shared by the threads
+const TIME_CHECK_INTERVAL: u64 = 2048;
+
+// Iterations a mate score must keep the same distance before the search
+// stops early
+const MATE_STABLE_ITERATIONS: u32 = 2;
 
-// Contempt - penalty for accepting draws
-const CONTEMPT: i32 = 25;
+// History heuristic: scores stay within +-HISTORY_MAX, and a cutoff at
+// depth d is worth HISTORY_BONUS_FACTOR * d^2 (at most HISTORY_BONUS_MAX)
+pub(crate) const HISTORY_MAX: i32 = 16384;
+const HISTORY_BONUS_FACTOR: i32 = 16;
+const HISTORY_BONUS_MAX: i32 = 1536;
+
+// Contempt - penalty for accepting draws, for the side to move at the root
+pub const DEFAULT_CONTEMPT: i32 = 25;
 
 // ============================================================================
-// ZOBRIST HASHING
+// NODE TYPES
 // ============================================================================
 
-pub struct ZobristHash {
-    piece_keys: [[u64; 64]; 32],
-    pub side_key: u64,
-    castling_keys: [u64; 16],
-    ep_keys: [u64; 9],
+/// Expected node type, used to gate pruning and reductions
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeType {
+    /// Principal variation node searched with an open window
+    Pv,
+    /// Node expected to fail high
+    Cut,
+    /// Node expected to fail low
+    All,
 }
 
-impl ZobristHash {
-    pub fn new() -> Self {
-        let mut rng = StdRng::seed_from_u64(12345);
-        
-        let mut piece_keys = [[0u64; 64]; 32];
-        for piece in 0..32 {
-            for sq in 0..64 {
-                piece_keys[piece][sq] = rng.gen();
-            }
-        }
-        
-        let side_key = rng.gen();
-        
-        let mut castling_keys = [0u64; 16];
-        for i in 0..16 {
-            castling_keys[i] = rng.gen();
-        }
-        
-        let mut ep_keys = [0u64; 9];
-        for i in 0..9 {
-            ep_keys[i] = rng.gen();
-        }
-        
-        ZobristHash { piece_keys, side_key, castling_keys, ep_keys }
-    }
-    
-    pub fn hash_position(&self, board: &Board) -> u64 {
-        let mut h = 0u64;
-        
-        for sq in 0..64 {
-            let piece = board.squares[sq];
-            if piece != EMPTY {
-                h ^= self.piece_keys[piece as usize][sq];
-            }
-        }
-        
-        if !board.white_to_move {
-            h ^= self.side_key;
-        }
-        
-        h ^= self.castling_keys[board.castling_rights as usize];
-        
-        let ep_idx = if board.en_passant_square >= 0 {
-            (board.en_passant_square as usize) % 8
-        } else {
-            8
-        };
-        h ^= self.ep_keys[ep_idx];
-        
-        h
+impl NodeType {
+    /// Expected type of a child searched with a null window
+    #[inline]
+    pub fn child(self) -> NodeType {
+        match self {
+            NodeType::Pv | NodeType::All => NodeType::Cut,
+            NodeType::Cut => NodeType::All,
+        }
     }
-}
 
-impl Default for ZobristHash {
-    fn default() -> Self {
-        ZobristHash::new()
+    /// Expected type of a child searched with the full `-beta, -alpha` window
+    #[inline]
+    pub fn full_window_child(self) -> NodeType {
+        match self {
+            NodeType::Pv => NodeType::Pv,
+            _ => self.child(),
+        }
     }
 }
 
 // ============================================================================
-// TRANSPOSITION TABLE
+// SEARCH STACK
 // ============================================================================
 
-#[derive(Clone)]
-struct TTEntry {
-    hash_key: u64,
-    depth: i32,
-    score: i32,
-    flag: u8,
-    best_move: Option<Move>,
+/// Search state of a single ply
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StackEntry {
+    /// Distance from the root
+    pub ply: usize,
+    /// Static evaluation of the node, when it was computed
+    pub static_eval: Option<i32>,
+    /// Move being searched from this node (null move during NMP)
+    pub current_move: Move,
+    /// Move that must be skipped at this node
+    pub excluded_move: Option<Move>,
+    /// Killer moves (2 per ply)
+    pub killers: [PackedMove; 2],
+    /// Length of this ply's line in the triangular PV table
+    pub pv_length: usize,
+    /// Fractional extension (below `ONE_PLY`) carried down this path
+    pub extension_credit: i32,
 }
 
-pub struct TranspositionTable {
-    table: HashMap<u64, TTEntry>,
-    size: usize,
-    mask: u64,
-    pub hits: u64,
-    pub writes: u64,
+/// Per-thread stack of search states indexed by ply, together with the
+/// triangular principal variation table
+pub struct SearchStack {
+    entries: Vec<StackEntry>,
+    pv_table: Vec<Move>,
+    /// Per-ply move ordering scratch space, reused to avoid allocating
+    move_scores: Vec<Vec<(Move, i32, usize)>>,
+    /// Per-ply quiet moves searched without a cutoff, penalized in the
+    /// history when a later move cuts off
+    quiets_tried: Vec<Vec<Move>>,
+    /// Per-ply network accumulators, kept only while NNUE evaluates
+    accumulators: Vec<Accumulator>,
 }
 
-impl TranspositionTable {
-    pub fn new(size_mb: usize) -> Self {
-        let num_entries = (size_mb * 1024 * 1024) / 50;
-        let mut size = 1usize;
-        while size * 2 <= num_entries {
-            size *= 2;
-        }
-        let mask = (size - 1) as u64;
-        
-        TranspositionTable {
-            table: HashMap::with_capacity(size),
-            size,
-            mask,
-            hits: 0,
-            writes: 0,
-        }
-    }
-    
-    fn probe(&mut self, hash_key: u64) -> Option<&TTEntry> {
-        let entry = self.table.get(&(hash_key & self.mask));
-        if let Some(e) = entry {
-            if e.hash_key == hash_key {
-                self.hits += 1;
-                return Some(e);
-            }
-        }
-        None
-    }
-    
-    fn store(&mut self, hash_key: u64, depth: i32, score: i32, flag: u8, best_move: Option<Move>) {
-        let index = hash_key & self.mask;
-        let should_replace = match self.table.get(&index) {
-            None => true,
-            Some(existing) => depth >= existing.depth || hash_key == existing.hash_key,
-        };
-        
-        if should_replace {
-            self.table.insert(index, TTEntry { hash_key, depth, score, flag, best_move });
-            self.writes += 1;
+impl SearchStack {
+    pub fn new() -> Self {
+        SearchStack {
+            entries: (0..MAX_PLY).map(|ply| StackEntry { ply, ..Default::default() }).collect(),
+            pv_table: vec![Move::null(); MAX_PLY * MAX_PLY],
+            move_scores: (0..MAX_PLY).map(|_| Vec::with_capacity(64)).collect(),
+            quiets_tried: (0..MAX_PLY).map(|_| Vec::with_capacity(64)).collect(),
+            accumulators: vec![Accumulator::default(); MAX_PLY],
         }
     }
-    
+
+    /// Reset every ply for a new search
     pub fn clear(&mut self) {
-        self.table.clear();
-        self.hits = 0;
-        self.writes = 0;
+        for entry in self.entries.iter_mut() {
+            *entry = StackEntry { ply: entry.ply, ..Default::default() };
+        }
     }
-    
-    pub fn hashfull(&self) -> usize {
-        if self.size == 0 { return 0; }
-        ((self.writes as usize * 1000) / self.size).min(1000)
+
+    /// Principal variation starting at `ply`
+    pub fn pv(&self, ply: usize) -> &[Move] {
+        let start = ply * MAX_PLY;
+        &self.pv_table[start..start + self.entries[ply].pv_length]
+    }
+
+    /// Accumulator of `ply` and a mutable one of the next ply
+    fn accumulator_pair(&mut self, ply: usize) -> (&Accumulator, &mut Accumulator) {
+        let (parents, children) = self.accumulators.split_at_mut(ply + 1);
+        (&parents[ply], &mut children[0])
+    }
+
+    /// Set the PV of `ply` to `mv` followed by the PV of the next ply
+    pub fn update_pv(&mut self, ply: usize, mv: Move) {
+        let start = ply * MAX_PLY;
+        let child_length = if ply + 1 < MAX_PLY {
+            self.entries[ply + 1].pv_length.min(MAX_PLY - 1 - ply)
+        } else {
+            0
+        };
+
+        self.pv_table[start] = mv;
+        if child_length > 0 {
+            let child_start = (ply + 1) * MAX_PLY;
+            self.pv_table.copy_within(child_start..child_start + child_length, start + 1);
+        }
+        self.entries[ply].pv_length = child_length + 1;
+    }
+}
+
+impl Default for SearchStack {
+    fn default() -> Self {
+        SearchStack::new()
+    }
+}
+
+impl Index<usize> for SearchStack {
+    type Output = StackEntry;
+
+    fn index(&self, ply: usize) -> &StackEntry {
+        &self.entries[ply]
+    }
+}
+
+impl IndexMut<usize> for SearchStack {
+    fn index_mut(&mut self, ply: usize) -> &mut StackEntry {
+        &mut self.entries[ply]
     }
 }
 
 // ============================================================================
-// SEARCH ENGINE
+// SEARCH THREAD
 // ============================================================================
 
-pub struct SearchEngine {
-    move_generator: MoveGenerator,
-    pub nodes_searched: u64,
-    pub best_move: Option<Move>,
-    max_depth: i32,
-    pub stop_search: bool,
-    
-    // Transposition table
-    tt: TranspositionTable,
-    zobrist: ZobristHash,
-    
-    // Killer moves (2 per ply)
-    killer_moves: [[Option<Move>; 2]; MAX_DEPTH],
-    
-    // History heuristic
-    history: [[i32; 64]; 32],
-    
-    // Configurable options
+/// Configurable search features
+#[derive(Clone, Copy, Debug)]
+pub struct SearchOptions {
     pub use_tt: bool,
     pub use_null_move: bool,
     pub use_lmr: bool,
-    
+    pub use_razoring: bool,
+    pub use_probcut: bool,
+    /// Evaluate with the loaded network instead of the handcrafted
+    /// evaluation (ignored while no network is loaded)
+    pub use_nnue: bool,
+    /// Try the quiet move that last refuted the opponent's previous move
+    /// right after the killers
+    pub use_countermove: bool,
+    /// Keep the refuting line of every root move that fails low
+    pub collect_refutations: bool,
+    /// Penalty (centipawns) for a draw, from the root side's point of view;
+    /// negative values make the engine seek draws
+    pub contempt: i32,
+    /// Largest random offset (centipawns) added to static evaluations, to
+    /// weaken the engine; 0 = none
+    pub eval_noise: i32,
+    /// Varies the noise between searches; a position always gets the same
+    /// offset within one search
+    pub noise_seed: u64,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        SearchOptions {
+            use_tt: true,
+            use_null_move: true,
+            use_lmr: true,
+            use_razoring: true,
+            use_probcut: true,
+            use_nnue: false,
+            use_countermove: true,
+            collect_refutations: false,
+            contempt: DEFAULT_CONTEMPT,
+            eval_noise: 0,
+            noise_seed: 0,
+        }
+    }
+}
+
+/// History entry after adding `bonus` with the gravity formula: the
+/// closer an entry is to HISTORY_MAX in the bonus' direction, the less it
+/// moves, so it never leaves the bounds and old results fade
+#[inline]
+pub(crate) fn history_gravity(entry: i32, bonus: i32) -> i32 {
+    entry + bonus - entry * bonus.abs() / HISTORY_MAX
+}
+
+/// History bonus for a cutoff at `depth`
+#[inline]
+fn history_bonus(depth: i32) -> i32 {
+    (HISTORY_BONUS_FACTOR * depth * depth).min(HISTORY_BONUS_MAX)
+}
+
+/// A thread's own move ordering statistics
+pub(crate) struct HistoryTable {
+    /// History heuristic scores by [side][from][to]
+    scores: Box<[[[i32; 64]; 64]; 2]>,
+    /// Quiet move that last cut off in reply to a move, by that move's
+    /// [from][to]
+    countermoves: Box<[[PackedMove; 64]; 64]>,
+}
+
+impl HistoryTable {
+    fn new() -> Self {
+        HistoryTable {
+            scores: Box::new([[[0; 64]; 64]; 2]),
+            countermoves: Box::new([[PackedMove::NONE; 64]; 64]),
+        }
+    }
+}
+
+/// Index of the side to move in the history tables
+#[inline]
+fn side_index(board: &Board) -> usize {
+    if board.white_to_move { 0 } else { 1 }
+}
+
+/// Distance in plies to the mate behind a mate score (None for other scores)
+pub fn mate_distance(score: i32) -> Option<i32> {
+    (score.abs() >= MATE_SCORE - MAX_PLY as i32).then(|| MATE_SCORE - score.abs())
+}
+
+/// Score to store in the TT at `ply`: mate scores are counted from the node
+/// instead of the root, so they stay right when the position is reached at
+/// another ply
+fn score_to_tt(score: i32, ply: usize) -> i32 {
+    match mate_distance(score) {
+        Some(_) if score > 0 => score + ply as i32,
+        Some(_) => score - ply as i32,
+        None => score,
+    }
+}
+
+/// Score read from the TT at `ply`, the inverse of `score_to_tt`
+fn score_from_tt(score: i32, ply: usize) -> i32 {
+    match mate_distance(score) {
+        Some(_) if score > 0 => score - ply as i32,
+        Some(_) => score + ply as i32,
+        None => score,
+    }
+}
+
+/// A legal root move with the results of its last search, which order the
+/// root moves of the next iteration
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct RootMove {
+    pub(crate) mv: Move,
+    /// Score of the last iteration, -INFINITY unless the move raised alpha
+    pub(crate) score: i32,
+    /// Nodes spent on the move's subtree in the last iteration
+    pub(crate) nodes: u64,
+}
+
+/// What a reported score is: exact, or only a bound because the search
+/// failed high or low
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScoreBound {
+    Exact,
+    Lower,
+    Upper,
+}
+
+/// Outcome of a finished search
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SearchResult {
+    pub best_move: Option<Move>,
+    /// Score of the best move from the side to move's point of view, in
+    /// internal centipawns (see `mate_distance` for mate scores)
+    pub score: i32,
+    /// Last completed iteration
+    pub depth: i32,
+    pub pv: Vec<Move>,
+    pub nodes: u64,
+}
+
+/// Progress of a search, streamed to `ParallelSearchEngine::events`
+#[derive(Clone, Debug, PartialEq)]
+pub enum SearchEvent {
+    /// The main thread started iteration `depth`
+    NewDepth(i32),
+    /// Line found by the main thread; a bound other than exact comes from
+    /// an aspiration failure
+    NewPv { depth: i32, score: i32, bound: ScoreBound, nodes: u64, time_ms: u64, pv: Vec<Move> },
+    /// A root move is about to be searched (numbered from 1)
+    CurrMove { depth: i32, mv: Move, number: usize },
+    /// The search ended; always the last event
+    Finished(SearchResult),
+}
+
+/// Progress of the search sent by a thread with a report channel
+pub(crate) enum SearchReport {
+    /// After every completed iteration, whenever a new root move becomes
+    /// best and on every aspiration failure
+    Line { depth: i32, score: i32, bound: ScoreBound, nodes: u64, pv: Vec<Move> },
+    /// A root move is about to be searched (numbered from 1)
+    CurrentMove { depth: i32, mv: Move, number: usize },
+    /// An iteration is about to start
+    Iteration { depth: i32 },
+}
+
+/// Alpha-beta search state owned by one thread
+pub(crate) struct SearchThread {
+    pub(crate) move_generator: MoveGenerator,
+    pub(crate) zobrist: &'static ZobristHash,
+    pub(crate) nodes_searched: u64,
+    pub(crate) best_move: Option<Move>,
+    pub(crate) stop_search: Arc<AtomicBool>,
+    pub(crate) options: SearchOptions,
+    pub(crate) thread_id: usize,
+    pub(crate) stack: SearchStack,
+    /// Depth of the current iteration, used to budget extensions
+    pub(crate) root_depth: i32,
+    /// Root moves to search (all legal moves when empty)
+    pub(crate) root_moves: Vec<Move>,
+    /// Legal root moves searched, ranked by the last completed iteration
+    root_list: Vec<RootMove>,
+    /// Explored tree, recorded only when debugging
+    pub(crate) tree: Option<SearchTree>,
+    /// Hard time limit; the thread stops every thread once it passes
+    pub(crate) deadline: Option<Instant>,
+    /// Node budget of all threads together (counted through `shared_nodes`
+    /// when set); the thread stops every thread once it is spent
+    pub(crate) node_limit: Option<u64>,
+    /// Where to send progress reports (main thread only)
+    pub(crate) reports: Option<mpsc::Sender<SearchReport>>,
+    /// Nodes of all threads of a parallel search, which every thread adds
+    /// its own to in steps of TIME_CHECK_INTERVAL
+    pub(crate) shared_nodes: Option<Arc<AtomicU64>>,
+    /// Aspiration fail-lows in the current iteration
+    pub(crate) fail_lows: u32,
+    /// Best fully searched root move of the current iteration that raised
+    /// alpha, with its score
+    root_best: Option<(Move, i32)>,
+    /// Latest refutation (reply and continuation) of each root move that
+    /// failed low, when `collect_refutations` is set
+    pub(crate) refutations: Vec<(Move, Vec<Move>)>,
+    tt: Arc<TranspositionTable>,
+    /// Network used when `options.use_nnue` is set
+    pub(crate) network: Option<Arc<Network>>,
+
+    // History heuristic and countermoves
+    history: HistoryTable,
+    shared_history: Option<Arc<SharedHistory>>,
+
     // Statistics
     tt_cutoffs: u64,
     null_move_cutoffs: u64,
     futility_prunes: u64,
-    
-    // PV
-    pub pv: Vec<Move>,
-    search_start_time: std::time::Instant,
 }
 
-impl SearchEngine {
-    pub fn new(tt_size_mb: usize) -> Self {
-        SearchEngine {
+impl SearchThread {
+    pub(crate) fn new(
+        thread_id: usize,
+        stop_search: Arc<AtomicBool>,
+        tt: Arc<TranspositionTable>,
+        options: SearchOptions,
+        shared_history: Option<Arc<SharedHistory>>,
+    ) -> Self {
+        SearchThread {
             move_generator: MoveGenerator::new(),
+            zobrist: ZobristHash::get(),
             nodes_searched: 0,
             best_move: None,
-            max_depth: 4,
-            stop_search: false,
-            tt: TranspositionTable::new(tt_size_mb),
-            zobrist: ZobristHash::new(),
-            killer_moves: [[None; 2]; MAX_DEPTH],
-            history: [[0; 64]; 32],
-            use_tt: true,
-            use_null_move: true,
-            use_lmr: true,
+            stop_search,
+            options,
+            thread_id,
+            stack: SearchStack::new(),
+            root_depth: 0,
+            root_moves: Vec::new(),
+            root_list: Vec::new(),
+            tree: None,
+            deadline: None,
+            node_limit: None,
+            reports: None,
+            shared_nodes: None,
+            fail_lows: 0,
+            root_best: None,
+            refutations: Vec::new(),
+            tt,
+            network: None,
+            history: HistoryTable::new(),
+            shared_history,
             tt_cutoffs: 0,
             null_move_cutoffs: 0,
             futility_prunes: 0,
-            pv: Vec::new(),
-            search_start_time: std::time::Instant::now(),
         }
     }
-    
-    /// Search with aspiration windows
-    pub fn search<F>(&mut self, board: &Board, depth: i32, mut info_callback: Option<F>) 
+
+    #[inline]
+    pub(crate) fn is_stopped(&self) -> bool {
+        self.stop_search.load(Ordering::Relaxed)
+    }
+
+    /// Score of a draw for the side to move at `ply`: the contempt
+    /// penalizes the root side, so its opponent sees it as a bonus
+    #[inline]
+    fn draw_score(&self, ply: usize) -> i32 {
+        if ply.is_multiple_of(2) { -self.options.contempt } else { self.options.contempt }
+    }
+
+    /// Network evaluating positions, if NNUE is enabled and one is loaded
+    #[inline]
+    fn active_network(&self) -> Option<&Network> {
+        self.network.as_deref().filter(|_| self.options.use_nnue)
+    }
+
+    /// Static evaluation (side to move): the network's when enabled and
+    /// loaded, else the handcrafted one
+    pub(crate) fn evaluate(&self, board: &Board) -> i32 {
+        match self.active_network() {
+            Some(network) => network.evaluate(board),
+            None => evaluate(board),
+        }
+    }
+
+    /// Static evaluation of the position at `ply`, plus the configured
+    /// noise; the network reads the accumulator kept on the stack
+    #[inline]
+    fn static_eval(&self, board: &Board, ply: usize) -> i32 {
+        let eval = match self.active_network() {
+            Some(network) => network.evaluate_accumulator(&self.stack.accumulators[ply], board.white_to_move),
+            None => evaluate(board),
+        };
+        if self.options.eval_noise == 0 {
+            return eval;
+        }
+
+        // Derived from the position hash, so transpositions agree
+        let hash = board.position_history.last().copied().unwrap_or(0) ^ self.options.noise_seed;
+        let spread = 2 * self.options.eval_noise as u64 + 1;
+        let offset = (hash.wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 32) % spread;
+        eval + offset as i32 - self.options.eval_noise
+    }
+
+    /// Compute the accumulator of `ply` from the whole board, before
+    /// searching from `board` at that ply
+    pub(crate) fn refresh_accumulator(&mut self, board: &Board, ply: usize) {
+        if let Some(network) = self.network.as_deref().filter(|_| self.options.use_nnue) {
+            network.refresh(board, &mut self.stack.accumulators[ply]);
+        }
+    }
+
+    /// Make `mv` at `ply`, updating the accumulator of the next ply from
+    /// this one. Unmaking needs no update: the parent's is still in place.
+    pub(crate) fn make_move(&mut self, board: &mut Board, mv: &Move, ply: usize) -> UndoInfo {
+        let undo = board.make_move(mv);
+        if let Some(network) = self.network.as_deref().filter(|_| self.options.use_nnue) {
+            let (parent, child) = self.stack.accumulator_pair(ply);
+            network.update(board, mv, &undo, parent, child);
+        }
+        undo
+    }
+
+    /// Make this thread enforce the node budget and hard time limit of
+    /// `limits`
+    pub(crate) fn set_limits(&mut self, limits: &SearchLimits) {
+        self.node_limit = limits.nodes;
+        self.deadline = limits.deadline();
+    }
+
+    /// Count a node and abort the search once a limit is reached
+    #[inline]
+    fn count_node(&mut self) {
+        self.nodes_searched += 1;
+        if let Some(shared_nodes) = self.shared_nodes.as_ref()
+            .filter(|_| self.nodes_searched.is_multiple_of(TIME_CHECK_INTERVAL)) {
+            shared_nodes.fetch_add(TIME_CHECK_INTERVAL, Ordering::Relaxed);
+        }
+        if self.limit_reached() {
+            self.stop_search.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Whether the node budget is spent or the hard time limit passed (the
+    /// clock is only read every TIME_CHECK_INTERVAL nodes)
+    #[inline]
+    fn limit_reached(&self) -> bool {
+        if self.node_limit.is_some_and(|limit| self.all_nodes() >= limit) {
+            return true;
+        }
+        self.nodes_searched.is_multiple_of(TIME_CHECK_INTERVAL)
+            && self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// Iterative deepening with aspiration windows up to `depth`, leaving
+    /// out the depths `skip_depth` holds true for (Lazy SMP helpers; depth 1
+    /// is always searched). `on_depth` is called with the depth and score
+    /// after every completed iteration.
+    pub(crate) fn iterative_deepening<S, F>(&mut self, board: &Board, depth: i32, skip_depth: S, mut on_depth: F)
         -> (Option<Move>, i32)
-    where F: FnMut(i32, i32, u64, u64, &str, usize, u64)
+    where S: Fn(i32) -> bool, F: FnMut(&SearchThread, i32, i32)
     {
         self.nodes_searched = 0;
         self.best_move = None;
-        self.max_depth = depth;
-        self.stop_search = false;
         self.tt_cutoffs = 0;
         self.null_move_cutoffs = 0;
         self.futility_prunes = 0;
-        self.pv.clear();
-        self.search_start_time = std::time::Instant::now();
-        self.killer_moves = [[None; 2]; MAX_DEPTH];
-        
+        self.stack.clear();
+        self.refutations.clear();
+
         let position_hash = self.zobrist.hash_position(board);
-        
+
+        // make/unmake leave the board unchanged, so one copy serves every
+        // iteration and re-search
+        let mut board = board.search_copy();
+        self.refresh_accumulator(&board, 0);
+        self.init_root_list(&board, position_hash);
         let mut best_move = None;
         let mut best_score = -INFINITY;
-        
+        let mut last_mate: Option<i32> = None;
+        let mut mate_stable = 0;
+
         // Initial search at depth 1
-        let mut temp_board = board.clone();
-        let score = self.alphabeta(&mut temp_board, 1, -INFINITY, INFINITY, 0, true, position_hash, true);
+        self.root_depth = 1;
+        self.report_iteration(1);
+        let score = self.alphabeta(&mut board, 1, -INFINITY, INFINITY, 0, position_hash, NodeType::Pv);
         if self.best_move.is_some() {
             best_move = self.best_move;
             best_score = score;
-            self.extract_pv(board, position_hash, 1);
-            if let Some(ref mut cb) = info_callback {
-                self.report_info(1, score, cb);
-            }
+            on_depth(self, 1, score);
         }
-        
+        self.rank_root_moves(best_move);
+
         // Iterative deepening with aspiration windows
         for current_depth in 2..=depth {
-            if self.stop_search {
+            if self.is_stopped() {
                 break;
             }
-            
-            let mut alpha = best_score - ASPIRATION_WINDOW;
-            let mut beta = best_score + ASPIRATION_WINDOW;
-            
+            if skip_depth(current_depth) {
+                continue;
+            }
+
+            self.root_depth = current_depth;
+            self.report_iteration(current_depth);
+            let _span = trace_span!("iteration", thread = self.thread_id, depth = current_depth);
+
+            let mut delta = ASPIRATION_WINDOW;
+            let mut alpha = (best_score - delta).max(-INFINITY);
+            let mut beta = (best_score + delta).min(INFINITY);
+            self.fail_lows = 0;
+            self.root_best = None;
+            for root_move in self.root_list.iter_mut() {
+                root_move.score = -INFINITY;
+                root_move.nodes = 0;
+            }
+
+            let mut score;
             loop {
-                let mut temp_board = board.clone();
-                let score = self.alphabeta(&mut temp_board, current_depth, alpha, beta, 
-                                          0, true, position_hash, true);
-                
-                if self.stop_search {
+                score = self.alphabeta(&mut board, current_depth, alpha, beta, 0, position_hash, NodeType::Pv);
+
+                if self.is_stopped() {
                     break;
                 }
-                
+
+                // Widen only the failing bound, doubling the step on every
+                // failure, and tell the GUI what is known so far
                 if score <= alpha {
-                    alpha = -INFINITY;
+                    trace_event!(debug, score, alpha, "aspiration fail low");
+                    self.fail_lows += 1;
+                    self.report(current_depth, score, ScoreBound::Upper, best_move.into_iter().collect());
+                    alpha = (score - delta).max(-INFINITY);
                 } else if score >= beta {
-                    beta = INFINITY;
+                    trace_event!(debug, score, beta, "aspiration fail high");
+                    self.report(current_depth, score, ScoreBound::Lower, self.stack.pv(0).to_vec());
+                    beta = (score + delta).min(INFINITY);
                 } else {
                     break;
                 }
+                delta *= 2;
             }
-            
-            if !self.stop_search && self.best_move.is_some() {
+
+            if self.is_stopped() {
+                // The interrupted iteration only replaces the last completed
+                // one once a root move was fully searched and raised alpha
+                if let Some((mv, score)) = self.root_best {
+                    best_move = Some(mv);
+                    best_score = score;
+                }
+                break;
+            }
+
+            if self.best_move.is_some() {
                 best_move = self.best_move;
-                best_score = self.alphabeta(&mut board.clone(), current_depth, -INFINITY, INFINITY, 
-                                           0, true, position_hash, true);
-                self.extract_pv(board, position_hash, current_depth);
-                if let Some(ref mut cb) = info_callback {
-                    self.report_info(current_depth, best_score, cb);
+                best_score = score;
+                trace_event!(debug, score = best_score, nodes = self.nodes_searched,
+                             best_move = %best_move.map(|m| m.to_uci()).unwrap_or_default(), "iteration complete");
+                on_depth(self, current_depth, best_score);
+            }
+            self.rank_root_moves(best_move);
+
+            // A found mate only needs more iterations while a shorter one
+            // could still turn up: not once the search is as deep as the
+            // mate is long, nor once the distance stopped changing
+            let mate = mate_distance(best_score);
+            mate_stable = if mate.is_some() && mate == last_mate { mate_stable + 1 } else { 0 };
+            last_mate = mate;
+            if let Some(plies) = mate {
+                if plies <= current_depth || mate_stable >= MATE_STABLE_ITERATIONS {
+                    trace_event!(debug, plies, depth = current_depth, "mate found, stopping");
+                    break;
                 }
             }
         }
-        
+
         (best_move, best_score)
     }
-    
-    fn extract_pv(&mut self, board: &Board, position_hash: u64, depth: i32) {
-        self.pv.clear();
-        
-        if !self.use_tt {
-            if let Some(mv) = self.best_move {
-                self.pv.push(mv);
-            }
-            return;
+
+    /// Legal root moves to search (restricted to `root_moves` when set), in
+    /// static order for the first iteration
+    fn init_root_list(&mut self, board: &Board, position_hash: u64) {
+        let mut moves = self.move_generator.generate_legal_moves(board);
+        if !self.root_moves.is_empty() {
+            moves.retain(|mv| self.root_moves.contains(mv));
         }
-        
-        let mut seen_hashes = std::collections::HashSet::new();
-        let mut current_hash = position_hash;
-        let mut temp_board = board.clone();
-        
-        for _ in 0..depth.min(20) {
-            if seen_hashes.contains(&current_hash) {
-                break;
-            }
-            seen_hashes.insert(current_hash);
-            
-            let entry = self.tt.table.get(&(current_hash & self.tt.mask)).cloned();
-            if let Some(e) = entry {
-                if e.hash_key == current_hash {
-                    if let Some(mv) = e.best_move {
-                        self.pv.push(mv);
-                        temp_board.make_move(&mv);
-                        current_hash = self.zobrist.hash_position(&temp_board);
-                    } else {
-                        break;
-                    }
-                } else {
-                    break;
-                }
-            } else {
-                break;
-            }
+
+        let tt_move = if self.options.use_tt {
+            self.tt.probe(position_hash).and_then(|entry| entry.best_move)
+        } else {
+            None
+        };
+        self.order_moves(board, &mut moves, tt_move, 0);
+
+        self.root_list = moves.into_iter()
+            .map(|mv| RootMove { mv, score: -INFINITY, nodes: 0 })
+            .collect();
+    }
+
+    /// Order the root moves for the next iteration: the best move first,
+    /// then the others by the size of their subtree, which tracks how hard
+    /// they were to refute. The stable sort keeps the previous order among
+    /// equals.
+    fn rank_root_moves(&mut self, best_move: Option<Move>) {
+        self.root_list.sort_by_key(|root_move| (Some(root_move.mv) != best_move, std::cmp::Reverse(root_move.nodes)));
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn alphabeta(&mut self, board: &mut Board, depth: i32, alpha: i32, beta: i32,
+                            ply: usize, position_hash: u64, node_type: NodeType) -> i32 {
+        if self.tree.is_none() {
+            return self.search_node(board, depth, alpha, beta, ply, position_hash, node_type);
+        }
+
+        let mv = if ply > 0 { Some(self.stack[ply - 1].current_move) } else { None };
+        let id = self.tree.as_mut().and_then(|tree| tree.open(ply, mv, depth, alpha, beta));
+        let score = self.search_node(board, depth, alpha, beta, ply, position_hash, node_type);
+        if let (Some(tree), Some(id)) = (self.tree.as_mut(), id) {
+            tree.close(id, score);
         }
+        score
     }
-    
-    fn report_info<F>(&self, depth: i32, score: i32, callback: &mut F)
-    where F: FnMut(i32, i32, u64, u64, &str, usize, u64)
-    {
-        let elapsed = self.search_start_time.elapsed();
-        let time_ms = elapsed.as_millis() as u64;
-        let nps = if time_ms > 0 { (self.nodes_searched * 1000) / time_ms } else { 0 };
-        let hashfull = self.tt.hashfull();
-        
-        let pv_str: String = self.pv.iter()
-            .map(|m| m.to_uci())
-            .collect::<Vec<_>>()
-            .join(" ");
-        
-        callback(depth, score, self.nodes_searched, time_ms, &pv_str, hashfull, nps);
-    }
-    
-    fn alphabeta(&mut self, board: &mut Board, depth: i32, mut alpha: i32, beta: i32,
-                 ply: usize, is_root: bool, position_hash: u64, allow_null: bool) -> i32 {
-        if self.stop_search {
+
+    /// Record why the node at `ply` ended early, when the tree is recorded
+    #[inline]
+    fn note(&mut self, ply: usize, reason: &'static str) {
+        if let Some(tree) = self.tree.as_mut() {
+            tree.note(ply, reason);
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn search_node(&mut self, board: &mut Board, depth: i32, mut alpha: i32, beta: i32,
+                   ply: usize, position_hash: u64, node_type: NodeType) -> i32 {
+        if self.is_stopped() {
             return 0;
         }
-        
-        self.nodes_searched += 1;
+
+        self.count_node();
+        let is_root = ply == 0;
+        let is_pv = node_type == NodeType::Pv;
         let original_alpha = alpha;
-        
-        // Draw detection
+        self.stack[ply].pv_length = 0;
+        self.stack[ply].static_eval = None;
+        self.stack.quiets_tried[ply].clear();
+
+        // Draw detection. Checkmate takes precedence over the fifty-move
+        // rule; a repeated position cannot be mate, as play went on from it
         if !is_root {
-            if board.is_fifty_moves() || board.is_repetition() {
-                return -CONTEMPT;
+            if board.is_fifty_moves() {
+                if self.move_generator.is_in_check(board) && !self.move_generator.has_legal_move(board) {
+                    self.note(ply, "checkmate");
+                    return -MATE_SCORE + ply as i32;
+                }
+                self.note(ply, "draw");
+                return self.draw_score(ply);
+            }
+            if board.is_repetition() {
+                self.note(ply, "draw");
+                return self.draw_score(ply);
             }
             if board.has_insufficient_material() {
-                return -CONTEMPT;
+                self.note(ply, "draw");
+                return self.draw_score(ply);
             }
             if board.repetition_count() >= 2 {
-                return -CONTEMPT * 2;
+                self.note(ply, "draw");
+                return self.draw_score(ply) * 2;
             }
         }
-        
+
+        // Out of stack space
+        if ply >= MAX_PLY - 1 {
+            self.note(ply, "max ply");
+            return self.static_eval(board, ply);
+        }
+
+        let excluded_move = self.stack[ply].excluded_move;
+
         // Probe TT
         let mut tt_move: Option<Move> = None;
-        
-        if self.use_tt {
+
+        if self.options.use_tt {
             if let Some(entry) = self.tt.probe(position_hash) {
-                if !is_root && entry.depth >= depth {
+                let tt_score = score_from_tt(entry.score, ply);
+                // PV nodes are always searched, so the triangular table holds
+                // the complete main line instead of one cut short by a hit
+                if !is_pv && excluded_move.is_none() && entry.depth >= depth {
                     match entry.flag {
                         TT_EXACT => {
                             self.tt_cutoffs += 1;
-                            return entry.score;
+                            trace_event!(trace, ply, depth, score = tt_score, "tt cutoff exact");
+                            self.note(ply, "tt cutoff");
+                            return tt_score;
                         }
-                        TT_ALPHA if entry.score <= alpha => {
+                        TT_ALPHA if tt_score <= alpha => {
                             self.tt_cutoffs += 1;
+                            trace_event!(trace, ply, depth, score = tt_score, alpha, "tt cutoff upper bound");
+                            self.note(ply, "tt cutoff");
                             return alpha;
                         }
-                        TT_BETA if entry.score >= beta => {
+                        TT_BETA if tt_score >= beta => {
                             self.tt_cutoffs += 1;
+                            trace_event!(trace, ply, depth, score = tt_score, beta, "tt cutoff lower bound");
+                            self.note(ply, "tt cutoff");
                             return beta;
                         }
                         _ => {}
@@ -426,151 +852,313 @@ impl SearchEngine {
                 tt_move = entry.best_move;
             }
         }
-        
+
         // Check detection
         let in_check = self.move_generator.is_in_check(board);
-        
+
         // Check extension
-        let extended_depth = if in_check { depth + CHECK_EXTENSION } else { depth };
-        
-        // Generate moves
-        let moves = self.move_generator.generate_legal_moves(board);
-        
-        // Checkmate / Stalemate
-        if moves.is_empty() {
-            return if in_check { -MATE_SCORE + ply as i32 } else { 0 };
+        let mut extended_depth = self.extend(ply, depth, if in_check { CHECK_EXTENSION } else { 0 });
+
+        // Internal Iterative Reductions: an expected cut node without a hash
+        // move is unlikely to be worth the full depth
+        if node_type == NodeType::Cut && tt_move.is_none() && extended_depth >= IIR_MIN_DEPTH {
+            trace_event!(trace, ply, depth = extended_depth, "iir");
+            extended_depth -= 1;
         }
-        
-        // Quiescence at leaf
+
+        // Quiescence at leaf, unless the game is over
         if extended_depth <= 0 {
-            return self.quiescence(board, alpha, beta);
+            if !self.move_generator.has_legal_move(board) {
+                self.note(ply, if in_check { "checkmate" } else { "stalemate" });
+                return if in_check { -MATE_SCORE + ply as i32 } else { 0 };
+            }
+            self.note(ply, "quiescence");
+            return self.quiescence(board, alpha, beta, ply);
         }
-        
+
         // Static evaluation for pruning
         let static_eval = if extended_depth <= 4 && !in_check && alpha.abs() < MATE_SCORE - 100 {
-            Some(evaluate(board))
+            Some(self.static_eval(board, ply))
         } else {
             None
         };
-        
-        // Null Move Pruning
-        if self.use_null_move && allow_null && !is_root && !in_check 
+        self.stack[ply].static_eval = static_eval;
+
+        // Razoring: this far below alpha at shallow depth, only a capture can
+        // save the node, so let quiescence decide (static_eval is only set
+        // when alpha is not a mate score)
+        if let Some(se) = static_eval {
+            if self.options.use_razoring && !is_pv && excluded_move.is_none()
+               && extended_depth <= RAZOR_DEPTH {
+                let margin = RAZOR_MARGIN[extended_depth as usize];
+                if se + margin <= alpha {
+                    if extended_depth == 1 {
+                        trace_event!(trace, ply, static_eval = se, alpha, "razoring");
+                        self.note(ply, "razoring");
+                        return self.quiescence(board, alpha, beta, ply);
+                    }
+
+                    let razor_alpha = alpha - margin;
+                    let score = self.quiescence(board, razor_alpha, razor_alpha + 1, ply);
+                    if score <= razor_alpha {
+                        trace_event!(trace, ply, depth = extended_depth, static_eval = se, score, alpha, "razoring");
+                        self.note(ply, "razoring");
+                        return score;
+                    }
+                }
+            }
+        }
+
+        // Null Move Pruning (never twice in a row, never in PV nodes)
+        let allow_null = !is_pv && !self.stack[ply - 1].current_move.is_null();
+        if self.options.use_null_move && allow_null && !in_check
            && extended_depth >= 3 && self.has_big_pieces(board) {
-            
+
+            // The en passant square must go as well: left in place, the side
+            // to move could "capture" its own pawn's skipped square and
+            // unmake would restore a pawn that never existed
+            let ep_square = board.en_passant_square;
             board.white_to_move = !board.white_to_move;
-            let null_hash = position_hash ^ self.zobrist.side_key;
-            
+            board.en_passant_square = -1;
+            let null_hash = position_hash ^ self.zobrist.side_key
+                ^ self.zobrist.ep_key(ep_square) ^ self.zobrist.ep_key(-1);
+            self.stack[ply].current_move = Move::null();
+            if self.active_network().is_some() {
+                let (parent, child) = self.stack.accumulator_pair(ply);
+                child.clone_from(parent);
+            }
+
             let null_score = -self.alphabeta(
                 board, extended_depth - 1 - NULL_MOVE_REDUCTION,
-                -beta, -beta + 1, ply + 1, false, null_hash, false
+                -beta, -beta + 1, ply + 1, null_hash, node_type.child()
             );
-            
+
             board.white_to_move = !board.white_to_move;
-            
+            board.en_passant_square = ep_square;
+
             if null_score >= beta {
                 self.null_move_cutoffs += 1;
+                trace_event!(trace, ply, depth = extended_depth, null_score, beta, "null move cutoff");
+                self.note(ply, "null move cutoff");
                 return beta;
             }
         }
-        
-        // Order moves
-        let ordered_moves = self.order_moves(board, moves, tt_move, ply);
-        
+
+        // ProbCut: a capture that wins enough material to beat beta by a
+        // margin in a reduced search would almost surely beat beta in the
+        // full one
+        if self.options.use_probcut && !is_pv && !in_check && excluded_move.is_none()
+           && extended_depth >= PROBCUT_DEPTH && beta.abs() < MATE_SCORE - 100 {
+            let probcut_beta = beta + PROBCUT_MARGIN;
+            let se = match static_eval {
+                Some(se) => se,
+                None => self.static_eval(board, ply),
+            };
+            let mut picker = MovePicker::captures(probcut_beta - se);
+
+            while let Some(mv) = picker.next(board, &self.move_generator, |_| 0) {
+                self.stack[ply].current_move = mv;
+                let undo = self.make_move(board, &mv, ply);
+                let new_hash = self.zobrist.hash_position(board);
+
+                // Quiescence first weeds out captures that plainly fail
+                let mut score = -self.quiescence(board, -probcut_beta, -probcut_beta + 1, ply + 1);
+                if score >= probcut_beta {
+                    score = -self.alphabeta(board, extended_depth - PROBCUT_REDUCTION, -probcut_beta,
+                                            -probcut_beta + 1, ply + 1, new_hash, node_type.child());
+                }
+                board.unmake_move(&mv, &undo);
+
+                if self.is_stopped() {
+                    return 0;
+                }
+                if score >= probcut_beta {
+                    trace_event!(trace, ply, depth = extended_depth, mv = %mv.to_uci(), score, beta, "probcut");
+                    self.note(ply, "probcut");
+                    return beta;
+                }
+            }
+        }
+
+        let mut picker = if is_root {
+            MovePicker::given(self.root_list.iter().map(|root_move| root_move.mv).collect())
+        } else {
+            MovePicker::new(tt_move, self.stack[ply].killers, self.countermove(self.stack[ply - 1].current_move))
+        };
         let mut best_score = -INFINITY;
         let mut best_move_at_node: Option<Move> = None;
         let mut moves_searched = 0;
-        
-        for mv in ordered_moves {
-            if self.stop_search {
+        let mut legal_moves = 0;
+
+        while let Some(mv) = picker.next(board, &self.move_generator, |m| self.quiet_score(board, m)) {
+            legal_moves += 1;
+            if self.is_stopped() {
                 break;
             }
-            
-            let is_capture = board.squares[mv.to_sq] != EMPTY || mv.is_en_passant;
+
+            if Some(mv) == excluded_move {
+                continue;
+            }
+
+            let is_capture = board.captured_piece(&mv) != EMPTY || mv.is_en_passant;
             let is_quiet = !is_capture && mv.promotion == 0;
-            
+            // Checking moves are neither pruned nor reduced
+            let gives_check = is_quiet && self.move_generator.gives_check(board, &mv);
+
             // Futility Pruning
             if let Some(se) = static_eval {
-                if moves_searched > 0 && extended_depth <= 3 && !in_check && is_quiet {
+                if !is_pv && moves_searched > 0 && extended_depth <= 3 && !in_check && is_quiet && !gives_check {
                     let futility_value = se + FUTILITY_MARGIN[extended_depth as usize];
                     if futility_value <= alpha {
                         self.futility_prunes += 1;
+                        trace_event!(trace, ply, mv = %mv.to_uci(), futility_value, alpha, "futility prune");
+                        if let Some(tree) = self.tree.as_mut() {
+                            tree.pruned_child(ply, mv, extended_depth - 1, "futility");
+                        }
                         moves_searched += 1;
                         continue;
                     }
                 }
             }
-            
+
+            if is_root {
+                self.report_current_move(mv, moves_searched + 1);
+            }
+
             // Make move
-            let undo = board.make_move(&mv);
-            
+            self.stack[ply].current_move = mv;
+            let nodes_before = self.nodes_searched;
+            let undo = self.make_move(board, &mv, ply);
+
             let new_hash = self.zobrist.hash_position(board);
-            
+
             // Late Move Reductions
             let mut score;
-            if self.use_lmr && moves_searched >= LMR_FULL_DEPTH_MOVES 
-               && extended_depth >= LMR_REDUCTION_LIMIT && is_quiet && !in_check {
-                
-                // Reduced depth search
-                let reduction = 1 + (moves_searched as i32 / 6);
+            if self.options.use_lmr && moves_searched >= LMR_FULL_DEPTH_MOVES
+               && extended_depth >= LMR_REDUCTION_LIMIT && is_quiet && !in_check && !gives_check {
+
+                // Reduced depth search: gentler in PV nodes, harder at expected cut nodes
+                let mut reduction = 1 + (moves_searched as i32 / 6);
+                match node_type {
+                    NodeType::Pv => reduction -= 1,
+                    NodeType::Cut => reduction += 1,
+                    NodeType::All => {}
+                }
                 let reduced_depth = (extended_depth - 1 - reduction).max(1);
-                
-                score = -self.alphabeta(board, reduced_depth, -alpha - 1, -alpha, 
-                                        ply + 1, false, new_hash, true);
-                
+
+                score = -self.alphabeta(board, reduced_depth, -alpha - 1, -alpha, ply + 1, new_hash,
+                                        node_type.child());
+
                 // Re-search at full depth if it looks promising
                 if score > alpha {
-                    score = -self.alphabeta(board, extended_depth - 1, -beta, -alpha, 
-                                           ply + 1, false, new_hash, true);
+                    trace_event!(trace, ply, mv = %mv.to_uci(), reduced_depth, score, alpha, "lmr re-search");
+                    score = -self.alphabeta(board, extended_depth - 1, -beta, -alpha, ply + 1, new_hash,
+                                            node_type.full_window_child());
                 }
             } else if moves_searched > 0 {
                 // PVS: Search with null window first
-                score = -self.alphabeta(board, extended_depth - 1, -alpha - 1, -alpha, 
-                                        ply + 1, false, new_hash, true);
-                
+                score = -self.alphabeta(board, extended_depth - 1, -alpha - 1, -alpha, ply + 1, new_hash,
+                                        node_type.child());
+
                 if score > alpha && score < beta {
-                    score = -self.alphabeta(board, extended_depth - 1, -beta, -alpha, 
-                                           ply + 1, false, new_hash, true);
+                    score = -self.alphabeta(board, extended_depth - 1, -beta, -alpha, ply + 1, new_hash,
+                                            node_type.full_window_child());
                 }
             } else {
                 // Full window search for first move
-                score = -self.alphabeta(board, extended_depth - 1, -beta, -alpha, 
-                                        ply + 1, false, new_hash, true);
+                score = -self.alphabeta(board, extended_depth - 1, -beta, -alpha, ply + 1, new_hash,
+                                        node_type.full_window_child());
             }
-            
+
             // Unmake move
             board.unmake_move(&mv, &undo);
-            
+
+            // The score of an interrupted subtree is meaningless
+            if self.is_stopped() {
+                break;
+            }
+
+            if is_root {
+                trace_event!(debug, mv = %mv.to_uci(), score, alpha, beta, "root move");
+
+                let nodes = self.nodes_searched - nodes_before;
+                if let Some(root_move) = self.root_list.iter_mut().find(|root_move| root_move.mv == mv) {
+                    root_move.nodes += nodes;
+                    if score > alpha {
+                        root_move.score = score;
+                    }
+                }
+
+                if score <= alpha && self.options.collect_refutations {
+                    self.record_refutation(mv);
+                }
+            }
+
             if score > best_score {
                 best_score = score;
                 best_move_at_node = Some(mv);
-                
+
                 if is_root {
                     self.best_move = Some(mv);
                 }
             }
-            
+
             if score > alpha {
                 alpha = score;
+                self.stack.update_pv(ply, mv);
+
+                if is_root {
+                    self.root_best = Some((mv, score));
+
+                    // A later move took over: tell the GUI now rather than
+                    // at the end of the iteration (a fail high is reported
+                    // by the aspiration loop)
+                    if moves_searched > 0 && score < beta {
+                        self.report(self.root_depth, score, ScoreBound::Exact, self.stack.pv(0).to_vec());
+                    }
+                }
             }
-            
+
             if alpha >= beta {
+                self.note(ply, "beta cutoff");
+
                 // Store killer move
-                if is_quiet && ply < MAX_DEPTH {
-                    self.killer_moves[ply][1] = self.killer_moves[ply][0];
-                    self.killer_moves[ply][0] = Some(mv);
-                    
-                    // Update history
-                    let piece = undo.moved_piece as usize;
-                    self.history[piece][mv.to_sq] += extended_depth * extended_depth;
+                if is_quiet {
+                    let killers = &mut self.stack[ply].killers;
+                    killers[1] = killers[0];
+                    killers[0] = PackedMove::new(mv);
+                    if ply > 0 {
+                        self.update_countermove(self.stack[ply - 1].current_move, mv);
+                    }
+
+                    // Update history: reward the cutoff move and penalize
+                    // the quiet moves that failed to cut before it
+                    let side = side_index(board);
+                    let bonus = history_bonus(extended_depth);
+                    self.update_history(side, &mv, bonus);
+                    let tried = std::mem::take(&mut self.stack.quiets_tried[ply]);
+                    for quiet in &tried {
+                        self.update_history(side, quiet, -bonus);
+                    }
+                    self.stack.quiets_tried[ply] = tried;
                 }
                 break;
             }
-            
+
+            if is_quiet {
+                self.stack.quiets_tried[ply].push(mv);
+            }
             moves_searched += 1;
         }
-        
+
+        // Checkmate / Stalemate
+        if legal_moves == 0 {
+            self.note(ply, if in_check { "checkmate" } else { "stalemate" });
+            return if in_check { -MATE_SCORE + ply as i32 } else { 0 };
+        }
+
         // Store in TT
-        if self.use_tt && !self.stop_search {
+        if self.options.use_tt && excluded_move.is_none() && !self.is_stopped() {
             let flag = if best_score <= original_alpha {
                 TT_ALPHA
             } else if best_score >= beta {
@@ -578,123 +1166,429 @@ impl SearchEngine {
             } else {
                 TT_EXACT
             };
-            
-            self.tt.store(position_hash, extended_depth, best_score, flag, best_move_at_node);
+
+            self.tt.store(position_hash, extended_depth, score_to_tt(best_score, ply), flag, best_move_at_node);
         }
-        
+
         best_score
     }
-    
-    fn quiescence(&mut self, board: &mut Board, mut alpha: i32, beta: i32) -> i32 {
-        self.nodes_searched += 1;
-        
-        let stand_pat = evaluate(board);
-        
-        if stand_pat >= beta {
-            return beta;
-        }
-        
-        if stand_pat > alpha {
-            alpha = stand_pat;
-        }
-        
-        let moves = self.move_generator.generate_legal_moves(board);
-        
-        // Only search captures
-        let mut captures: Vec<Move> = moves.into_iter()
-            .filter(|m| board.squares[m.to_sq] != EMPTY || m.is_en_passant || m.promotion != 0)
-            .collect();
-        
-        // Order captures by MVV-LVA
-        captures.sort_by_key(|m| -evaluate_move(board, m));
-        
-        for mv in captures {
-            if self.stop_search {
+
+    /// Send a progress report, if this thread reports
+    pub(crate) fn report(&self, depth: i32, score: i32, bound: ScoreBound, pv: Vec<Move>) {
+        if let Some(reports) = &self.reports {
+            let _ = reports.send(SearchReport::Line { depth, score, bound, nodes: self.all_nodes(), pv });
+        }
+    }
+
+    /// Nodes searched so far by all threads: the shared count, plus the
+    /// nodes of this thread not yet added to it
+    fn all_nodes(&self) -> u64 {
+        match &self.shared_nodes {
+            Some(shared_nodes) => shared_nodes.load(Ordering::Relaxed) + self.nodes_searched % TIME_CHECK_INTERVAL,
+            None => self.nodes_searched,
+        }
+    }
+
+    /// Report the iteration about to start, when reporting
+    fn report_iteration(&self, depth: i32) {
+        if let Some(reports) = &self.reports {
+            let _ = reports.send(SearchReport::Iteration { depth });
+        }
+    }
+
+    /// Report the root move about to be searched, when reporting
+    fn report_current_move(&self, mv: Move, number: usize) {
+        if let Some(reports) = &self.reports {
+            let _ = reports.send(SearchReport::CurrentMove { depth: self.root_depth, mv, number });
+        }
+    }
+
+    /// Keep the child's PV as the refutation of root move `mv`, replacing the
+    /// one found at a shallower depth
+    fn record_refutation(&mut self, mv: Move) {
+        let line = self.stack.pv(1).to_vec();
+        if line.is_empty() {
+            return;
+        }
+        match self.refutations.iter_mut().find(|(root_move, _)| *root_move == mv) {
+            Some(entry) => entry.1 = line,
+            None => self.refutations.push((mv, line)),
+        }
+    }
+
+    /// Exact score of every legal root move, each searched with a full
+    /// window to `depth` (0 = quiescence only), best first
+    pub(crate) fn score_root_moves(&mut self, board: &Board, depth: i32) -> Vec<(Move, i32)> {
+        let mut board = board.search_copy();
+        self.stack.clear();
+        self.refresh_accumulator(&board, 0);
+        self.root_depth = depth.max(1);
+
+        let moves = self.move_generator.generate_legal_moves(&board);
+        let mut scores = Vec::with_capacity(moves.len());
+
+        for mv in moves {
+            if self.is_stopped() {
+                break;
+            }
+
+            self.stack[0].current_move = mv;
+            let undo = self.make_move(&mut board, &mv, 0);
+            let hash = self.zobrist.hash_position(&board);
+            let score = if depth <= 1 {
+                -self.quiescence(&mut board, -INFINITY, INFINITY, 1)
+            } else {
+                -self.alphabeta(&mut board, depth - 1, -INFINITY, INFINITY, 1, hash, NodeType::Pv)
+            };
+            board.unmake_move(&mv, &undo);
+
+            scores.push((mv, score));
+        }
+
+        scores.sort_by_key(|&(_, score)| std::cmp::Reverse(score));
+        scores
+    }
+
+    /// Add `units` (fractions of a ply) of extension to the credit inherited
+    /// from the parent and return `depth` plus the whole plies earned. New
+    /// extensions are refused once the path reaches the extension budget, so
+    /// perpetual-check lines cannot grow the tree without bound.
+    pub(crate) fn extend(&mut self, ply: usize, depth: i32, units: i32) -> i32 {
+        let mut credit = if ply > 0 { self.stack[ply - 1].extension_credit } else { 0 };
+        if ply as i32 + depth < self.root_depth * MAX_EXTENSION_FACTOR {
+            credit += units;
+        }
+
+        self.stack[ply].extension_credit = credit % ONE_PLY;
+        depth + credit / ONE_PLY
+    }
+
+    /// Quiescence search: captures and promotions that do not lose material
+    /// (the evasions alone would make losing captures that give check
+    /// explode), with the static evaluation as a lower bound ("stand pat").
+    /// In check there is no standing pat: every evasion is searched, and no
+    /// evasion means mate. The first ply also tries quiet checks, so mates
+    /// and perpetuals just past the horizon are seen.
+    pub(crate) fn quiescence(&mut self, board: &mut Board, alpha: i32, beta: i32, ply: usize) -> i32 {
+        self.qsearch(board, alpha, beta, ply, QS_DEPTH_CHECKS)
+    }
+
+    fn qsearch(&mut self, board: &mut Board, mut alpha: i32, beta: i32, ply: usize, depth: i32) -> i32 {
+        self.count_node();
+
+        // Out of stack space
+        if ply >= MAX_PLY - 1 {
+            return self.static_eval(board, ply);
+        }
+
+        // Probe TT
+        let position_hash = self.zobrist.hash_position(board);
+        let original_alpha = alpha;
+        let mut tt_move = None;
+        if self.options.use_tt {
+            if let Some(entry) = self.tt.probe(position_hash) {
+                let tt_score = score_from_tt(entry.score, ply);
+                match entry.flag {
+                    _ if entry.depth < depth => {}
+                    TT_EXACT => return tt_score,
+                    TT_ALPHA if tt_score <= alpha => return alpha,
+                    TT_BETA if tt_score >= beta => return beta,
+                    _ => {}
+                }
+                tt_move = entry.best_move;
+            }
+        }
+
+        let in_check = self.move_generator.is_in_check(board);
+        let mut moves = if in_check {
+            self.move_generator.generate_evasions(board)
+        } else if depth == QS_DEPTH_CHECKS {
+            self.move_generator.generate_legal_moves(board)
+        } else {
+            self.move_generator.generate_captures(board)
+        };
+
+        if in_check {
+            if moves.is_empty() {
+                return -MATE_SCORE + ply as i32;
+            }
+        } else {
+            let stand_pat = self.static_eval(board, ply);
+            if stand_pat >= beta {
+                return beta;
+            }
+            if stand_pat > alpha {
+                alpha = stand_pat;
+            }
+
+            // Only search captures (and at the first ply, quiet checks) that
+            // do not lose material
+            let checks = depth == QS_DEPTH_CHECKS;
+            let move_generator = &self.move_generator;
+            moves.retain(|m| {
+                let is_capture = board.captured_piece(m) != EMPTY || m.is_en_passant || m.promotion != 0;
+                (is_capture || checks && move_generator.gives_check(board, m)) && see(board, m) >= 0
+            });
+        }
+
+        // Order captures by MVV-LVA (quiet moves last), after the hash move
+        moves.sort_by_key(|m| if Some(*m) == tt_move { i32::MIN } else { -evaluate_move(board, m) });
+
+        let mut best_move = None;
+        for mv in moves {
+            if self.is_stopped() {
                 break;
             }
-            
-            let undo = board.make_move(&mv);
-            let score = -self.quiescence(board, -beta, -alpha);
+
+            let undo = self.make_move(board, &mv, ply);
+            let score = -self.qsearch(board, -beta, -alpha, ply + 1, QS_DEPTH_NO_CHECKS);
             board.unmake_move(&mv, &undo);
-            
+
             if score >= beta {
+                self.store_quiescence(position_hash, depth, beta, TT_BETA, Some(mv), ply);
                 return beta;
             }
             if score > alpha {
                 alpha = score;
+                best_move = Some(mv);
             }
         }
-        
+
+        let flag = if alpha > original_alpha { TT_EXACT } else { TT_ALPHA };
+        self.store_quiescence(position_hash, depth, alpha, flag, best_move, ply);
         alpha
     }
-    
-    fn order_moves(&self, board: &Board, moves: Vec<Move>, tt_move: Option<Move>, ply: usize) -> Vec<Move> {
-        let mut scored_moves: Vec<(Move, i32)> = moves.into_iter().map(|m| {
+
+    /// Store a quiescence result in the TT
+    fn store_quiescence(&self, position_hash: u64, depth: i32, score: i32, flag: u8, best_move: Option<Move>,
+                        ply: usize) {
+        if self.options.use_tt && !self.is_stopped() {
+            self.tt.store(position_hash, depth, score_to_tt(score, ply), flag, best_move);
+        }
+    }
+
+    /// Sort `moves` in place, best first, scoring them in the ply's reusable
+    /// buffer (ties keep generation order)
+    pub(crate) fn order_moves(&mut self, board: &Board, moves: &mut [Move], tt_move: Option<Move>, ply: usize) {
+        let killers = self.stack[ply].killers;
+        let mut scored_moves = std::mem::take(&mut self.stack.move_scores[ply]);
+        scored_moves.clear();
+        scored_moves.extend(moves.iter().enumerate().map(|(index, &m)| {
             let mut score = 0i32;
-            
+
             // TT move gets highest priority
             if Some(m) == tt_move {
                 score += 10000000;
             }
-            
+
             // Captures
-            let victim = board.squares[m.to_sq];
+            let victim = board.captured_piece(&m);
             if victim != EMPTY {
                 let victim_value = PIECE_VALUES[get_piece_type(victim) as usize];
                 let attacker = board.squares[m.from_sq];
                 let attacker_value = PIECE_VALUES[get_piece_type(attacker) as usize];
                 score += 1000000 + 10 * victim_value - attacker_value;
             }
-            
+
             // Promotions
             if m.promotion != 0 {
                 score += 900000 + PIECE_VALUES[m.promotion as usize];
             }
-            
+
             // Killer moves
-            if ply < MAX_DEPTH {
-                if Some(m) == self.killer_moves[ply][0] {
-                    score += 800000;
-                } else if Some(m) == self.killer_moves[ply][1] {
-                    score += 700000;
-                }
+            let packed = PackedMove::new(m);
+            if packed == killers[0] {
+                score += 800000;
+            } else if packed == killers[1] {
+                score += 700000;
             }
-            
-            // History heuristic
-            let piece = board.squares[m.from_sq] as usize;
-            if piece < 32 {
-                score += self.history[piece][m.to_sq];
+
+            score += self.quiet_score(board, &m);
+
+            (m, score, index)
+        }));
+
+        scored_moves.sort_unstable_by_key(|&(_, score, index)| (std::cmp::Reverse(score), index));
+        for (slot, &(m, _, _)) in moves.iter_mut().zip(scored_moves.iter()) {
+            *slot = m;
+        }
+        self.stack.move_scores[ply] = scored_moves;
+    }
+
+    /// Ordering score of a quiet move from the history heuristic, from the
+    /// shared table when enabled, else the local one
+    #[inline]
+    fn quiet_score(&self, board: &Board, mv: &Move) -> i32 {
+        let side = side_index(board);
+        match self.shared_history {
+            Some(ref shared) => shared.get(side, mv.from_sq, mv.to_sq),
+            None => self.history.scores[side][mv.from_sq][mv.to_sq],
+        }
+    }
+
+    /// Halve the thread's own history scores, before reusing the thread for
+    /// another search
+    pub(crate) fn decay_history(&mut self) {
+        for entry in self.history.scores.iter_mut().flatten().flatten() {
+            *entry /= 2;
+        }
+    }
+
+    /// Continue from the history table a thread of an earlier search left,
+    /// with its scores halved
+    pub(crate) fn resume_history(&mut self, history: HistoryTable) {
+        self.history = history;
+        self.decay_history();
+    }
+
+    /// The thread's own history table, for the next search
+    pub(crate) fn into_history(self) -> HistoryTable {
+        self.history
+    }
+
+    #[inline]
+    fn update_history(&mut self, side: usize, mv: &Move, bonus: i32) {
+        match self.shared_history {
+            Some(ref shared) => shared.update(side, mv.from_sq, mv.to_sq, bonus),
+            None => {
+                let entry = &mut self.history.scores[side][mv.from_sq][mv.to_sq];
+                *entry = history_gravity(*entry, bonus);
             }
-            
-            (m, score)
-        }).collect();
-        
-        scored_moves.sort_by(|a, b| b.1.cmp(&a.1));
-        scored_moves.into_iter().map(|(m, _)| m).collect()
+        }
+    }
+
+    /// Countermove of the opponent's move `previous` (none after a null
+    /// move), from the shared table when enabled, else the local one
+    #[inline]
+    fn countermove(&self, previous: Move) -> PackedMove {
+        if !self.options.use_countermove || previous.is_null() {
+            return PackedMove::NONE;
+        }
+        match self.shared_history {
+            Some(ref shared) => shared.countermove(previous.from_sq, previous.to_sq),
+            None => self.history.countermoves[previous.from_sq][previous.to_sq],
+        }
     }
-    
+
+    /// Record `mv` as the countermove of the opponent's move `previous`
+    #[inline]
+    fn update_countermove(&mut self, previous: Move, mv: Move) {
+        if !self.options.use_countermove || previous.is_null() {
+            return;
+        }
+        match self.shared_history {
+            Some(ref shared) => shared.set_countermove(previous.from_sq, previous.to_sq, PackedMove::new(mv)),
+            None => self.history.countermoves[previous.from_sq][previous.to_sq] = PackedMove::new(mv),
+        }
+    }
+
     fn has_big_pieces(&self, board: &Board) -> bool {
-        let color = if board.white_to_move { WHITE } else { BLACK };
-        
-        for sq in 0..64 {
-            let piece = board.squares[sq];
-            if piece != EMPTY && get_piece_color(piece) == color {
-                let pt = get_piece_type(piece);
-                if pt == KNIGHT || pt == BISHOP || pt == ROOK || pt == QUEEN {
-                    return true;
-                }
+        let big = board.bb_knights | board.bb_bishops | board.bb_rooks | board.bb_queens;
+        big & board.get_own_pieces() != 0
+    }
+}
+
+// ============================================================================
+// SEARCH ENGINE
+// ============================================================================
+
+pub struct SearchEngine {
+    thread: SearchThread,
+    tt: Arc<TranspositionTable>,
+    stop_search: Arc<AtomicBool>,
+    pub nodes_searched: u64,
+    pub best_move: Option<Move>,
+
+    // Configurable options
+    pub options: SearchOptions,
+    /// Network for `options.use_nnue`
+    pub network: Option<Arc<Network>>,
+
+    // PV
+    pub pv: Vec<Move>,
+    search_start_time: std::time::Instant,
+}
+
+impl SearchEngine {
+    pub fn new(tt_size_mb: usize) -> Self {
+        let tt = Arc::new(TranspositionTable::new(tt_size_mb));
+        let stop_search = Arc::new(AtomicBool::new(false));
+        let options = SearchOptions::default();
+
+        SearchEngine {
+            thread: SearchThread::new(0, Arc::clone(&stop_search), Arc::clone(&tt), options, None),
+            tt,
+            stop_search,
+            nodes_searched: 0,
+            best_move: None,
+            options,
+            network: None,
+            pv: Vec::new(),
+            search_start_time: std::time::Instant::now(),
+        }
+    }
+
+    /// Search with aspiration windows
+    pub fn search<F>(&mut self, board: &Board, depth: i32, mut info_callback: Option<F>)
+        -> (Option<Move>, i32)
+    where F: FnMut(i32, i32, ScoreBound, u64, u64, &str, usize, u64)
+    {
+        self.stop_search.store(false, Ordering::SeqCst);
+        self.best_move = None;
+        self.pv.clear();
+        self.search_start_time = std::time::Instant::now();
+        self.tt.new_search();
+        self.thread.decay_history();
+        self.thread.options = self.options;
+        self.thread.network = self.network.clone();
+
+        let start_time = self.search_start_time;
+        let tt = Arc::clone(&self.tt);
+        let mut pv = Vec::new();
+
+        let (best_move, best_score) = self.thread.iterative_deepening(board, depth, |_| false, |thread, d, score| {
+            pv = thread.stack.pv(0).to_vec();
+            if let Some(ref mut cb) = info_callback {
+                let time_ms = start_time.elapsed().as_millis() as u64;
+                let nps = (thread.nodes_searched * 1000).checked_div(time_ms).unwrap_or(0);
+                let pv_str: String = pv.iter()
+                    .map(|m| m.to_uci())
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                cb(d, score, ScoreBound::Exact, thread.nodes_searched, time_ms, &pv_str, tt.hashfull(), nps);
             }
+        });
+
+        self.nodes_searched = self.thread.nodes_searched;
+        self.best_move = best_move;
+
+        // The main line of the last completed iteration, unless an
+        // interrupted iteration changed the move
+        if pv.first() != best_move.as_ref() {
+            pv = best_move.into_iter().collect();
         }
-        false
+        self.pv = pv;
+
+        (best_move, best_score)
     }
-    
-    pub fn stop(&mut self) {
-        self.stop_search = true;
+
+    pub fn stop(&self) {
+        self.stop_search.store(true, Ordering::SeqCst);
     }
-    
+
     pub fn clear_tt(&mut self) {
         self.tt.clear();
     }
+
+    /// Record the explored tree within `budget` during the next searches
+    pub fn record_tree(&mut self, budget: TreeBudget) {
+        self.thread.tree = Some(SearchTree::new(budget));
+    }
+
+    /// Stop recording and return the tree of the last root search
+    pub fn take_tree(&mut self) -> Option<SearchTree> {
+        self.thread.tree.take()
+    }
 }
 
 impl Default for SearchEngine {
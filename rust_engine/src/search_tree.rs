@@ -0,0 +1,172 @@
+//! OpusChess - Search Tree Export Module
+//!
+//! This module records the tree explored by the alpha-beta search for
+//! debugging. Every node keeps the move leading to it, the window and depth
+//! it was searched with, the returned score and why it was cut short, if it
+//! was. Recording stops at a node and ply budget, and the tree can be dumped
+//! as JSON or as a Graphviz digraph.
+//!
+//! Only one root search is kept: a root node searched at least as deep as
+//! the recorded one replaces the tree, so an aspiration re-search replaces
+//! the failed window. After iterative deepening the export shows the
+//! accepted window of the deepest iteration.
+
+use std::fmt::Write as _;
+
+use crate::board::Move;
+
+/// Limits on how much of the tree is recorded
+#[derive(Clone, Copy, Debug)]
+pub struct TreeBudget {
+    pub max_nodes: usize,
+    pub max_ply: usize,
+}
+
+impl Default for TreeBudget {
+    fn default() -> Self {
+        TreeBudget { max_nodes: 10000, max_ply: 4 }
+    }
+}
+
+/// One recorded node
+#[derive(Clone, Debug)]
+pub struct TreeNode {
+    pub parent: Option<usize>,
+    pub ply: usize,
+    /// Move leading to this node (None at the root, null move for NMP)
+    pub mv: Option<Move>,
+    pub depth: i32,
+    pub alpha: i32,
+    pub beta: i32,
+    pub score: Option<i32>,
+    /// Pruning or cutoff that ended this node early
+    pub reason: Option<&'static str>,
+}
+
+/// Recorded search tree
+pub struct SearchTree {
+    budget: TreeBudget,
+    nodes: Vec<TreeNode>,
+    /// Open node at each ply of the current path (None when not recorded)
+    path: Vec<Option<usize>>,
+    /// Nodes dropped because the node budget was exhausted
+    pub dropped: u64,
+}
+
+impl SearchTree {
+    pub fn new(budget: TreeBudget) -> Self {
+        SearchTree {
+            budget,
+            nodes: Vec::new(),
+            path: Vec::new(),
+            dropped: 0,
+        }
+    }
+
+    pub fn nodes(&self) -> &[TreeNode] {
+        &self.nodes
+    }
+
+    fn parent_of(&self, ply: usize) -> Option<usize> {
+        if ply == 0 { None } else { self.path.get(ply - 1).copied().flatten() }
+    }
+
+    /// Start a node at `ply`; returns its id if it is recorded
+    pub(crate) fn open(&mut self, ply: usize, mv: Option<Move>, depth: i32, alpha: i32, beta: i32) -> Option<usize> {
+        self.path.resize(ply + 1, None);
+        self.path[ply] = None;
+
+        if ply == 0 {
+            if self.nodes.first().is_some_and(|root| depth < root.depth) {
+                return None;
+            }
+            self.nodes.clear();
+            self.dropped = 0;
+        }
+
+        // Children of unrecorded nodes are not recorded either
+        let parent = self.parent_of(ply);
+        if ply > self.budget.max_ply || (ply > 0 && parent.is_none()) {
+            return None;
+        }
+        if self.nodes.len() >= self.budget.max_nodes {
+            self.dropped += 1;
+            return None;
+        }
+
+        let id = self.nodes.len();
+        self.nodes.push(TreeNode { parent, ply, mv, depth, alpha, beta, score: None, reason: None });
+        self.path[ply] = Some(id);
+        Some(id)
+    }
+
+    /// Finish node `id` with its score
+    pub(crate) fn close(&mut self, id: usize, score: i32) {
+        self.nodes[id].score = Some(score);
+    }
+
+    /// Record why the open node at `ply` ended early
+    pub(crate) fn note(&mut self, ply: usize, reason: &'static str) {
+        if let Some(Some(id)) = self.path.get(ply) {
+            self.nodes[*id].reason = Some(reason);
+        }
+    }
+
+    /// Record a child of the open node at `ply` that was pruned without
+    /// being searched
+    pub(crate) fn pruned_child(&mut self, ply: usize, mv: Move, depth: i32, reason: &'static str) {
+        if let Some(id) = self.open(ply + 1, Some(mv), depth, 0, 0) {
+            self.nodes[id].reason = Some(reason);
+        }
+    }
+
+    fn move_label(node: &TreeNode) -> String {
+        match node.mv {
+            None => "root".to_string(),
+            Some(mv) if mv.is_null() => "null".to_string(),
+            Some(mv) => mv.to_uci(),
+        }
+    }
+
+    /// Dump as a JSON array of nodes
+    pub fn to_json(&self) -> String {
+        let mut out = String::from("[\n");
+        for (id, node) in self.nodes.iter().enumerate() {
+            let parent = node.parent.map(|p| p.to_string()).unwrap_or_else(|| "null".to_string());
+            let score = node.score.map(|s| s.to_string()).unwrap_or_else(|| "null".to_string());
+            let reason = node.reason.map(|r| format!("\"{}\"", r)).unwrap_or_else(|| "null".to_string());
+            let _ = write!(
+                out,
+                "  {{\"id\": {}, \"parent\": {}, \"ply\": {}, \"move\": \"{}\", \"depth\": {}, \
+                 \"alpha\": {}, \"beta\": {}, \"score\": {}, \"reason\": {}}}",
+                id, parent, node.ply, Self::move_label(node), node.depth,
+                node.alpha, node.beta, score, reason
+            );
+            out.push_str(if id + 1 < self.nodes.len() { ",\n" } else { "\n" });
+        }
+        out.push_str("]\n");
+        out
+    }
+
+    /// Dump as a Graphviz digraph
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph search {\n  node [shape=box, fontname=\"monospace\"];\n");
+        for (id, node) in self.nodes.iter().enumerate() {
+            let score = node.score.map(|s| s.to_string()).unwrap_or_else(|| "-".to_string());
+            let mut label = format!(
+                "{}\\nd={} [{}, {}]\\nscore={}",
+                Self::move_label(node), node.depth, node.alpha, node.beta, score
+            );
+            if let Some(reason) = node.reason {
+                let _ = write!(label, "\\n{}", reason);
+            }
+            let style = if node.reason.is_some() { ", style=dashed" } else { "" };
+            let _ = writeln!(out, "  n{} [label=\"{}\"{}];", id, label, style);
+            if let Some(parent) = node.parent {
+                let _ = writeln!(out, "  n{} -> n{};", parent, id);
+            }
+        }
+        out.push_str("}\n");
+        out
+    }
+}
@@ -0,0 +1,42 @@
+//! OpusChess - Serde Support Module
+//!
+//! This module, built with the `serde` feature, serializes the core types
+//! in the notations chess tools already exchange:
+//! - `Board` as its FEN (the position history is not kept, so repetitions
+//!   before the serialized position are not seen after loading it)
+//! - `Move` as its UCI string; castling and en passant flags are not part
+//!   of UCI notation, so a loaded move should be matched against the legal
+//!   moves of its position before it is played
+//!
+//! `SearchResult` derives its implementations where it is defined.
+
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+
+use crate::board::{Board, Move};
+
+impl Serialize for Board {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_fen())
+    }
+}
+
+impl<'de> Deserialize<'de> for Board {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let fen = String::deserialize(deserializer)?;
+        Board::from_fen(&fen).map_err(de::Error::custom)
+    }
+}
+
+impl Serialize for Move {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_uci())
+    }
+}
+
+impl<'de> Deserialize<'de> for Move {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let uci = String::deserialize(deserializer)?;
+        Move::from_uci(&uci).ok_or_else(|| de::Error::custom(format!("invalid UCI move {}", uci)))
+    }
+}
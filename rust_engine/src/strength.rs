@@ -0,0 +1,46 @@
+//! OpusChess - Strength Limiting Module
+//!
+//! This module maps a target rating (UCI_Elo, used with UCI_LimitStrength)
+//! to the handicaps that make the engine a fair practice opponent:
+//! - a depth cap and a node budget, so it misses deeper tactics
+//! - bounded evaluation noise, so it misjudges quiet positions and does
+//!   not always play the same move
+//!
+//! The mapping is a rough calibration: each step of the curve was chosen
+//! so that the handicaps weaken together, not from rating tests.
+
+/// Range of the UCI_Elo option
+pub const MIN_ELO: i32 = 800;
+pub const MAX_ELO: i32 = 2400;
+
+/// Depth cap at MIN_ELO, and the rating gained per extra ply
+const MIN_DEPTH: i32 = 1;
+const ELO_PER_PLY: i32 = 160;
+
+/// Node budget at MIN_ELO, doubled every ELO_PER_DOUBLING
+const MIN_NODES: u64 = 500;
+const ELO_PER_DOUBLING: i32 = 200;
+
+/// Evaluation noise (centipawns) at MIN_ELO; it falls linearly to 0 at
+/// MAX_ELO
+const MAX_NOISE: i32 = 300;
+
+/// Search handicaps for one rating
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StrengthLimit {
+    pub depth: i32,
+    pub nodes: u64,
+    pub eval_noise: i32,
+}
+
+impl StrengthLimit {
+    /// Handicaps for a rating (clamped to MIN_ELO..=MAX_ELO)
+    pub fn from_elo(elo: i32) -> Self {
+        let above_min = elo.clamp(MIN_ELO, MAX_ELO) - MIN_ELO;
+        StrengthLimit {
+            depth: MIN_DEPTH + above_min / ELO_PER_PLY,
+            nodes: MIN_NODES << (above_min / ELO_PER_DOUBLING),
+            eval_noise: MAX_NOISE * (MAX_ELO - MIN_ELO - above_min) / (MAX_ELO - MIN_ELO),
+        }
+    }
+}
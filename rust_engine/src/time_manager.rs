@@ -0,0 +1,235 @@
+//! OpusChess - Time Management Module
+//!
+//! This module turns the clock information of a `go` command into two limits:
+//! - a soft limit, checked after every completed iteration, past which no new
+//!   iteration is started
+//! - a hard limit, checked inside the search, at which the search is aborted
+//!
+//! The soft limit is stretched (never beyond the hard limit) when the root
+//! score drops against the previous iteration or the aspiration search fails
+//! low, so the engine does not commit to a move it just found to be worse,
+//! and while the best move keeps changing between iterations. It shrinks
+//! once the best move has held for several iterations at a steady score,
+//! so an obvious move such as a recapture does not use up the budget.
+//!
+//! The base budget depends on the game phase: the opening moves, which are
+//! mostly known theory, and simple endgames get less than the early
+//! middlegame, where the game is usually decided.
+//!
+//! `SearchLimits` bundles the time budget with a node budget, so a `go`
+//! command may combine depth, nodes and clock limits and the search stops
+//! at whichever is reached first.
+
+use std::time::{Duration, Instant};
+
+use crate::board::{Board, Move, MAX_PHASE};
+
+/// Time kept in reserve for communication with the GUI
+const MOVE_OVERHEAD_MS: u64 = 30;
+
+/// Moves to plan for when the GUI does not send movestogo, from a bare
+/// endgame to the full board
+const MIN_MOVES_TO_GO: u64 = 20;
+const MAX_MOVES_TO_GO: u64 = 35;
+
+/// Share of the base budget by game stage
+const OPENING_MOVES: u16 = 8;
+const OPENING_FACTOR: f64 = 0.6;
+const MIDDLEGAME_PHASE: i32 = 16;
+const MIDDLEGAME_FACTOR: f64 = 1.25;
+const ENDGAME_PHASE: i32 = 4;
+const ENDGAME_FACTOR: f64 = 0.75;
+
+/// Score drops (centipawns) that trigger a longer think, and the factors
+/// applied to the soft limit
+const SCORE_DROP_SMALL: i32 = 30;
+const SCORE_DROP_LARGE: i32 = 80;
+const EXTENSION_SMALL: f64 = 1.5;
+const EXTENSION_LARGE: f64 = 2.0;
+
+/// Stretch of the soft limit per recent best-move change; the count of
+/// changes is halved every iteration, so only recent ones matter
+const INSTABILITY_EXTENSION: f64 = 0.5;
+
+/// Iterations the best move must hold before the soft limit shrinks, and
+/// the factor it shrinks by
+const STABLE_ITERATIONS: u32 = 4;
+const STABILITY_FACTOR: f64 = 0.6;
+
+/// Clock parameters of a `go` command
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TimeControl {
+    pub wtime: Option<u64>,
+    pub btime: Option<u64>,
+    pub winc: u64,
+    pub binc: u64,
+    pub movestogo: Option<u64>,
+    pub movetime: Option<u64>,
+}
+
+impl TimeControl {
+    /// Whether the command limits the search by time at all
+    pub fn is_timed(&self) -> bool {
+        self.movetime.is_some() || self.wtime.is_some() || self.btime.is_some()
+    }
+}
+
+/// Per-search time budget
+#[derive(Clone, Debug)]
+pub struct TimeManager {
+    start: Instant,
+    soft_ms: u64,
+    hard_ms: u64,
+    /// Current stretch of the soft limit
+    extension: f64,
+    previous_score: Option<i32>,
+    previous_move: Option<Move>,
+    /// Decaying count of best-move changes
+    best_move_changes: f64,
+    /// Iterations since the best move last changed
+    stable_iterations: u32,
+}
+
+/// Depth, node and time limits of one search. All are optional and may be
+/// combined; `depth` further caps the depth passed to the search.
+#[derive(Clone, Debug, Default)]
+pub struct SearchLimits {
+    pub depth: Option<i32>,
+    /// Node budget shared by all search threads
+    pub nodes: Option<u64>,
+    pub time: Option<TimeManager>,
+}
+
+impl SearchLimits {
+    /// Only a depth limit
+    pub fn depth(depth: i32) -> Self {
+        SearchLimits { depth: Some(depth), ..SearchLimits::default() }
+    }
+
+    /// Only a node budget
+    pub fn nodes(nodes: u64) -> Self {
+        SearchLimits { nodes: Some(nodes), ..SearchLimits::default() }
+    }
+
+    /// Only a fixed time per move; the clock starts now
+    pub fn movetime(ms: u64) -> Self {
+        let time_control = TimeControl { movetime: Some(ms), ..TimeControl::default() };
+        SearchLimits { time: TimeManager::new(&time_control, &Board::new()), ..SearchLimits::default() }
+    }
+
+    /// Whether any limit besides the depth applies
+    pub fn is_limited(&self) -> bool {
+        self.nodes.is_some() || self.time.is_some()
+    }
+
+    /// Instant at which the search must be aborted, if timed
+    pub fn deadline(&self) -> Option<Instant> {
+        self.time.as_ref().map(TimeManager::deadline)
+    }
+
+    /// Feed the result of a completed iteration and tell whether another
+    /// iteration should be started
+    pub fn continue_after(&mut self, score: i32, fail_lows: u32, best_move: Option<Move>) -> bool {
+        match self.time {
+            Some(ref mut tm) => {
+                tm.on_iteration(score, fail_lows, best_move);
+                !tm.should_stop()
+            }
+            None => true,
+        }
+    }
+}
+
+/// Moves left in the game, estimated from the material still on the board
+fn estimated_moves_to_go(phase: i32) -> u64 {
+    let phase = phase.clamp(0, MAX_PHASE) as u64;
+    MIN_MOVES_TO_GO + (MAX_MOVES_TO_GO - MIN_MOVES_TO_GO) * phase / MAX_PHASE as u64
+}
+
+/// Scale of the base budget for the stage of the game
+fn stage_factor(board: &Board) -> f64 {
+    let phase = board.phase();
+    if board.fullmove_number <= OPENING_MOVES {
+        OPENING_FACTOR
+    } else if phase >= MIDDLEGAME_PHASE {
+        MIDDLEGAME_FACTOR
+    } else if phase <= ENDGAME_PHASE {
+        ENDGAME_FACTOR
+    } else {
+        1.0
+    }
+}
+
+impl TimeManager {
+    /// Budget for the side to move in `board`, or None when the search is
+    /// untimed
+    pub fn new(tc: &TimeControl, board: &Board) -> Option<Self> {
+        let white_to_move = board.white_to_move;
+        let (soft_ms, hard_ms) = if let Some(movetime) = tc.movetime {
+            let limit = movetime.saturating_sub(MOVE_OVERHEAD_MS).max(1);
+            (limit, limit)
+        } else {
+            let (time, inc) = if white_to_move { (tc.wtime?, tc.winc) } else { (tc.btime?, tc.binc) };
+            let available = time.saturating_sub(MOVE_OVERHEAD_MS).max(1);
+            let moves_to_go = tc.movestogo.unwrap_or_else(|| estimated_moves_to_go(board.phase())).max(1);
+
+            let base = (available / moves_to_go + inc * 3 / 4) as f64 * stage_factor(board);
+            let soft = (base as u64).min(available);
+            let hard = (soft * 4).min(available / 3).max(soft);
+            (soft.max(1), hard.max(1))
+        };
+
+        Some(TimeManager {
+            start: Instant::now(),
+            soft_ms,
+            hard_ms,
+            extension: 1.0,
+            previous_score: None,
+            previous_move: None,
+            best_move_changes: 0.0,
+            stable_iterations: 0,
+        })
+    }
+
+    pub fn elapsed_ms(&self) -> u64 {
+        self.start.elapsed().as_millis() as u64
+    }
+
+    /// Instant at which the search must be aborted
+    pub fn deadline(&self) -> Instant {
+        self.start + Duration::from_millis(self.hard_ms)
+    }
+
+    /// Feed the result of a completed iteration. `fail_lows` is the number
+    /// of aspiration fail-lows seen while searching it.
+    pub fn on_iteration(&mut self, score: i32, fail_lows: u32, best_move: Option<Move>) {
+        let drop = self.previous_score.map_or(0, |previous| previous - score);
+
+        let score_extension = if drop >= SCORE_DROP_LARGE {
+            EXTENSION_LARGE
+        } else if drop >= SCORE_DROP_SMALL || fail_lows > 0 {
+            EXTENSION_SMALL
+        } else {
+            1.0
+        };
+
+        let changed = self.previous_move.is_some() && best_move != self.previous_move;
+        self.best_move_changes = self.best_move_changes / 2.0 + if changed { 1.0 } else { 0.0 };
+        self.stable_iterations = if changed { 0 } else { self.stable_iterations + 1 };
+
+        let stability = if self.stable_iterations >= STABLE_ITERATIONS && score_extension == 1.0 {
+            STABILITY_FACTOR
+        } else {
+            1.0
+        };
+        self.extension = score_extension * (1.0 + INSTABILITY_EXTENSION * self.best_move_changes) * stability;
+        self.previous_score = Some(score);
+        self.previous_move = best_move;
+    }
+
+    /// Whether another iteration should be started
+    pub fn should_stop(&self) -> bool {
+        let soft = (self.soft_ms as f64 * self.extension) as u64;
+        self.elapsed_ms() >= soft.min(self.hard_ms)
+    }
+}
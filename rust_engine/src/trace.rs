@@ -0,0 +1,32 @@
+//! OpusChess - Tracing Module
+//!
+//! Thin wrappers around the `tracing` crate used to instrument the search.
+//! With the `trace` feature disabled the macros expand to nothing, so normal
+//! builds pay no cost. With it enabled, the binary logs to stderr and the
+//! output is filtered with `RUST_LOG` (e.g. `RUST_LOG=opus_chess=trace`).
+
+/// Emit a tracing event at the given level (`trace`, `debug`, ...)
+macro_rules! trace_event {
+    ($level:ident, $($arg:tt)*) => {
+        #[cfg(feature = "trace")]
+        tracing::$level!($($arg)*);
+    };
+}
+
+/// Enter a debug-level span that lasts until the returned guard is dropped
+macro_rules! trace_span {
+    ($($arg:tt)*) => {{
+        #[cfg(feature = "trace")]
+        let guard = tracing::debug_span!($($arg)*).entered();
+        #[cfg(not(feature = "trace"))]
+        let guard = $crate::trace::NoSpan;
+        guard
+    }};
+}
+
+/// Stand-in span guard when tracing is compiled out
+#[cfg(not(feature = "trace"))]
+pub(crate) struct NoSpan;
+
+pub(crate) use trace_event;
+pub(crate) use trace_span;
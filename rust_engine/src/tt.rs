@@ -0,0 +1,291 @@
+//! OpusChess - Transposition Table Module
+//!
+//! This module provides the lock-free transposition table shared by the
+//! single-threaded and the parallel search. Each slot is a pair of atomic
+//! words: the packed entry and the hash key XORed with it. A slot torn by
+//! two threads writing at once no longer matches its key and reads as a
+//! miss, so no lock is needed to probe or store. Large tables are allocated
+//! and cleared by several threads at once.
+//!
+//! Entries carry the generation of the search that stored them. The
+//! generation is bumped by every new search, and entries left over from an
+//! earlier one are replaced even by shallower results, so deep entries from
+//! analysis of other positions do not linger in the table.
+//!
+//! Slots are grouped in clusters of CLUSTER_SIZE sharing one cache line, and
+//! a position may be stored in any slot of its cluster. All but the last
+//! slot are depth-preferred: a new entry takes the shallowest (or stale)
+//! one if it is at least as deep. Otherwise it goes to the last slot, which
+//! is always replaced, so recent results are kept too.
+
+use std::mem::size_of;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::thread;
+
+use crate::board::{Move, PackedMove};
+
+// Transposition table entry types
+pub const TT_EXACT: u8 = 0;
+pub const TT_ALPHA: u8 = 1;
+pub const TT_BETA: u8 = 2;
+
+/// Transposition table entry
+#[derive(Clone)]
+pub struct TTEntry {
+    pub hash_key: u64,
+    pub depth: i32,
+    pub score: i32,
+    pub flag: u8,
+    pub best_move: Option<Move>,
+}
+
+// Layout of a packed entry:
+// bits  0-15  best move (a PackedMove)
+// bits 16-35  score, offset by SCORE_OFFSET
+// bits 36-43  depth, offset by DEPTH_OFFSET
+// bits 44-45  flag
+// bits 46-53  generation
+// bit  63     slot in use
+const MOVE_BITS: u32 = 16;
+const SCORE_SHIFT: u32 = 16;
+const SCORE_BITS: u32 = 20;
+const SCORE_OFFSET: i32 = 1 << (SCORE_BITS - 1);
+const DEPTH_SHIFT: u32 = 36;
+const DEPTH_OFFSET: i32 = 128;
+const FLAG_SHIFT: u32 = 44;
+const GENERATION_SHIFT: u32 = 46;
+const USED_BIT: u64 = 1 << 63;
+
+/// How much shallower than the entry already stored for a position a new
+/// result of the same search may be and still replace it
+const SAME_POSITION_DEPTH_MARGIN: i32 = 3;
+
+const fn mask(bits: u32) -> u64 {
+    (1 << bits) - 1
+}
+
+fn pack(depth: i32, score: i32, flag: u8, best_move: Option<Move>, generation: u8) -> u64 {
+    let score = (score + SCORE_OFFSET).clamp(0, mask(SCORE_BITS) as i32) as u64;
+    let depth = (depth + DEPTH_OFFSET).clamp(0, 0xff) as u64;
+    USED_BIT
+        | PackedMove::from_option(best_move).bits() as u64
+        | score << SCORE_SHIFT
+        | depth << DEPTH_SHIFT
+        | (flag as u64 & 0x3) << FLAG_SHIFT
+        | (generation as u64) << GENERATION_SHIFT
+}
+
+fn generation_of(data: u64) -> u8 {
+    (data >> GENERATION_SHIFT) as u8
+}
+
+fn unpack(hash_key: u64, data: u64) -> TTEntry {
+    TTEntry {
+        hash_key,
+        depth: (data >> DEPTH_SHIFT & 0xff) as i32 - DEPTH_OFFSET,
+        score: (data >> SCORE_SHIFT & mask(SCORE_BITS)) as i32 - SCORE_OFFSET,
+        flag: (data >> FLAG_SHIFT & 0x3) as u8,
+        best_move: PackedMove::from_bits((data & mask(MOVE_BITS)) as u16).to_move(),
+    }
+}
+
+/// One table slot: the packed entry and its hash key XORed with it
+#[derive(Default)]
+struct Slot {
+    key: AtomicU64,
+    data: AtomicU64,
+}
+
+impl Slot {
+    /// Hash key and packed entry, or None for an empty slot
+    #[inline]
+    fn load(&self) -> Option<(u64, u64)> {
+        let data = self.data.load(Ordering::Relaxed);
+        let key = self.key.load(Ordering::Relaxed);
+        (data & USED_BIT != 0).then_some((key ^ data, data))
+    }
+
+    #[inline]
+    fn save(&self, hash_key: u64, data: u64) {
+        self.key.store(hash_key ^ data, Ordering::Relaxed);
+        self.data.store(data, Ordering::Relaxed);
+    }
+
+    fn reset(&self) {
+        self.key.store(0, Ordering::Relaxed);
+        self.data.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Slots per cluster; the last one is the always-replace slot
+const CLUSTER_SIZE: usize = 4;
+
+/// Slots a position may be stored in, aligned to a cache line
+#[derive(Default)]
+#[repr(align(64))]
+struct Cluster {
+    slots: [Slot; CLUSTER_SIZE],
+}
+
+/// Number of shards the slots are split into for parallel allocation and
+/// clearing (a power of two)
+const SHARD_COUNT: usize = 256;
+
+/// Clusters sampled by `hashfull`
+const HASHFULL_SAMPLE: usize = 250;
+
+/// Lock-free transposition table: a fixed array of clusters split into
+/// shards, so allocation and clearing can be spread over several threads
+pub struct TranspositionTable {
+    shards: Vec<Box<[Cluster]>>,
+    /// Requested size in MB
+    size_mb: usize,
+    size: usize,
+    mask: u64,
+    /// Generation of the current search
+    generation: AtomicU8,
+}
+
+impl TranspositionTable {
+    pub fn new(size_mb: usize) -> Self {
+        TranspositionTable::with_threads(size_mb, 1)
+    }
+
+    /// Allocate and zero the table using `threads` threads
+    pub fn with_threads(size_mb: usize, threads: usize) -> Self {
+        // Largest power of two of clusters that fits, computed in u64 so
+        // large sizes cannot overflow on 32-bit targets
+        let bytes = size_mb.max(1) as u64 * 1024 * 1024;
+        let num_clusters = (bytes / size_of::<Cluster>() as u64).clamp(1, usize::MAX as u64);
+        let size = 1usize << num_clusters.ilog2();
+        let mask = (size - 1) as u64;
+
+        let shard_count = SHARD_COUNT.min(size);
+        let shard_len = size / shard_count;
+        let per_thread = shard_count.div_ceil(threads.max(1));
+        let shards = thread::scope(|scope| {
+            let handles: Vec<_> = (0..shard_count).step_by(per_thread)
+                .map(|first| {
+                    let count = per_thread.min(shard_count - first);
+                    scope.spawn(move || {
+                        (0..count)
+                            .map(|_| (0..shard_len).map(|_| Cluster::default()).collect::<Box<[Cluster]>>())
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect()
+        });
+
+        TranspositionTable { shards, size_mb, size, mask, generation: AtomicU8::new(0) }
+    }
+
+    /// Size the table was created for, in MB
+    pub fn size_mb(&self) -> usize {
+        self.size_mb
+    }
+
+    /// Reallocate the table for `size_mb` using `threads` threads, dropping
+    /// its contents; nothing happens when the size is unchanged
+    pub fn resize(&mut self, size_mb: usize, threads: usize) {
+        if size_mb == self.size_mb {
+            return;
+        }
+        // Free the old clusters before allocating the new ones
+        self.shards = Vec::new();
+        *self = TranspositionTable::with_threads(size_mb, threads);
+    }
+
+    /// Start a new generation; called once at the start of every search
+    pub fn new_search(&self) {
+        self.generation.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Cluster of a hash key
+    #[inline]
+    fn cluster(&self, hash_key: u64) -> &Cluster {
+        let index = (hash_key & self.mask) as usize;
+        let shard_count = self.shards.len();
+        &self.shards[index % shard_count][index / shard_count]
+    }
+
+    pub fn probe(&self, hash_key: u64) -> Option<TTEntry> {
+        self.cluster(hash_key).slots.iter().find_map(|slot| match slot.load() {
+            Some((key, data)) if key == hash_key => Some(unpack(key, data)),
+            _ => None,
+        })
+    }
+
+    pub fn store(&self, hash_key: u64, depth: i32, score: i32, flag: u8, best_move: Option<Move>) {
+        let slots = &self.cluster(hash_key).slots;
+        let generation = self.generation.load(Ordering::Relaxed);
+
+        // The position's own slot is updated unless it holds a much deeper
+        // result of this search (such as a full search under a quiescence
+        // result); a result without a best move keeps the stored one
+        let own_slot = slots.iter().find_map(|slot| match slot.load() {
+            Some((key, data)) if key == hash_key => Some((slot, unpack(key, data), generation_of(data))),
+            _ => None,
+        });
+        if let Some((slot, existing, existing_generation)) = own_slot {
+            if existing_generation != generation || depth + SAME_POSITION_DEPTH_MARGIN >= existing.depth {
+                let best_move = best_move.or(existing.best_move);
+                slot.save(hash_key, pack(depth, score, flag, best_move, generation));
+            }
+            return;
+        }
+
+        let data = pack(depth, score, flag, best_move, generation);
+
+        // Otherwise the least valuable depth-preferred slot: empty first,
+        // then entries of an earlier search, then the shallowest
+        let (depth_preferred, always_replace) = slots.split_at(CLUSTER_SIZE - 1);
+        let (victim, victim_depth) = depth_preferred.iter()
+            .map(|slot| {
+                let depth = match slot.load() {
+                    None => i32::MIN,
+                    Some((_, data)) if generation_of(data) != generation => i32::MIN + 1,
+                    Some((key, data)) => unpack(key, data).depth,
+                };
+                (slot, depth)
+            })
+            .min_by_key(|&(_, depth)| depth)
+            .unwrap();
+
+        if depth >= victim_depth {
+            victim.save(hash_key, data);
+        } else {
+            always_replace[0].save(hash_key, data);
+        }
+    }
+
+    pub fn clear(&self) {
+        self.clear_with_threads(1);
+    }
+
+    /// Zero the table using `threads` threads
+    pub fn clear_with_threads(&self, threads: usize) {
+        let per_thread = self.shards.len().div_ceil(threads.max(1));
+        thread::scope(|scope| {
+            for chunk in self.shards.chunks(per_thread) {
+                scope.spawn(move || {
+                    for cluster in chunk.iter().flat_map(|shard| shard.iter()) {
+                        cluster.slots.iter().for_each(Slot::reset);
+                    }
+                });
+            }
+        });
+    }
+
+    /// Permille of slots used by the current search, estimated from a
+    /// sample of them
+    pub fn hashfull(&self) -> usize {
+        let sample = HASHFULL_SAMPLE.min(self.size);
+        let generation = self.generation.load(Ordering::Relaxed);
+        let used = (0..sample)
+            .flat_map(|index| self.cluster(index as u64).slots.iter())
+            .filter(|slot| slot.load().is_some_and(|(_, data)| generation_of(data) == generation))
+            .count();
+        used * 1000 / (sample * CLUSTER_SIZE)
+    }
+}
@@ -2,18 +2,144 @@
 //!
 //! This module implements the Universal Chess Interface (UCI) protocol,
 //! allowing the engine to communicate with chess GUIs.
+//!
+//! `go` starts the search on a background thread so the command loop keeps
+//! reading input: `isready` is answered at once and `stop` interrupts the
+//! search. Other commands wait until the search has finished.
 
 use std::io::{self, BufRead, Write};
-use crate::types::*;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 use crate::board::{Board, Move};
 use crate::move_generator::MoveGenerator;
-use crate::parallel_search::ParallelSearchEngine;
+use crate::parallel_search::{self, ParallelMode, ParallelSearchEngine};
+use crate::perft::{self, PerftDiff, ReferenceEngine};
+use crate::cluster;
+use crate::explain;
+use crate::pgn::{self, Game};
+use crate::version;
+use crate::book::OpeningBook;
+use crate::nnue::{self, Network};
+use crate::search::{ScoreBound, SearchEngine, DEFAULT_CONTEMPT};
+use crate::search_tree::TreeBudget;
+use crate::strength::{self, StrengthLimit};
+use crate::wdl::{self, WdlModel};
+use crate::time_manager::{SearchLimits, TimeControl, TimeManager};
 
 // Engine identification
 const ENGINE_NAME: &str = "OpusChess";
 const ENGINE_AUTHOR: &str = "AI Assistant";
 const ENGINE_VERSION: &str = "2.1";
 
+// Default depth of `go evalall`
+const EVALALL_DEPTH: i32 = 3;
+
+// Default depth of `explain`
+const EXPLAIN_DEPTH: i32 = 8;
+
+// Default depth of `analyzepgn`, and the losses (internal centipawns) at
+// which a move is annotated as a blunder ($4), mistake ($2) or dubious ($6)
+const ANALYZE_DEPTH: i32 = 10;
+const ANNOTATION_NAGS: [(i32, u8); 3] = [(300, 4), (100, 2), (50, 6)];
+
+// Book verification: node budget of each check search and how far (internal
+// centipawns) a book move may score below the engine's choice
+const BOOK_VERIFY_NODES: u64 = 20000;
+const BOOK_VERIFY_MARGIN: i32 = 100;
+
+// Largest Hash option value in MB
+const MAX_HASH_MB: i32 = 32768;
+
+/// UCI score string: mate distance or normalized centipawns
+fn format_score(score: i32, wdl_model: &WdlModel) -> String {
+    if score.abs() > 40000 {
+        let mate_distance = (50000 - score.abs() + 1) / 2;
+        if score > 0 {
+            format!("mate {}", mate_distance)
+        } else {
+            format!("mate -{}", mate_distance)
+        }
+    } else {
+        format!("cp {}", wdl_model.normalize(score))
+    }
+}
+
+/// Write a line to the GUI
+fn send(message: &str) {
+    println!("{}", message);
+    io::stdout().flush().ok();
+}
+
+/// Search engine that reports its progress through the root moves
+fn new_engine(tt_size_mb: usize, num_threads: usize) -> ParallelSearchEngine {
+    let mut engine = ParallelSearchEngine::new(tt_size_mb, num_threads);
+    engine.on_current_move = Some(Box::new(|depth, mv: Move, number| {
+        send(&format!("info depth {} currmove {} currmovenumber {}", depth, mv.to_uci(), number));
+    }));
+    engine
+}
+
+/// Lock the shared engine; a search thread that panicked leaves it usable
+fn lock_engine(engine: &Mutex<ParallelSearchEngine>) -> MutexGuard<'_, ParallelSearchEngine> {
+    engine.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// info refutation <move> <line> for every root move that failed low,
+/// except the move finally played
+fn send_refutations(engine: &ParallelSearchEngine, best_move: Option<Move>) {
+    for (mv, line) in &engine.refutations {
+        if Some(*mv) == best_move {
+            continue;
+        }
+        let line: Vec<String> = line.iter().map(|m| m.to_uci()).collect();
+        send(&format!("info refutation {} {}", mv.to_uci(), line.join(" ")));
+    }
+}
+
+/// Search `board` briefly, once freely and once restricted to the book move
+/// `mv`, and reject the move if it scores clearly below the engine's own
+/// choice
+fn verify_book_move(engine: &mut ParallelSearchEngine, board: &Board, mv: Move) -> bool {
+    type NoInfo = fn(i32, i32, ScoreBound, u64, u64, &str, usize, u64);
+
+    engine.limits = SearchLimits::nodes(BOOK_VERIFY_NODES);
+    let (best_move, best_score) = engine.search::<NoInfo>(board, 30, None);
+    if best_move.is_none() || best_move == Some(mv) {
+        return true;
+    }
+
+    engine.root_moves = vec![mv];
+    engine.limits = SearchLimits::nodes(BOOK_VERIFY_NODES);
+    let (_, book_score) = engine.search::<NoInfo>(board, 30, None);
+    engine.root_moves.clear();
+
+    let accepted = book_score >= best_score - BOOK_VERIFY_MARGIN;
+    if !accepted {
+        send(&format!(
+            "info string book move {} rejected: {} vs {} for {}",
+            mv.to_uci(), book_score, best_score, best_move.map(|m| m.to_uci()).unwrap_or_default()
+        ));
+    }
+    accepted
+}
+
+/// Second PV move, if pondering is enabled and the PV still starts with
+/// `best_move` and the reply is legal after it
+fn ponder_move(engine: &ParallelSearchEngine, board: &Board, best_move: Move, ponder_enabled: bool) -> Option<Move> {
+    let pv = &engine.pv;
+    if !ponder_enabled || pv.len() < 2 || pv[0] != best_move {
+        return None;
+    }
+
+    let mut board = board.clone();
+    board.make_move(&best_move);
+    MoveGenerator::new().generate_legal_moves(&board)
+        .into_iter()
+        .find(|m| *m == pv[1])
+}
+
 /// UCI option representation
 #[derive(Clone)]
 pub struct UCIOption {
@@ -23,6 +149,7 @@ pub struct UCIOption {
     pub value: String,
     pub min: Option<i32>,
     pub max: Option<i32>,
+    pub vars: Vec<String>,
 }
 
 impl UCIOption {
@@ -34,6 +161,7 @@ impl UCIOption {
             value: default.to_string(),
             min: Some(min),
             max: Some(max),
+            vars: Vec::new(),
         }
     }
 
@@ -45,6 +173,7 @@ impl UCIOption {
             value: if default { "true".to_string() } else { "false".to_string() },
             min: None,
             max: None,
+            vars: Vec::new(),
         }
     }
 
@@ -56,6 +185,31 @@ impl UCIOption {
             value: String::new(),
             min: None,
             max: None,
+            vars: Vec::new(),
+        }
+    }
+
+    pub fn string(name: &str, default: &str) -> Self {
+        UCIOption {
+            name: name.to_string(),
+            opt_type: "string".to_string(),
+            default: default.to_string(),
+            value: default.to_string(),
+            min: None,
+            max: None,
+            vars: Vec::new(),
+        }
+    }
+
+    pub fn combo(name: &str, default: &str, vars: &[&str]) -> Self {
+        UCIOption {
+            name: name.to_string(),
+            opt_type: "combo".to_string(),
+            default: default.to_string(),
+            value: default.to_string(),
+            min: None,
+            max: None,
+            vars: vars.iter().map(|v| v.to_string()).collect(),
         }
     }
 
@@ -72,6 +226,16 @@ impl UCIOption {
             "check" => {
                 s.push_str(&format!(" default {}", self.default));
             }
+            "string" => {
+                let default = if self.default.is_empty() { "<empty>" } else { &self.default };
+                s.push_str(&format!(" default {}", default));
+            }
+            "combo" => {
+                s.push_str(&format!(" default {}", self.default));
+                for var in &self.vars {
+                    s.push_str(&format!(" var {}", var));
+                }
+            }
             _ => {}
         }
         
@@ -98,6 +262,16 @@ impl UCIOption {
                 };
                 return true;
             }
+            "string" => {
+                self.value = if value_str == "<empty>" { String::new() } else { value_str.to_string() };
+                return true;
+            }
+            "combo" => {
+                if let Some(var) = self.vars.iter().find(|v| v.eq_ignore_ascii_case(value_str)) {
+                    self.value = var.clone();
+                    return true;
+                }
+            }
             _ => {}
         }
         false
@@ -116,22 +290,53 @@ impl UCIOption {
 pub struct UCIProtocol {
     board: Board,
     move_generator: MoveGenerator,
-    search_engine: ParallelSearchEngine,
+    search_engine: Arc<Mutex<ParallelSearchEngine>>,
+    /// Search running in the background, the engine's stop flag, and
+    /// whether it was started with go infinite or go ponder
+    search_thread: Option<JoinHandle<()>>,
+    search_stop: Arc<AtomicBool>,
+    search_infinite: bool,
+    /// Set by stop and ponderhit; a finished go infinite or go ponder
+    /// search waits for it
+    stop_requested: Arc<AtomicBool>,
+    /// Arguments of setoption commands received during a search, applied
+    /// once it ends
+    deferred_options: Vec<Vec<String>>,
     running: bool,
     debug_mode: bool,
     options: Vec<UCIOption>,
+    /// Loaded opening book and the path it was read from
+    book: Option<OpeningBook>,
+    book_path: String,
+    /// Start position of the current game and the moves played from it
+    game: Game,
+    /// Score to win/draw/loss model and the config file it was read from
+    wdl_model: WdlModel,
+    wdl_path: String,
+    /// File the engine's network was read from (empty when none is loaded)
+    network_path: String,
 }
 
 impl UCIProtocol {
     pub fn new() -> Self {
-        let num_threads = num_cpus::get();
         let mut protocol = UCIProtocol {
             board: Board::new(),
             move_generator: MoveGenerator::new(),
-            search_engine: ParallelSearchEngine::new(64, num_threads),
+            search_engine: Arc::new(Mutex::new(new_engine(64, 0))),
+            search_thread: None,
+            search_stop: Arc::new(AtomicBool::new(false)),
+            search_infinite: false,
+            stop_requested: Arc::new(AtomicBool::new(false)),
+            deferred_options: Vec::new(),
             running: true,
             debug_mode: false,
             options: Vec::new(),
+            book: None,
+            book_path: String::new(),
+            game: Game::default(),
+            wdl_model: WdlModel::default(),
+            wdl_path: String::new(),
+            network_path: String::new(),
         };
         
         protocol.init_options();
@@ -139,12 +344,15 @@ impl UCIProtocol {
     }
 
     fn init_options(&mut self) {
-        let default_threads = num_cpus::get() as i32;
         self.options = vec![
-            UCIOption::spin("Threads", default_threads, 1, 256),
-            UCIOption::spin("Hash", 64, 1, 1024),
+            // 0 = one thread per physical core (one less while pondering)
+            UCIOption::spin("Threads", 0, 0, 256),
+            UCIOption::spin("Hash", 64, 1, MAX_HASH_MB),
+            UCIOption::combo("ParallelMode", ParallelMode::LazySmp.name(),
+                &[ParallelMode::LazySmp.name(), ParallelMode::RootSplit.name()]),
             UCIOption::spin("Depth", 10, 1, 30),
             UCIOption::check("Ponder", true),
+            UCIOption::spin("Contempt", DEFAULT_CONTEMPT, -100, 100),
             UCIOption::check("UseTranspositionTable", true),
             UCIOption::check("UseNullMove", true),
             UCIOption::check("UseLMR", true),
@@ -155,50 +363,210 @@ impl UCIProtocol {
             UCIOption::check("UseProbcut", true),
             UCIOption::check("UseSingularExtensions", true),
             UCIOption::check("UseCountermove", true),
+            UCIOption::check("SharedHistory", false),
+            UCIOption::check("Use NNUE", false),
+            UCIOption::string("EvalFile", nnue::DEFAULT_EVAL_FILE),
+            UCIOption::string("ClusterHosts", ""),
+            UCIOption::check("OwnBook", false),
+            UCIOption::string("BookFile", ""),
+            UCIOption::check("Book Verification", false),
+            UCIOption::string("WdlFile", ""),
+            UCIOption::check("UCI_ShowRefutations", false),
+            UCIOption::check("UCI_Chess960", false),
+            UCIOption::check("UCI_ShowWDL", false),
+            UCIOption::check("UCI_LimitStrength", false),
+            UCIOption::spin("UCI_Elo", 1500, strength::MIN_ELO, strength::MAX_ELO),
             UCIOption::button("Clear Hash"),
         ];
     }
 
     fn apply_options(&mut self) {
+        let mut book_file = None;
+        let mut wdl_file = None;
+        let mut use_nnue = false;
+        let mut eval_file = String::new();
+
         for opt in &self.options {
             match opt.name.as_str() {
                 "Threads" => {
-                    let threads = opt.get_int() as usize;
-                    self.search_engine.set_threads(threads);
+                    let threads = match opt.get_int() {
+                        0 => {
+                            let ponder = self.options.iter().any(|o| o.name == "Ponder" && o.get_bool());
+                            parallel_search::auto_thread_count(ponder)
+                        }
+                        n => n as usize,
+                    };
+                    self.engine().set_threads(threads);
                 }
                 "Hash" => {
-                    let size = opt.get_int() as usize;
-                    let threads = self.search_engine.num_threads;
-                    self.search_engine = ParallelSearchEngine::new(size, threads);
+                    self.engine().resize_tt(opt.get_int() as usize);
+                }
+                "ParallelMode" => {
+                    if let Some(mode) = ParallelMode::from_name(&opt.value) {
+                        self.engine().parallel_mode = mode;
+                    }
+                }
+                "Contempt" => {
+                    self.engine().options.contempt = opt.get_int();
                 }
                 "UseTranspositionTable" => {
-                    self.search_engine.use_tt = opt.get_bool();
+                    self.engine().options.use_tt = opt.get_bool();
                 }
                 "UseNullMove" => {
-                    self.search_engine.use_null_move = opt.get_bool();
+                    self.engine().options.use_null_move = opt.get_bool();
                 }
                 "UseLMR" => {
-                    self.search_engine.use_lmr = opt.get_bool();
+                    self.engine().options.use_lmr = opt.get_bool();
+                }
+                "UseRazoring" => {
+                    self.engine().options.use_razoring = opt.get_bool();
+                }
+                "UseProbcut" => {
+                    self.engine().options.use_probcut = opt.get_bool();
+                }
+                "UCI_ShowRefutations" => {
+                    self.engine().options.collect_refutations = opt.get_bool();
+                }
+                "UseCountermove" => {
+                    self.engine().options.use_countermove = opt.get_bool();
+                }
+                "SharedHistory" => {
+                    self.engine().use_shared_history = opt.get_bool();
+                }
+                "Use NNUE" => {
+                    use_nnue = opt.get_bool();
+                    self.engine().options.use_nnue = use_nnue;
+                }
+                "BookFile" => {
+                    book_file = Some(opt.value.clone());
+                }
+                "WdlFile" => {
+                    wdl_file = Some(opt.value.clone());
+                }
+                "EvalFile" => {
+                    eval_file = opt.value.clone();
                 }
                 _ => {}
             }
         }
+
+        if let Some(path) = book_file {
+            self.load_book(&path);
+        }
+        if let Some(path) = wdl_file {
+            self.load_wdl_model(&path);
+        }
+        if use_nnue {
+            self.load_network(&eval_file);
+        }
+
+        let eval_noise = self.strength_limit().map_or(0, |limit| limit.eval_noise);
+        self.engine().options.eval_noise = eval_noise;
+    }
+
+    /// Handicaps for UCI_Elo, when UCI_LimitStrength is on
+    fn strength_limit(&self) -> Option<StrengthLimit> {
+        let limited = self.options.iter()
+            .any(|opt| opt.name == "UCI_LimitStrength" && opt.get_bool());
+        let elo = self.options.iter().find(|opt| opt.name == "UCI_Elo")?.get_int();
+        limited.then(|| StrengthLimit::from_elo(elo))
+    }
+
+    /// (Re)load the WDL model parameters when the WdlFile path changes
+    fn load_wdl_model(&mut self, path: &str) {
+        if path == self.wdl_path {
+            return;
+        }
+
+        self.wdl_path = path.to_string();
+        self.wdl_model = WdlModel::default();
+        if path.is_empty() {
+            return;
+        }
+
+        match WdlModel::load(path) {
+            Ok(model) => self.wdl_model = model,
+            Err(e) => self.send(&format!("info string Could not load WDL model {}: {}", path, e)),
+        }
+    }
+
+    /// (Re)load the engine's network when the path changes; the search
+    /// falls back to the handcrafted evaluation while none is loaded
+    fn load_network(&mut self, path: &str) {
+        if path == self.network_path {
+            return;
+        }
+
+        self.network_path = path.to_string();
+        self.engine().network = None;
+        if path.is_empty() {
+            return;
+        }
+
+        match Network::load(path) {
+            Ok(network) => self.engine().network = Some(Arc::new(network)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => self.send(&format!(
+                "info string EvalFile {} not found, using the handcrafted evaluation", path)),
+            Err(e) => self.send(&format!(
+                "info string EvalFile {} is incompatible ({}), using the handcrafted evaluation", path, e)),
+        }
+    }
+
+    /// (Re)load the opening book when the BookFile path changes
+    fn load_book(&mut self, path: &str) {
+        if path == self.book_path {
+            return;
+        }
+
+        self.book_path = path.to_string();
+        self.book = None;
+        if path.is_empty() {
+            return;
+        }
+
+        match OpeningBook::open(path) {
+            Ok(book) => self.book = Some(book),
+            Err(e) => self.send(&format!("info string Could not load book {}: {}", path, e)),
+        }
+    }
+
+    /// Book move for the current game, if OwnBook is enabled and the game
+    /// is still in the book
+    fn book_move(&self) -> Option<Move> {
+        let own_book = self.options.iter()
+            .any(|opt| opt.name == "OwnBook" && opt.get_bool());
+        if !own_book {
+            return None;
+        }
+
+        let book_move = self.book.as_ref()?.pick(&self.book_line()?)?;
+        self.parse_move(&book_move)
+    }
+
+    /// Moves played from the initial position in UCI notation, the key
+    /// of book lookups (None for games started from a FEN)
+    fn book_line(&self) -> Option<Vec<String>> {
+        if !self.game.from_initial_position() {
+            return None;
+        }
+        Some(self.game.moves.iter().map(Move::to_uci).collect())
     }
 
     pub fn run(&mut self) {
         let stdin = io::stdin();
         
-        for line in stdin.lock().lines() {
-            if let Ok(line) = line {
-                let line = line.trim();
-                if !line.is_empty() {
-                    self.process_command(line);
-                }
-                if !self.running {
-                    break;
-                }
+        for line in stdin.lock().lines().map_while(Result::ok) {
+            let line = line.trim();
+            if !line.is_empty() {
+                self.process_command(line);
+            }
+            if !self.running {
+                break;
             }
         }
+
+        // Input closed: let a piped search still report its move
+        self.wait_for_search();
     }
 
     fn process_command(&mut self, line: &str) {
@@ -210,6 +578,12 @@ impl UCIProtocol {
         let command = parts[0];
         let args: Vec<&str> = parts[1..].to_vec();
 
+        // Only these are answered while a search runs in the background
+        // (setoption is queued until it ends)
+        if !matches!(command, "isready" | "stop" | "ponderhit" | "quit" | "debug" | "setoption") {
+            self.wait_for_search();
+        }
+
         match command {
             "uci" => self.cmd_uci(),
             "isready" => self.cmd_isready(),
@@ -218,11 +592,22 @@ impl UCIProtocol {
             "position" => self.cmd_position(&args),
             "go" => self.cmd_go(&args),
             "stop" => self.cmd_stop(),
+            "ponderhit" => self.cmd_ponderhit(),
             "quit" => self.cmd_quit(),
             "debug" => self.cmd_debug(&args),
             "d" => self.cmd_display(),
             "perft" => self.cmd_perft(&args),
+            "divide" => self.cmd_divide(&args),
+            "perftcompare" => self.cmd_perftcompare(&args),
+            "perftepd" => self.cmd_perftepd(&args),
+            "searchtree" => self.cmd_searchtree(&args),
+            "wdlfit" => self.cmd_wdlfit(&args),
+            "evalfile" => self.cmd_evalfile(&args),
             "bench" => self.cmd_bench(),
+            "explain" => self.cmd_explain(&args),
+            "pgn" => self.cmd_pgn(),
+            "analyzepgn" => self.cmd_analyzepgn(&args),
+            "version" | "compiler" => self.cmd_version(),
             _ => {
                 if self.debug_mode {
                     self.send(&format!("info string Unknown command: {}", command));
@@ -232,12 +617,16 @@ impl UCIProtocol {
     }
 
     fn send(&self, message: &str) {
-        println!("{}", message);
-        io::stdout().flush().ok();
+        send(message);
+    }
+
+    /// The search engine, once no search thread holds it
+    fn engine(&self) -> MutexGuard<'_, ParallelSearchEngine> {
+        lock_engine(&self.search_engine)
     }
 
     fn cmd_uci(&self) {
-        self.send(&format!("id name {} {}", ENGINE_NAME, ENGINE_VERSION));
+        self.send(&format!("id name {} {} ({})", ENGINE_NAME, ENGINE_VERSION, version::GIT_COMMIT));
         self.send(&format!("id author {}", ENGINE_AUTHOR));
         
         for option in &self.options {
@@ -252,6 +641,13 @@ impl UCIProtocol {
             return;
         }
 
+        // Options must not change under a running search
+        if self.search_thread.as_ref().is_some_and(|handle| !handle.is_finished()) {
+            self.deferred_options.push(args.iter().map(|arg| arg.to_string()).collect());
+            return;
+        }
+        self.wait_for_search();
+
         // Parse option name and value
         let mut name_parts = Vec::new();
         let mut value_str = None;
@@ -281,10 +677,8 @@ impl UCIProtocol {
                         clear_hash = true;
                     }
                 } else if let Some(ref val) = value_str {
-                    if opt.set_value(val) {
-                        if self.debug_mode {
-                            opt_set_msg = Some(format!("info string Option {} set to {}", name, opt.value));
-                        }
+                    if opt.set_value(val) && self.debug_mode {
+                        opt_set_msg = Some(format!("info string Option {} set to {}", name, opt.value));
                     }
                 }
                 break;
@@ -294,7 +688,7 @@ impl UCIProtocol {
             self.send(&msg);
         }
         if clear_hash {
-            self.search_engine.clear_tt();
+            self.engine().clear_tt();
             if self.debug_mode {
                 self.send("info string Hash table cleared");
             }
@@ -307,9 +701,19 @@ impl UCIProtocol {
         self.send("readyok");
     }
 
+    /// Build information: version, commit, date, target and features
+    fn cmd_version(&self) {
+        self.send(&format!("info string {} {}", ENGINE_NAME, ENGINE_VERSION));
+        for line in version::describe() {
+            self.send(&format!("info string {}", line));
+        }
+    }
+
     fn cmd_ucinewgame(&mut self) {
         self.board = Board::new();
-        self.search_engine.clear_tt();
+        self.game = Game::default();
+        self.engine().clear_tt();
+        self.engine().clear_history();
     }
 
     fn cmd_position(&mut self, args: &[&str]) {
@@ -334,8 +738,9 @@ impl UCIProtocol {
             
             if !fen_parts.is_empty() {
                 let fen = fen_parts.join(" ");
-                if let Some(board) = Board::from_fen(&fen) {
-                    self.board = board;
+                match Board::from_fen(&fen) {
+                    Ok(board) => self.board = board,
+                    Err(e) => self.send(&format!("info string Invalid FEN: {}", e)),
                 }
             }
             
@@ -344,34 +749,23 @@ impl UCIProtocol {
             }
         }
 
+        // Chess960 castling moves are sent and expected as king takes rook
+        self.board.chess960 = self.options.iter()
+            .any(|opt| opt.name == "UCI_Chess960" && opt.get_bool());
+        self.game = Game::new(self.board.clone());
+
         if let Some(idx) = moves_index {
             for move_str in &args[idx..] {
                 if let Some(mv) = self.parse_move(move_str) {
                     self.board.make_move(&mv);
+                    self.game.moves.push(mv);
                 }
             }
         }
     }
 
     fn parse_move(&self, move_str: &str) -> Option<Move> {
-        if move_str.len() < 4 {
-            return None;
-        }
-
-        let from_sq = parse_square(&move_str[0..2])?;
-        let to_sq = parse_square(&move_str[2..4])?;
-
-        let promotion = if move_str.len() == 5 {
-            match move_str.chars().nth(4)? {
-                'q' | 'Q' => QUEEN,
-                'r' | 'R' => ROOK,
-                'b' | 'B' => BISHOP,
-                'n' | 'N' => KNIGHT,
-                _ => 0,
-            }
-        } else {
-            0
-        };
+        let Move { from_sq, to_sq, promotion, .. } = Move::from_uci(move_str)?;
 
         let legal_moves = self.move_generator.generate_legal_moves(&self.board);
 
@@ -399,6 +793,16 @@ impl UCIProtocol {
     }
 
     fn cmd_go(&mut self, args: &[&str]) {
+        if args.first() == Some(&"perft") {
+            self.cmd_divide(&args[1..]);
+            return;
+        }
+
+        if args.first() == Some(&"evalall") {
+            self.cmd_evalall(&args[1..]);
+            return;
+        }
+
         let mut depth = 6;
         
         // Parse depth option
@@ -408,20 +812,47 @@ impl UCIProtocol {
             }
         }
 
+        let mut time_control = TimeControl::default();
+        let mut node_limit = None;
+        let mut explicit_depth = false;
+        let mut infinite = false;
+        let mut ponder = false;
+
         let mut i = 0;
         while i < args.len() {
             match args[i] {
                 "depth" if i + 1 < args.len() => {
                     if let Ok(d) = args[i + 1].parse::<i32>() {
                         depth = d;
+                        explicit_depth = true;
                     }
                     i += 2;
                 }
+                "nodes" if i + 1 < args.len() => {
+                    node_limit = args[i + 1].parse::<u64>().ok();
+                    i += 2;
+                }
                 "infinite" => {
                     depth = 30;
+                    infinite = true;
                     i += 1;
                 }
-                "wtime" | "btime" | "winc" | "binc" | "movestogo" | "movetime" => {
+                "ponder" => {
+                    ponder = true;
+                    i += 1;
+                }
+                "wtime" | "btime" | "winc" | "binc" | "movestogo" | "movetime" if i + 1 < args.len() => {
+                    if let Ok(value) = args[i + 1].parse::<i64>() {
+                        let value = value.max(0) as u64;
+                        match args[i] {
+                            "wtime" => time_control.wtime = Some(value),
+                            "btime" => time_control.btime = Some(value),
+                            "winc" => time_control.winc = value,
+                            "binc" => time_control.binc = value,
+                            "movestogo" => time_control.movestogo = Some(value),
+                            _ => time_control.movetime = Some(value),
+                        }
+                    }
                     i += 2;
                 }
                 _ => {
@@ -430,53 +861,241 @@ impl UCIProtocol {
             }
         }
 
+        // go infinite and go ponder must not answer before stop (or
+        // ponderhit), even when there is nothing to search
+        let hold = infinite || ponder;
+        let game_over = self.report_game_over();
+        if game_over && !hold {
+            self.send("bestmove 0000");
+            return;
+        }
+
+        // A book move is an immediate answer, which go infinite and go
+        // ponder must not give; its verification searches run on the search
+        // thread, so stop and isready are still read meanwhile
+        let book_move = if hold || game_over { None } else { self.book_move() };
+        let verify_book = self.options.iter()
+            .any(|opt| opt.name == "Book Verification" && opt.get_bool());
+
+        // Depth, nodes and clock limits all apply; the search stops at the
+        // first one reached. Without an explicit depth the other limits
+        // decide alone.
+        let mut limits = SearchLimits {
+            depth: None,
+            nodes: node_limit,
+            time: TimeManager::new(&time_control, &self.board),
+        };
+        if limits.is_limited() && !explicit_depth {
+            depth = 30;
+        }
         depth = depth.min(30);
 
+        // A weakened engine never searches past its handicaps
+        if let Some(strength) = self.strength_limit() {
+            depth = depth.min(strength.depth);
+            limits.nodes = Some(limits.nodes.map_or(strength.nodes, |nodes| nodes.min(strength.nodes)));
+        }
+        self.engine().options.noise_seed = rand::random();
+
+        let hosts = self.options.iter()
+            .find(|opt| opt.name == "ClusterHosts")
+            .map(|opt| cluster::parse_hosts(&opt.value))
+            .unwrap_or_default();
+
         // Search with info callback
-        let (best_move, _score) = self.search_engine.search(&self.board, depth, Some(|d: i32, s: i32, n: u64, t: u64, pv: &str, hf: usize, nps: u64| {
-            // Format score
-            let score_str = if s.abs() > 40000 {
-                let mate_distance = (50000 - s.abs() + 1) / 2;
-                if s > 0 {
-                    format!("mate {}", mate_distance)
-                } else {
-                    format!("mate -{}", mate_distance)
-                }
-            } else {
-                format!("cp {}", s)
-            };
-            
+        // Scores are normalized only here, at the reporting boundary
+        let wdl_model = self.wdl_model;
+        let show_wdl = self.options.iter()
+            .any(|opt| opt.name == "UCI_ShowWDL" && opt.get_bool());
+        let phase = self.board.phase();
+        let report = move |d: i32, s: i32, bound: ScoreBound, n: u64, t: u64, pv: &str, hf: usize, nps: u64| {
+            let mut score_str = format_score(s, &wdl_model);
+            match bound {
+                ScoreBound::Exact => {}
+                ScoreBound::Lower => score_str.push_str(" lowerbound"),
+                ScoreBound::Upper => score_str.push_str(" upperbound"),
+            }
+            if show_wdl {
+                let (win, draw, loss) = wdl_model.wdl_permille(s, phase);
+                score_str.push_str(&format!(" wdl {} {} {}", win, draw, loss));
+            }
+
             let info = format!(
                 "info depth {} score {} nodes {} time {} nps {} hashfull {} pv {}",
                 d, score_str, n, t, nps, hf, pv
             );
-            println!("{}", info);
-            io::stdout().flush().ok();
-        }));
+            send(&info);
+        };
 
-        // Get ponder move from PV
-        let mut ponder_str = String::new();
-        if self.search_engine.pv.len() >= 2 {
-            ponder_str = format!(" ponder {}", self.search_engine.pv[1].to_uci());
-        }
+        let ponder_enabled = self.options.iter()
+            .any(|opt| opt.name == "Ponder" && opt.get_bool());
+        let board = self.board.clone();
+        let search_engine = Arc::clone(&self.search_engine);
+        let stop_requested = Arc::clone(&self.stop_requested);
+        stop_requested.store(false, Ordering::SeqCst);
+        let search_stop = self.engine().stop_handle();
+        self.search_stop = search_stop;
+        self.search_infinite = hold;
 
-        if let Some(mv) = best_move {
-            self.send(&format!("bestmove {}{}", mv.to_uci(), ponder_str));
-        } else {
-            let legal_moves = self.move_generator.generate_legal_moves(&self.board);
-            if !legal_moves.is_empty() {
-                self.send(&format!("bestmove {}", legal_moves[0].to_uci()));
+        // The search runs on its own thread so the main loop keeps reading
+        // commands; stop ends it and waits for its bestmove
+        self.search_thread = Some(thread::spawn(move || {
+            let mut engine = lock_engine(&search_engine);
+            let book_move = book_move.filter(|&mv| !verify_book || verify_book_move(&mut engine, &board, mv));
+            let best_move = if game_over {
+                None
+            } else if book_move.is_some() {
+                book_move
             } else {
-                self.send("bestmove 0000");
+                engine.limits = limits;
+                if hosts.is_empty() {
+                    engine.search(&board, depth, Some(report)).0
+                } else {
+                    let result = cluster::search(&mut engine, &board, depth, &hosts, Some(report));
+                    send(&format!("info string cluster nodes {} workers {}", result.nodes, hosts.len()));
+                    result.best_move
+                }
+            };
+
+            // go infinite and go ponder must not answer before stop, even
+            // once done
+            while hold && !stop_requested.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(1));
             }
+
+            if engine.options.collect_refutations && book_move.is_none() {
+                send_refutations(&engine, best_move);
+            }
+
+            if let Some(mv) = best_move {
+                // Get ponder move from PV (a book move has none)
+                let ponder_str = match ponder_move(&engine, &board, mv, ponder_enabled && book_move.is_none()) {
+                    Some(reply) => format!(" ponder {}", reply.to_uci()),
+                    None => String::new(),
+                };
+                send(&format!("bestmove {}{}", mv.to_uci(), ponder_str));
+            } else {
+                let legal_moves = MoveGenerator::new().generate_legal_moves(&board);
+                if !legal_moves.is_empty() {
+                    send(&format!("bestmove {}", legal_moves[0].to_uci()));
+                } else {
+                    send("bestmove 0000");
+                }
+            }
+        }));
+    }
+
+    /// Report the score of a position without legal moves, mate or
+    /// stalemate, which is not searched; the caller answers `bestmove 0000`.
+    /// Dead positions (no mating material left) are searched like any other,
+    /// so the move played is still a sensible one. Returns whether the game
+    /// is over.
+    fn report_game_over(&self) -> bool {
+        if !self.move_generator.generate_legal_moves(&self.board).is_empty() {
+            return false;
+        }
+
+        let score = if self.move_generator.is_in_check(&self.board) { "mate 0" } else { "cp 0" };
+        self.send(&format!("info depth 0 score {}", score));
+        true
+    }
+
+    /// explain [depth <n>]: search the position, then the same depth without
+    /// the chosen move, and describe why the move was preferred
+    fn cmd_explain(&mut self, args: &[&str]) {
+        type NoInfo = fn(i32, i32, ScoreBound, u64, u64, &str, usize, u64);
+
+        let depth = match args {
+            ["depth", d, ..] => d.parse::<i32>().unwrap_or(EXPLAIN_DEPTH),
+            _ => EXPLAIN_DEPTH,
+        }.clamp(1, 30);
+
+        // The search fills the hash table for the one pass that scores
+        // every move, so the best move and the runner-up are comparable
+        let (searched_move, _) = self.engine().search::<NoInfo>(&self.board, depth, None);
+        let mut pv = self.engine().pv.clone();
+        let scores = self.engine().score_root_moves(&self.board, depth);
+        let Some(&(best_move, score)) = scores.first() else {
+            self.send("info string explain: no legal moves");
+            return;
+        };
+        let runner_up = scores.get(1).copied();
+
+        if searched_move != Some(best_move) || pv.is_empty() {
+            self.engine().root_moves = vec![best_move];
+            self.engine().search::<NoInfo>(&self.board, depth, None);
+            pv = match self.engine().pv.clone() {
+                line if line.is_empty() => vec![best_move],
+                line => line,
+            };
+            self.engine().root_moves.clear();
+        }
+
+        for line in explain::explain(&self.board, &pv, score, runner_up, &self.wdl_model) {
+            self.send(&format!("info string explain {}", line));
+        }
+    }
+
+    /// go evalall [depth <n>]: score every legal move with a fixed shallow
+    /// search (depth 1 = quiescence after the move) and list them best first
+    fn cmd_evalall(&mut self, args: &[&str]) {
+        let depth = match args {
+            ["depth", d, ..] => d.parse::<i32>().unwrap_or(EVALALL_DEPTH),
+            _ => EVALALL_DEPTH,
+        }.clamp(1, 30);
+
+        let scores = self.engine().score_root_moves(&self.board, depth);
+        for (mv, score) in &scores {
+            self.send(&format!("info string evalall move {} score {}", mv.to_uci(), format_score(*score, &self.wdl_model)));
+        }
+
+        match scores.first() {
+            Some((mv, _)) => self.send(&format!("bestmove {}", mv.to_uci())),
+            None => self.send("bestmove 0000"),
         }
     }
 
     fn cmd_stop(&mut self) {
-        self.search_engine.stop();
+        self.stop_requested.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.search_thread.take() {
+            // Repeat the stop until the search ends, in case it only just
+            // started and cleared the flag
+            while !handle.is_finished() {
+                self.search_stop.store(true, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(1));
+            }
+            handle.join().ok();
+        }
+        self.apply_deferred_options();
+    }
+
+    /// The opponent played the expected move: a go ponder search may answer
+    /// once it reaches its limits
+    fn cmd_ponderhit(&mut self) {
+        self.stop_requested.store(true, Ordering::SeqCst);
+    }
+
+    /// Let a running search finish before a command that needs the engine;
+    /// an infinite or pondering search only ends with stop, so it is stopped
+    fn wait_for_search(&mut self) {
+        if self.search_infinite {
+            self.cmd_stop();
+        } else if let Some(handle) = self.search_thread.take() {
+            handle.join().ok();
+        }
+        self.apply_deferred_options();
+    }
+
+    /// Apply the setoption commands queued during the search, in order
+    fn apply_deferred_options(&mut self) {
+        for args in std::mem::take(&mut self.deferred_options) {
+            let args: Vec<&str> = args.iter().map(String::as_str).collect();
+            self.cmd_setoption(&args);
+        }
     }
 
     fn cmd_quit(&mut self) {
+        self.cmd_stop();
         self.running = false;
     }
 
@@ -496,7 +1115,7 @@ impl UCIProtocol {
         let legal_moves = self.move_generator.generate_legal_moves(&self.board);
         self.send(&format!("Legal moves: {}", legal_moves.len()));
         
-        let move_list: Vec<String> = legal_moves.iter().take(20).map(|m| m.to_uci()).collect();
+        let move_list: Vec<String> = legal_moves.iter().take(20).map(|m| m.to_san(&self.board)).collect();
         let mut moves_str = move_list.join(" ");
         if legal_moves.len() > 20 {
             moves_str.push_str(" ...");
@@ -504,35 +1123,372 @@ impl UCIProtocol {
         self.send(&format!("Moves: {}", moves_str));
     }
 
+    /// Print the current game as PGN
+    fn cmd_pgn(&self) {
+        for line in self.game.to_pgn().lines() {
+            self.send(line);
+        }
+    }
+
+    /// Annotate the games of a PGN file:
+    /// `analyzepgn <path> [depth <d>] [movetime <ms>] [output <path>]`
+    fn cmd_analyzepgn(&mut self, args: &[&str]) {
+        let Some(&path) = args.first() else {
+            self.send("info string Usage: analyzepgn <path> [depth <d>] [movetime <ms>] [output <path>]");
+            return;
+        };
+
+        let mut depth = None;
+        let mut movetime = None;
+        let mut output = std::path::Path::new(path).with_extension("annotated.pgn").display().to_string();
+        for pair in args[1..].chunks(2) {
+            match pair {
+                ["depth", d] => depth = d.parse::<i32>().ok().map(|d| d.clamp(1, 30)),
+                ["movetime", ms] => movetime = ms.parse::<u64>().ok(),
+                ["output", out] => output = out.to_string(),
+                _ => {}
+            }
+        }
+        // As with go, a time limit alone decides how deep to search
+        let depth = depth.unwrap_or(if movetime.is_some() { 30 } else { ANALYZE_DEPTH });
+
+        let games = match std::fs::read_to_string(path) {
+            Ok(text) => pgn::read_games(&text),
+            Err(e) => {
+                self.send(&format!("info string Cannot read {}: {}", path, e));
+                return;
+            }
+        };
+        let mut games = match games {
+            Ok(games) => games,
+            Err(e) => {
+                self.send(&format!("info string Cannot import {}: {}", path, e));
+                return;
+            }
+        };
+
+        let count = games.len();
+        let mut annotated = String::new();
+        for (i, game) in games.iter_mut().enumerate() {
+            self.annotate_game(game, depth, movetime);
+            self.send(&format!("info string analyzepgn game {}/{}: {} moves", i + 1, count, game.moves.len()));
+            if i > 0 {
+                annotated.push('\n');
+            }
+            annotated.push_str(&game.to_pgn());
+        }
+
+        match std::fs::write(&output, annotated) {
+            Ok(()) => self.send(&format!("info string analyzepgn wrote {} games to {}", count, output)),
+            Err(e) => self.send(&format!("info string Cannot write {}: {}", output, e)),
+        }
+    }
+
+    /// Comment every move of `game` with the evaluation after it, and mark
+    /// mistakes with a NAG and the line the engine prefers
+    fn annotate_game(&mut self, game: &mut Game, depth: i32, movetime: Option<u64>) {
+        type NoInfo = fn(i32, i32, ScoreBound, u64, u64, &str, usize, u64);
+
+        let mut board = game.start.clone();
+        for i in 0..game.moves.len() {
+            let played = game.moves[i];
+            let time_control = TimeControl { movetime, ..TimeControl::default() };
+
+            self.engine().limits = SearchLimits { time: TimeManager::new(&time_control, &board), ..SearchLimits::default() };
+            let (best_move, best_score) = self.engine().search::<NoInfo>(&board, depth, None);
+            let Some(best_move) = best_move else { break };
+            let pv = match self.engine().pv.clone() {
+                pv if pv.is_empty() => vec![best_move],
+                pv => pv,
+            };
+
+            let played_score = if played == best_move {
+                best_score
+            } else {
+                self.engine().root_moves = vec![played];
+                self.engine().limits = SearchLimits { time: TimeManager::new(&time_control, &board), ..SearchLimits::default() };
+                let (_, score) = self.engine().search::<NoInfo>(&board, depth, None);
+                self.engine().root_moves.clear();
+                score
+            };
+
+            let loss = best_score - played_score;
+            let note = game.note_mut(i);
+            note.comment = Some(pgn::format_eval(if board.white_to_move { played_score } else { -played_score }));
+            note.nag = ANNOTATION_NAGS.iter()
+                .find(|&&(threshold, _)| loss >= threshold)
+                .map(|&(_, nag)| nag);
+            if note.nag.is_some() {
+                note.variation = pv;
+            }
+
+            board.make_move(&played);
+        }
+    }
+
     fn cmd_perft(&self, args: &[&str]) {
         let depth = args.first()
             .and_then(|s| s.parse::<usize>().ok())
             .unwrap_or(1);
 
         let mut board = self.board.clone();
-        let nodes = self.perft(&mut board, depth);
+        let start = Instant::now();
+        let nodes = perft::perft(&self.move_generator, &mut board, depth);
         self.send(&format!("Nodes: {}", nodes));
+        self.send_perft_speed(nodes, start);
+    }
+
+    /// Print per-move perft counts in the format used by `go perft`
+    fn cmd_divide(&self, args: &[&str]) {
+        let depth = args.first()
+            .and_then(|s| s.parse::<usize>().ok())
+            .unwrap_or(1);
+
+        let mut board = self.board.clone();
+        let start = Instant::now();
+        let results = perft::divide(&self.move_generator, &mut board, depth);
+        let mut total = 0u64;
+        for (mv, nodes) in &results {
+            self.send(&format!("{}: {}", mv.to_uci(), nodes));
+            total += nodes;
+        }
+        self.send("");
+        self.send(&format!("Nodes searched: {}", total));
+        self.send_perft_speed(total, start);
+    }
+
+    /// Report the time a perft run took and its speed in nodes per second
+    fn send_perft_speed(&self, nodes: u64, start: Instant) {
+        let ms = start.elapsed().as_millis() as u64;
+        self.send(&format!("info string time {} ms nps {}", ms, nodes * 1000 / ms.max(1)));
+    }
+
+    /// Verify every position of a perft EPD suite (`;D1 20 ;D2 400 ...`),
+    /// printing divide output for failures:
+    /// `perftepd <path> [max depth]`
+    fn cmd_perftepd(&self, args: &[&str]) {
+        let path = match args.first() {
+            Some(path) => *path,
+            None => {
+                self.send("info string Usage: perftepd <path> [max depth]");
+                return;
+            }
+        };
+        let max_depth = args.get(1).and_then(|s| s.parse::<usize>().ok()).unwrap_or(usize::MAX);
+
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                self.send(&format!("info string Cannot read {}: {}", path, e));
+                return;
+            }
+        };
+
+        let (mut passed, mut failed) = (0, 0);
+        for case in contents.lines().filter_map(perft::parse_epd_line) {
+            let board = match Board::from_fen(&case.fen) {
+                Ok(board) => board,
+                Err(e) => {
+                    self.send(&format!("info string Invalid FEN {}: {}", case.fen, e));
+                    failed += 1;
+                    continue;
+                }
+            };
+
+            for &(depth, expected) in case.expected.iter().filter(|(d, _)| *d <= max_depth) {
+                let mut board = board.clone();
+                let nodes = perft::perft(&self.move_generator, &mut board, depth);
+                if nodes == expected {
+                    passed += 1;
+                    continue;
+                }
+
+                failed += 1;
+                self.send(&format!("FAIL {} depth {}: expected {} got {}", case.fen, depth, expected, nodes));
+                for (mv, count) in perft::divide(&self.move_generator, &mut board, depth) {
+                    self.send(&format!("  {}: {}", mv.to_uci(), count));
+                }
+            }
+        }
+
+        self.send(&format!("Perft suite: {} passed, {} failed", passed, failed));
+    }
+
+    /// Bisect perft differences against an external engine:
+    /// `perftcompare <depth> <engine path>`
+    fn cmd_perftcompare(&self, args: &[&str]) {
+        let depth = match args.first().and_then(|s| s.parse::<usize>().ok()) {
+            Some(d) if args.len() > 1 => d,
+            _ => {
+                self.send("info string Usage: perftcompare <depth> <engine path>");
+                return;
+            }
+        };
+        let path = args[1..].join(" ");
+
+        let mut reference = match ReferenceEngine::spawn(&path) {
+            Ok(engine) => engine,
+            Err(e) => {
+                self.send(&format!("info string Cannot start {}: {}", path, e));
+                return;
+            }
+        };
+
+        let result = perft::compare_with_reference(
+            &self.move_generator, &self.board, depth, &mut reference,
+            |line| self.send(line),
+        );
+
+        match result {
+            Ok(PerftDiff::Match) => self.send("Perft matches reference"),
+            Ok(PerftDiff::ExtraMove { path, mv }) => {
+                self.send(&format!("Illegal move generated: {} after [{}]", mv, path.join(" ")));
+            }
+            Ok(PerftDiff::MissingMove { path, mv }) => {
+                self.send(&format!("Legal move not generated: {} after [{}]", mv, path.join(" ")));
+            }
+            Err(e) => self.send(&format!("info string Reference engine error: {}", e)),
+        }
     }
 
-    fn perft(&self, board: &mut Board, depth: usize) -> u64 {
-        if depth == 0 {
-            return 1;
+    /// Debug command: search the current position single-threaded and dump
+    /// the explored tree of the final iteration as JSON or Graphviz
+    /// (chosen by the `.dot` / `.gv` extension).
+    ///
+    /// Usage: searchtree <depth> <file> [nodes <n>] [plies <n>]
+    fn cmd_searchtree(&self, args: &[&str]) {
+        let (depth, path) = match (args.first().and_then(|s| s.parse::<i32>().ok()), args.get(1)) {
+            (Some(depth), Some(path)) => (depth.clamp(1, 30), *path),
+            _ => {
+                self.send("info string Usage: searchtree <depth> <file> [nodes <n>] [plies <n>]");
+                return;
+            }
+        };
+
+        let mut budget = TreeBudget::default();
+        let mut i = 2;
+        while i + 1 < args.len() {
+            match (args[i], args[i + 1].parse::<usize>()) {
+                ("nodes", Ok(n)) => budget.max_nodes = n,
+                ("plies", Ok(n)) => budget.max_ply = n,
+                _ => {}
+            }
+            i += 2;
+        }
+
+        let mut engine = SearchEngine::new(16);
+        engine.options = self.engine().options;
+        engine.network = self.engine().network.clone();
+        engine.record_tree(budget);
+        let (best_move, score) = engine.search::<fn(i32, i32, ScoreBound, u64, u64, &str, usize, u64)>(&self.board, depth, None);
+
+        let tree = match engine.take_tree() {
+            Some(tree) => tree,
+            None => return,
+        };
+        let output = if path.ends_with(".dot") || path.ends_with(".gv") { tree.to_dot() } else { tree.to_json() };
+
+        match std::fs::write(path, output) {
+            Ok(()) => self.send(&format!(
+                "info string Wrote {} nodes ({} over budget) to {}, best {} score {}",
+                tree.nodes().len(), tree.dropped, path,
+                best_move.map(|m| m.to_uci()).unwrap_or_else(|| "none".to_string()), score
+            )),
+            Err(e) => self.send(&format!("info string Cannot write {}: {}", path, e)),
         }
+    }
+
+    /// Fit the WDL model to a labelled dataset, optionally saving the
+    /// parameters to a config file usable with the WdlFile option.
+    ///
+    /// Usage: wdlfit <dataset> [save <config>]
+    fn cmd_wdlfit(&self, args: &[&str]) {
+        let path = match args.first() {
+            Some(path) => *path,
+            None => {
+                self.send("info string Usage: wdlfit <dataset> [save <config>]");
+                return;
+            }
+        };
+
+        let samples = match wdl::load_samples(path) {
+            Ok(samples) if !samples.is_empty() => samples,
+            Ok(_) => {
+                self.send(&format!("info string No labelled positions in {}", path));
+                return;
+            }
+            Err(e) => {
+                self.send(&format!("info string Cannot read {}: {}", path, e));
+                return;
+            }
+        };
 
-        let moves = self.move_generator.generate_legal_moves(board);
+        let (model, likelihood) = wdl::fit(&samples, self.wdl_model);
+        self.send(&format!("Positions: {}", samples.len()));
+        self.send(&format!("wdl_a = {:.2}", model.a));
+        self.send(&format!("wdl_b = {:.2}", model.b));
+        self.send(&format!("Log-likelihood per position: {:.5}", likelihood));
 
-        if depth == 1 {
-            return moves.len() as u64;
+        if let (Some(&"save"), Some(config)) = (args.get(1), args.get(2)) {
+            match model.save(config) {
+                Ok(()) => self.send(&format!("info string Saved WDL model to {}", config)),
+                Err(e) => self.send(&format!("info string Cannot write {}: {}", config, e)),
+            }
         }
+    }
+
+    /// Evaluate every FEN of a file (one per line) on all threads and print
+    /// `fen;static_eval;qsearch_eval` lines, or write them to `output`.
+    ///
+    /// Usage: evalfile <path> [output]
+    fn cmd_evalfile(&mut self, args: &[&str]) {
+        let path = match args.first() {
+            Some(path) => *path,
+            None => {
+                self.send("info string Usage: evalfile <path> [output]");
+                return;
+            }
+        };
 
-        let mut nodes = 0u64;
-        for mv in moves {
-            let undo = board.make_move(&mv);
-            nodes += self.perft(board, depth - 1);
-            board.unmake_move(&mv, &undo);
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) => {
+                self.send(&format!("info string Cannot read {}: {}", path, e));
+                return;
+            }
+        };
+
+        let mut fens = Vec::new();
+        let mut boards = Vec::new();
+        for line in contents.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            // The side to move is required for a meaningful evaluation
+            let board = Board::from_fen(line).ok().filter(|_| line.split_whitespace().count() >= 2);
+            match board {
+                Some(board) => {
+                    fens.push(line);
+                    boards.push(board);
+                }
+                None => self.send(&format!("info string Skipping invalid FEN: {}", line)),
+            }
         }
 
-        nodes
+        let evals = self.engine().evaluate_positions(&boards);
+        let lines: Vec<String> = fens.iter()
+            .zip(&evals)
+            .map(|(fen, (static_eval, qsearch_eval))| format!("{};{};{}", fen, static_eval, qsearch_eval))
+            .collect();
+
+        match args.get(1) {
+            Some(output) => match std::fs::write(output, lines.join("\n") + "\n") {
+                Ok(()) => self.send(&format!("info string Wrote {} evaluations to {}", lines.len(), output)),
+                Err(e) => self.send(&format!("info string Cannot write {}: {}", output, e)),
+            },
+            None => {
+                for line in &lines {
+                    self.send(line);
+                }
+            }
+        }
     }
 
     fn cmd_bench(&mut self) {
@@ -548,13 +1504,13 @@ impl UCIProtocol {
         let start_time = Instant::now();
 
         for fen in &positions {
-            if let Some(board) = Board::from_fen(fen) {
+            if let Ok(board) = Board::from_fen(fen) {
                 self.board = board;
-                self.search_engine.clear_tt();
-                let (_, _) = self.search_engine.search::<fn(i32, i32, u64, u64, &str, usize, u64)>(
+                self.engine().clear_tt();
+                let (_, _) = self.engine().search::<fn(i32, i32, ScoreBound, u64, u64, &str, usize, u64)>(
                     &self.board, 5, None
                 );
-                total_nodes += self.search_engine.nodes_searched;
+                total_nodes += self.engine().nodes_searched;
             }
         }
 
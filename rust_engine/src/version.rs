@@ -0,0 +1,48 @@
+//! OpusChess - Build Information Module
+//!
+//! This module describes the exact binary: package version, git commit and
+//! build date (recorded by `build.rs`), target, the CPU features the code
+//! was compiled for and the enabled cargo features. It backs the engine
+//! identification and the `version` command, so bug reports and benchmark
+//! results can be traced to a build.
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const GIT_COMMIT: &str = env!("OPUS_GIT_COMMIT");
+pub const BUILD_DATE: &str = env!("OPUS_BUILD_DATE");
+pub const TARGET: &str = env!("OPUS_TARGET");
+
+/// CPU features enabled at compile time (e.g. with -C target-cpu=native)
+pub fn cpu_features() -> Vec<&'static str> {
+    let features = [
+        ("popcnt", cfg!(target_feature = "popcnt")),
+        ("sse4.2", cfg!(target_feature = "sse4.2")),
+        ("avx2", cfg!(target_feature = "avx2")),
+        ("bmi2", cfg!(target_feature = "bmi2")),
+        ("neon", cfg!(target_feature = "neon")),
+    ];
+    features.iter().filter(|&&(_, enabled)| enabled).map(|&(name, _)| name).collect()
+}
+
+/// Cargo features the binary was built with
+pub fn cargo_features() -> Vec<&'static str> {
+    let features = [
+        ("trace", cfg!(feature = "trace")),
+        ("serde", cfg!(feature = "serde")),
+    ];
+    features.iter().filter(|&&(_, enabled)| enabled).map(|&(name, _)| name).collect()
+}
+
+fn list(items: &[&str]) -> String {
+    if items.is_empty() { "none".to_string() } else { items.join(" ") }
+}
+
+/// Lines printed by the `version` command
+pub fn describe() -> Vec<String> {
+    let profile = if cfg!(debug_assertions) { "debug" } else { "release" };
+    vec![
+        format!("version {} commit {} built {}", VERSION, GIT_COMMIT, BUILD_DATE),
+        format!("target {} profile {}", TARGET, profile),
+        format!("cpu features {}", list(&cpu_features())),
+        format!("cargo features {}", list(&cargo_features())),
+    ]
+}
@@ -0,0 +1,210 @@
+//! OpusChess - Win/Draw/Loss Model Module
+//!
+//! This module maps search scores to expected game outcomes with a logistic
+//! model: the win probability at score `v` (centipawns, side to move) is
+//! `1 / (1 + exp((a - v) / b))` and the loss probability is the win
+//! probability of `-v`. `a` is the score at which the side to move wins half
+//! of its games and `b` controls how quickly the curve saturates.
+//!
+//! Reported centipawn scores are normalized with the same model so that
+//! +100 corresponds to a 50% win probability.
+//!
+//! For the `wdl` output of UCI_ShowWDL the model is adjusted to the material
+//! left: with fewer pieces a given advantage converts more reliably, so `a`
+//! shrinks towards the endgame.
+//!
+//! The parameters can be fitted to labelled positions with `fit` (the
+//! `wdlfit` UCI command) and stored in a small `key = value` config file.
+
+use std::fs;
+use std::io;
+
+use crate::board::MAX_PHASE;
+
+/// Default parameters, roughly matching engines of this strength
+pub const DEFAULT_WDL_A: f64 = 150.0;
+pub const DEFAULT_WDL_B: f64 = 70.0;
+
+/// Share of `a` left with no pieces besides pawns (phase 0)
+const ENDGAME_A_SCALE: f64 = 0.7;
+
+/// Logistic win/draw/loss model
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WdlModel {
+    pub a: f64,
+    pub b: f64,
+}
+
+impl Default for WdlModel {
+    fn default() -> Self {
+        WdlModel { a: DEFAULT_WDL_A, b: DEFAULT_WDL_B }
+    }
+}
+
+impl WdlModel {
+    /// Probability that the side to move wins at `score`
+    pub fn win_probability(&self, score: i32) -> f64 {
+        1.0 / (1.0 + ((self.a - score as f64) / self.b).exp())
+    }
+
+    /// Convert an internal score to reported centipawns, scaled so that
+    /// +100 means a 50% chance to win (NormalizeToPawnValue = `a`)
+    pub fn normalize(&self, score: i32) -> i32 {
+        (score as f64 * 100.0 / self.a).round() as i32
+    }
+
+    /// Win, draw and loss probabilities at `score`
+    pub fn probabilities(&self, score: i32) -> (f64, f64, f64) {
+        let win = self.win_probability(score);
+        let loss = self.win_probability(-score);
+        (win, (1.0 - win - loss).max(0.0), loss)
+    }
+
+    /// The model for a position of game phase `phase` (0 = pawn endgame,
+    /// MAX_PHASE = all pieces on the board)
+    pub fn at_phase(&self, phase: i32) -> WdlModel {
+        let share = phase.clamp(0, MAX_PHASE) as f64 / MAX_PHASE as f64;
+        WdlModel { a: self.a * (ENDGAME_A_SCALE + (1.0 - ENDGAME_A_SCALE) * share), b: self.b }
+    }
+
+    /// Win, draw and loss chances in per mille, as sent in UCI `wdl`, at
+    /// `score` in a position of game phase `phase`
+    pub fn wdl_permille(&self, score: i32, phase: i32) -> (u32, u32, u32) {
+        let (win, _, loss) = self.at_phase(phase).probabilities(score);
+        let win = (win * 1000.0).round() as u32;
+        let loss = ((loss * 1000.0).round() as u32).min(1000 - win);
+        (win, 1000 - win - loss, loss)
+    }
+
+    /// Load parameters from a config file with `wdl_a = ...` / `wdl_b = ...`
+    /// lines; missing keys keep their defaults
+    pub fn load(path: &str) -> io::Result<Self> {
+        let mut model = WdlModel::default();
+        for line in fs::read_to_string(path)?.lines() {
+            let Some((key, value)) = line.split_once('=') else { continue };
+            let Ok(value) = value.trim().parse::<f64>() else { continue };
+            match key.trim() {
+                "wdl_a" if value > 0.0 => model.a = value,
+                "wdl_b" if value > 0.0 => model.b = value,
+                _ => {}
+            }
+        }
+        Ok(model)
+    }
+
+    /// Write the parameters in the format read by `load`
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        fs::write(path, format!("wdl_a = {:.2}\nwdl_b = {:.2}\n", self.a, self.b))
+    }
+
+    /// Average log-likelihood of the labelled samples under this model
+    fn log_likelihood(&self, samples: &[WdlSample]) -> f64 {
+        const EPSILON: f64 = 1e-9;
+        let total: f64 = samples.iter().map(|sample| {
+            let (win, draw, loss) = self.probabilities(sample.score);
+            let p = match sample.outcome {
+                Outcome::Win => win,
+                Outcome::Draw => draw,
+                Outcome::Loss => loss,
+            };
+            p.max(EPSILON).ln()
+        }).sum();
+        total / samples.len().max(1) as f64
+    }
+}
+
+// ============================================================================
+// CALIBRATION
+// ============================================================================
+
+/// Game result from the point of view of the scored side
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Win,
+    Draw,
+    Loss,
+}
+
+/// A scored position labelled with the game result
+#[derive(Clone, Copy, Debug)]
+pub struct WdlSample {
+    pub score: i32,
+    pub outcome: Outcome,
+}
+
+fn parse_outcome(text: &str) -> Option<Outcome> {
+    match text.trim().trim_matches(|c| c == '[' || c == ']') {
+        "1" | "1.0" | "1-0" => Some(Outcome::Win),
+        "0.5" | "1/2-1/2" => Some(Outcome::Draw),
+        "0" | "0.0" | "0-1" => Some(Outcome::Loss),
+        _ => None,
+    }
+}
+
+/// Parse a dataset line. Both scores and results are from White's point of
+/// view. Accepted forms:
+///
+/// ```text
+/// <fen> | <score> | <result>      (datagen output)
+/// <score> <result>
+/// ```
+///
+/// where the result is `1`, `0.5`, `0`, `1-0`, `1/2-1/2` or `0-1`.
+pub fn parse_sample(line: &str) -> Option<WdlSample> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    let fields: Vec<&str> = line.split('|').collect();
+    let (score, result) = if fields.len() >= 3 {
+        (fields[1], fields[2])
+    } else {
+        let mut tokens = line.split_whitespace().rev();
+        let result = tokens.next()?;
+        (tokens.next()?, result)
+    };
+
+    Some(WdlSample {
+        score: score.trim().parse().ok()?,
+        outcome: parse_outcome(result)?,
+    })
+}
+
+/// Read every parseable sample of a dataset file
+pub fn load_samples(path: &str) -> io::Result<Vec<WdlSample>> {
+    Ok(fs::read_to_string(path)?.lines().filter_map(parse_sample).collect())
+}
+
+/// Fit the model to `samples` by maximizing the likelihood with a
+/// shrinking-step coordinate search starting from `initial`.
+/// Returns the fitted model and its average log-likelihood.
+pub fn fit(samples: &[WdlSample], initial: WdlModel) -> (WdlModel, f64) {
+    let mut model = initial;
+    let mut best = model.log_likelihood(samples);
+    let mut step = 64.0;
+
+    while step > 0.01 {
+        let mut improved = false;
+
+        for (da, db) in [(step, 0.0), (-step, 0.0), (0.0, step), (0.0, -step)] {
+            let candidate = WdlModel { a: model.a + da, b: model.b + db };
+            if candidate.b <= 1.0 {
+                continue;
+            }
+
+            let likelihood = candidate.log_likelihood(samples);
+            if likelihood > best {
+                model = candidate;
+                best = likelihood;
+                improved = true;
+            }
+        }
+
+        if !improved {
+            step /= 2.0;
+        }
+    }
+
+    (model, best)
+}
@@ -0,0 +1,77 @@
+//! OpusChess - Zobrist Hashing Module
+//!
+//! This module holds the Zobrist keys used to hash positions. The keys are
+//! generated once from a fixed seed and shared by every component through
+//! `ZobristHash::get()`, so the board's repetition history, the search and
+//! the transposition table all agree on the hash of a position.
+
+use std::sync::OnceLock;
+
+use rand::prelude::*;
+
+use crate::board::Board;
+
+static KEYS: OnceLock<ZobristHash> = OnceLock::new();
+
+pub struct ZobristHash {
+    piece_keys: [[u64; 64]; 32],
+    pub side_key: u64,
+    castling_keys: [u64; 16],
+    ep_keys: [u64; 9],
+}
+
+impl ZobristHash {
+    /// The shared keys, generated on first use
+    pub fn get() -> &'static ZobristHash {
+        KEYS.get_or_init(ZobristHash::generate)
+    }
+
+    fn generate() -> Self {
+        let mut rng = StdRng::seed_from_u64(12345);
+
+        let mut piece_keys = [[0u64; 64]; 32];
+        for keys in piece_keys.iter_mut() {
+            for key in keys.iter_mut() {
+                *key = rng.gen();
+            }
+        }
+
+        let side_key = rng.gen();
+
+        let mut castling_keys = [0u64; 16];
+        for key in castling_keys.iter_mut() {
+            *key = rng.gen();
+        }
+
+        let mut ep_keys = [0u64; 9];
+        for key in ep_keys.iter_mut() {
+            *key = rng.gen();
+        }
+
+        ZobristHash { piece_keys, side_key, castling_keys, ep_keys }
+    }
+
+    /// Key of an en passant square (-1 for none)
+    #[inline]
+    pub fn ep_key(&self, ep_square: i8) -> u64 {
+        let ep_idx = if ep_square >= 0 { ep_square as usize % 8 } else { 8 };
+        self.ep_keys[ep_idx]
+    }
+
+    pub fn hash_position(&self, board: &Board) -> u64 {
+        let mut h = 0u64;
+
+        for (sq, piece) in board.all_pieces() {
+            h ^= self.piece_keys[piece as usize][sq];
+        }
+
+        if !board.white_to_move {
+            h ^= self.side_key;
+        }
+
+        h ^= self.castling_keys[board.castling_rights as usize];
+        h ^= self.ep_key(board.en_passant_square);
+
+        h
+    }
+}